@@ -60,4 +60,13 @@ import("../pkg/index.js").then(module => {
     document.getElementById("config-load-default").onclick = _ => {
         loadScene('default');
     };
+
+    // Handlers are named as in the registry,
+    // and may also be given a config, e.g. { "name": "bvh", "config": ... }.
+    // Configured options hold that JSON already, the others just a name
+    document.getElementById("config-handler").onchange = event => {
+        const desc = event.target.value;
+
+        module.update_handler(desc.startsWith('{') ? desc : JSON.stringify(desc));
+    };
 }).catch(console.error);
\ No newline at end of file
@@ -16,10 +16,11 @@ fn main() -> anyhow::Result<()> {
     let scene: rt::scene::Scene = //
         serde_json::from_reader(scene_reader)?;
 
+    let handler = handlers::registry::build("naive", serde_json::Value::Null)?;
+
     pollster::block_on({
-        type Handler = handlers::BasicIntrs;
         type Scheduler = timing::DefaultScheduler;
 
-        rt::run_native::<Handler, Scheduler>(config, (), scene)
+        rt::run_native::<Scheduler>(config, handler, scene)
     })
 }
\ No newline at end of file
@@ -74,11 +74,11 @@ impl<'de> serde::Deserialize<'de> for Bounds {
 }
 
 impl Bounds {
-    fn new<P>(prims: P, vertices: &[geom::PrimVertex]) -> Self
+    pub fn new<P>(prims: P, vertices: &[geom::PrimVertex]) -> Self
         where P: Iterator<Item = geom::Prim> {
 
         let mut min = [f32::MAX; 3];
-        let mut max = [f32::MAX * -1.; 3];
+        let mut max = [-f32::MAX; 3];
 
         fn extrema_vertex(
             vertex: [f32; 3], 
@@ -107,7 +107,7 @@ impl Bounds {
         Self { min, _p0: 0, max, _p1: 0 }
     }
 
-    fn contains(&self, point: [f32; 3]) -> bool {
+    pub fn contains(&self, point: [f32; 3]) -> bool {
         point[0] >= self.min[0] &&
         point[0] <= self.max[0] &&
         point[1] >= self.min[1] &&
@@ -115,6 +115,79 @@ impl Bounds {
         point[2] >= self.min[2] &&
         point[2] <= self.max[2]
     }
+
+    // Bounds enclosing an arbitrary set of points
+    pub fn from_points<P>(points: P) -> Self
+        where P: Iterator<Item = [f32; 3]> {
+
+        let mut min = [f32::MAX; 3];
+        let mut max = [-f32::MAX; 3];
+
+        for point in points {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(point[axis]);
+                max[axis] = max[axis].max(point[axis]);
+            }
+        }
+
+        Self { min, _p0: 0, max, _p1: 0 }
+    }
+
+    // The region shared by both operands. 
+    // May be empty if they don't overlap
+    pub fn intersection(self, other: Self) -> Self {
+        let Self { mut min, mut max, .. } = self;
+
+        for axis in 0..3 {
+            min[axis] = min[axis].max(other.min[axis]);
+            max[axis] = max[axis].min(other.max[axis]);
+        }
+
+        Self { min, _p0: 0, max, _p1: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|axis| self.min[axis] > self.max[axis])
+    }
+
+    // The smallest Bounds that encloses both operands
+    pub fn union(self, other: Self) -> Self {
+        let Self { mut min, mut max, .. } = self;
+
+        for axis in 0..3 {
+            min[axis] = min[axis].min(other.min[axis]);
+            max[axis] = max[axis].max(other.max[axis]);
+        }
+
+        Self { min, _p0: 0, max, _p1: 0 }
+    }
+
+    // Surface area, used to evaluate the SAH
+    // Empty bounds (min > max) have no area
+    pub fn area(&self) -> f32 {
+        use geom::V3Ops as _;
+
+        let [dx, dy, dz] = self.max.sub(self.min);
+
+        if dx < 0. || dy < 0. || dz < 0. { 
+            return 0.;
+        }
+
+        2. * (dx * dy + dy * dz + dz * dx)
+    }
+
+    // Empty bounds have no volume either
+    pub fn volume(&self) -> f32 {
+        use geom::V3Ops as _;
+
+        let [dx, dy, dz] = self.max.sub(self.min);
+
+        if dx < 0. || dy < 0. || dz < 0. { 
+            return 0.;
+        }
+
+        dx * dy * dz
+    }
 }
 
 pub struct Aabb {
@@ -147,12 +220,86 @@ impl fmt::Debug for Aabb {
 }
 
 impl Aabb {
+    // Builds a leaf node containing the given items
+    pub fn leaf(
+        items: Vec<usize>, 
+        prims: &[geom::Prim], 
+        vertices: &[geom::PrimVertex],
+    ) -> Self {
+        Self {
+            fst: OnceCell::new(),
+            snd: OnceCell::new(),
+            bounds: Bounds::new(items.iter().map(|&i| prims[i]), vertices),
+            items,
+        }
+    }
+
     fn split(
         &mut self, 
         eps: f32,
         prims: &[geom::Prim], 
         vertices: &[geom::PrimVertex],
         target_item_count: usize,
+        strategy: super::Strategy,
+        fork: super::Fork,
+    ) {
+        match strategy {
+            super::Strategy::Midpoint => //
+                self.split_midpoint(eps, prims, vertices, target_item_count, fork),
+            super::Strategy::Sah { bins } => //
+                super::sah::split(self, eps, prims, vertices, target_item_count, bins, fork),
+            super::Strategy::Spatial { bins, budget } => //
+                super::sbvh::split(self, eps, prims, vertices, target_item_count, bins, budget, fork),
+            // Linear BVHs are not built top-down, see `Aabb::from_scene`
+            super::Strategy::Linear { .. } => unreachable!(),
+        }
+    }
+
+    // Lowers the tree's SAH cost by rearranging its nodes, see `optimize::optimize`.
+    // Returns the number of treelets that were improved
+    pub fn optimize(&mut self, budget: super::Budget) -> usize {
+        super::optimize::optimize(self, budget)
+    }
+
+    // Reconstructs the tree from its flattened shader data
+    pub fn from_data(data: &super::BvhData) -> Self {
+        fn from_data_inner(data: &super::BvhData, curr: usize) -> Aabb {
+            let super::AabbUniform {
+                fst,
+                snd,
+                item_idx,
+                item_count,
+                bounds,
+            } = data.uniforms[curr];
+
+            let aabb = Aabb {
+                fst: OnceCell::new(),
+                snd: OnceCell::new(),
+                bounds,
+                items: data.indices[(item_idx as usize)..((item_idx + item_count) as usize)]
+                    .iter()
+                    .map(|&idx| idx as usize)
+                    .collect(),
+            };
+
+            if item_count == 0 {
+                let _ = aabb.fst.set(Box::new(from_data_inner(data, fst as usize)));
+                let _ = aabb.snd.set(Box::new(from_data_inner(data, snd as usize)));
+            }
+
+            aabb
+        }
+
+        from_data_inner(data, 0)
+    }
+
+    fn split_midpoint(
+        &mut self, 
+        eps: f32,
+        prims: &[geom::Prim], 
+        vertices: &[geom::PrimVertex],
+        target_item_count: usize,
+        fork: super::Fork,
     ) {
         use geom::V3Ops as _;
 
@@ -193,23 +340,8 @@ impl Aabb {
             snd.bounds.min[2] = fst.bounds.max[2];
         }
 
-        let centroid = |tri: geom::Prim| -> [f32; 3] {
-            let [a, b, c] = tri.indices;
-
-            let a = vertices[a as usize].pos;
-            let b = vertices[b as usize].pos;
-            let c = vertices[c as usize].pos;
-
-            let ab = a.add(b).scale(0.5);
-            let bc = b.add(c).scale(0.5);
-            let ca = c.add(a).scale(0.5);
-
-            // I'll let the compiler figure out the precision
-            (ab.add(bc).add(ca)).scale(1. / 3.)
-        };
-
         for (idx, tri) in self.items.iter().map(|&idx| (idx, prims[idx])) {
-            let centroid = centroid(tri);
+            let centroid = tri.centroid(vertices);
 
             if fst.bounds.contains(centroid) {
                 fst.items.push(idx);
@@ -221,11 +353,11 @@ impl Aabb {
         if fst.items.is_empty() {
             self.bounds = snd.bounds;
 
-            self.split(eps, prims, vertices, target_item_count);
+            self.split_midpoint(eps, prims, vertices, target_item_count, fork);
         } else if snd.items.is_empty() {
             self.bounds = fst.bounds;
 
-            self.split(eps, prims, vertices, target_item_count);
+            self.split_midpoint(eps, prims, vertices, target_item_count, fork);
         } else {
             self.items.clear();
 
@@ -239,8 +371,12 @@ impl Aabb {
                 vertices
             );
 
-            fst.split(eps, prims, vertices, target_item_count);
-            snd.split(eps, prims, vertices, target_item_count);
+            let items = fst.items.len() + snd.items.len();
+
+            fork.join(items, 
+                |fork| fst.split_midpoint(eps, prims, vertices, target_item_count, fork),
+                |fork| snd.split_midpoint(eps, prims, vertices, target_item_count, fork),
+            );
 
             self.fst.set(Box::new(fst)).unwrap();
             self.snd.set(Box::new(snd)).unwrap();
@@ -260,6 +396,7 @@ impl Aabb {
         eps: f32,
         scene: &scene::Scene,
         target_item_count: usize,
+        strategy: super::Strategy,
     ) -> Self {
         let scene::Scene::Active { 
             prims, 
@@ -268,14 +405,40 @@ impl Aabb {
             return Self::from_scene_unloaded();
         };
 
-        let mut root = Self {
-            fst: OnceCell::new(),
-            snd: OnceCell::new(),
-            bounds: Bounds::new(prims.iter().copied(), vertices),
-            items: (0..prims.len()).collect()
-        };
+        Self::from_prims(eps, prims, vertices, target_item_count, strategy)
+    }
+
+    // Builds the tree over an arbitrary set of primitives,
+    // such as a single mesh of an instanced scene
+    pub fn from_prims(
+        eps: f32,
+        prims: &[geom::Prim],
+        vertices: &[geom::PrimVertex],
+        target_item_count: usize,
+        strategy: super::Strategy,
+    ) -> Self {
+        // Large subtrees are built in parallel on native targets
+        Self::from_prims_with(eps, prims, vertices, target_item_count, strategy, super::Fork::new())
+    }
+
+    // Same as `from_prims`, but on the threads `fork` provides.
+    // The tree doesn't depend on the thread count
+    pub fn from_prims_with(
+        eps: f32,
+        prims: &[geom::Prim],
+        vertices: &[geom::PrimVertex],
+        target_item_count: usize,
+        strategy: super::Strategy,
+        fork: super::Fork,
+    ) -> Self {
+        // The linear builder emits shader data directly
+        if let super::Strategy::Linear { bits } = strategy {
+            return Self::from_data(&super::lbvh::build(prims, vertices, target_item_count, bits));
+        }
+
+        let mut root = Self::leaf((0..prims.len()).collect(), prims, vertices);
 
-        root.split(eps, prims, vertices, target_item_count);
+        root.split(eps, prims, vertices, target_item_count, strategy, fork);
         root
     }
 }
\ No newline at end of file
@@ -0,0 +1,373 @@
+use std::ops;
+
+use crate::geom;
+
+use super::{AabbUniform, BvhData, Bounds};
+
+// Nodes are kept in an arena and refer to each other by index,
+// so subtrees can be relinked without moving them
+#[derive(Clone)]
+#[derive(Debug)]
+struct Node {
+    bounds: Bounds,
+    parent: Option<usize>,
+    // Only internal nodes have children
+    children: Option<[usize; 2]>,
+    // Only leaves have items
+    items: Vec<u32>,
+}
+
+// A BVH that can be edited in place.
+// Meshes are inserted and removed without rebuilding the whole tree,
+// and the tree is rebalanced with rotations along the modified paths.
+// Item indices refer to the scene's primitives in their original order,
+// before any handler reorders them. `Scene::add_mesh` and
+// `Scene::remove_mesh` restore that order before editing the scene
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(Default)]
+pub struct DynamicBvh {
+    nodes: Vec<Node>,
+    // Slots of the arena that can be reused
+    free: Vec<usize>,
+    root: Option<usize>,
+}
+
+impl DynamicBvh {
+    // Unflattens the shader data
+    pub fn from_data(data: &BvhData) -> Self {
+        let mut bvh = Self::default();
+
+        // Unloaded scenes are represented by a single leaf without bounds
+        if data.uniforms.first().is_some_and(|root| !root.bounds.is_empty()) {
+            bvh.root = Some(bvh.graft(data, 0, 0));
+        }
+
+        bvh
+    }
+
+    // Flattens the tree into shader data, with its nodes in pre-order
+    pub fn data(&self) -> BvhData {
+        let Some(root) = self.root else {
+            return BvhData::new(&super::Aabb::from_scene_unloaded());
+        };
+
+        let mut data = BvhData::default();
+
+        fn data_inner(bvh: &DynamicBvh, data: &mut BvhData, curr: usize) -> u32 {
+            let Node { bounds, children, items, .. } = &bvh.nodes[curr];
+
+            let uniform = data.uniforms.len();
+
+            data.uniforms.push(AabbUniform {
+                fst: 0,
+                snd: 0,
+                bounds: *bounds,
+                item_idx: data.indices.len() as u32,
+                item_count: items.len() as u32,
+            });
+
+            data.indices.extend(items.iter().copied());
+
+            if let Some([fst, snd]) = *children {
+                data.uniforms[uniform].fst = data_inner(bvh, data, fst);
+                data.uniforms[uniform].snd = data_inner(bvh, data, snd);
+            }
+
+            uniform as u32
+        }
+
+        data_inner(self, &mut data, root);
+
+        data
+    }
+
+    // Builds a subtree over a contiguous range of primitives
+    // (i.e. a mesh that was just added to the scene) and inserts it
+    pub fn insert(
+        &mut self,
+        eps: f32,
+        prims: &[geom::Prim],
+        vertices: &[geom::PrimVertex],
+        range: ops::Range<usize>,
+        target_item_count: usize,
+        strategy: super::Strategy,
+    ) {
+        if range.is_empty() { return; }
+
+        let data = BvhData::from_prims(
+            eps,
+            &prims[range.clone()],
+            vertices,
+            target_item_count,
+            strategy,
+        );
+
+        // The subtree's items are relative to the start of the range
+        let subtree = self.graft(&data, 0, range.start as u32);
+
+        let Some(root) = self.root else {
+            self.root = Some(subtree);
+
+            return;
+        };
+
+        let sibling = self.sibling(root, self.nodes[subtree].bounds);
+
+        // The new parent takes the sibling's place in the tree
+        let parent = self.alloc(Node {
+            bounds: self.nodes[sibling].bounds.union(self.nodes[subtree].bounds),
+            parent: self.nodes[sibling].parent,
+            children: Some([sibling, subtree]),
+            items: Vec::new(),
+        });
+
+        match self.nodes[sibling].parent {
+            Some(grandparent) => self.replace_child(grandparent, sibling, parent),
+            None => self.root = Some(parent),
+        }
+
+        self.nodes[sibling].parent = Some(parent);
+        self.nodes[subtree].parent = Some(parent);
+
+        self.rebalance(Some(parent));
+    }
+
+    // Removes every item in `range`, then shifts the following items down.
+    // `prims` and `vertices` must already have the range removed,
+    // as they are after `Scene::remove_mesh`
+    pub fn remove(
+        &mut self,
+        prims: &[geom::Prim],
+        vertices: &[geom::PrimVertex],
+        range: ops::Range<usize>,
+    ) {
+        let Some(root) = self.root else { return; };
+
+        let (start, end) = (range.start as u32, range.end as u32);
+
+        // Gather the leaves before any of them are unlinked
+        let mut leaves = Vec::new();
+        let mut stack = vec![root];
+        while let Some(idx) = stack.pop() {
+            match self.nodes[idx].children {
+                Some([fst, snd]) => stack.extend([fst, snd]),
+                None => leaves.push(idx),
+            }
+        }
+
+        for leaf in leaves {
+            let items = &mut self.nodes[leaf].items;
+
+            let count = items.len();
+
+            items.retain(|&item| item < start || item >= end);
+
+            for item in items.iter_mut().filter(|item| **item >= end) {
+                *item -= end - start;
+            }
+
+            if items.is_empty() {
+                self.remove_leaf(leaf);
+            } else if items.len() < count {
+                self.nodes[leaf].bounds = Bounds::new(
+                    self.nodes[leaf].items.iter().map(|&item| prims[item as usize]),
+                    vertices,
+                );
+
+                self.rebalance(self.nodes[leaf].parent);
+            }
+        }
+    }
+
+    fn alloc(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(idx) => {
+                self.nodes[idx] = node;
+
+                idx
+            },
+            None => {
+                self.nodes.push(node);
+
+                self.nodes.len() - 1
+            },
+        }
+    }
+
+    // Copies a subtree of the shader data into the arena.
+    // Returns the index of its root
+    fn graft(&mut self, data: &BvhData, curr: usize, offset: u32) -> usize {
+        let AabbUniform {
+            fst,
+            snd,
+            item_idx,
+            item_count,
+            bounds,
+        } = data.uniforms[curr];
+
+        let items = data.indices[(item_idx as usize)..((item_idx + item_count) as usize)]
+            .iter()
+            .map(|&item| item + offset)
+            .collect();
+
+        let node = self.alloc(Node {
+            bounds,
+            parent: None,
+            children: None,
+            items,
+        });
+
+        if item_count == 0 && !(fst == 0 && snd == 0) {
+            let fst = self.graft(data, fst as usize, offset);
+            let snd = self.graft(data, snd as usize, offset);
+
+            self.nodes[fst].parent = Some(node);
+            self.nodes[snd].parent = Some(node);
+
+            self.nodes[node].children = Some([fst, snd]);
+        }
+
+        node
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if let Some(children) = self.nodes[parent].children.as_mut() {
+            for child in children.iter_mut().filter(|child| **child == old) {
+                *child = new;
+            }
+        }
+    }
+
+    // Descends towards the node that is the cheapest sibling for `bounds`.
+    // The cost of a sibling is the area of the new parent,
+    // plus the growth of every ancestor above it
+    fn sibling(&self, root: usize, bounds: Bounds) -> usize {
+        let mut idx = root;
+
+        // Growth of the ancestors visited so far
+        let mut inherited = 0.;
+
+        loop {
+            let Node { bounds: curr, children, .. } = &self.nodes[idx];
+
+            let Some(children) = children else { return idx; };
+
+            let combined = curr.union(bounds).area();
+
+            // Pairing with the current node creates a parent this large
+            let cost = combined + inherited;
+
+            // Descending grows the current node as well
+            let inherited_child = inherited + combined - curr.area();
+
+            // Lower bounds on the cost of descending into each child
+            let costs = children.map(|child| {
+                let Node { bounds: child_bounds, children, .. } = &self.nodes[child];
+
+                let area = child_bounds.union(bounds).area() + inherited_child;
+
+                match children {
+                    Some(_) => area - child_bounds.area(),
+                    None => area,
+                }
+            });
+
+            let (child, cost_child) = if costs[0] <= costs[1] {
+                (children[0], costs[0])
+            } else {
+                (children[1], costs[1])
+            };
+
+            if cost <= cost_child { return idx; }
+
+            idx = child;
+            inherited = inherited_child;
+        }
+    }
+
+    // Unlinks a leaf, its sibling takes the place of their parent
+    fn remove_leaf(&mut self, leaf: usize) {
+        self.free.push(leaf);
+
+        let Some(parent) = self.nodes[leaf].parent else {
+            self.root = None;
+
+            return;
+        };
+
+        let Some([fst, snd]) = self.nodes[parent].children else { unreachable!(); };
+
+        let sibling = if fst == leaf { snd } else { fst };
+
+        let grandparent = self.nodes[parent].parent;
+
+        match grandparent {
+            Some(grandparent) => self.replace_child(grandparent, parent, sibling),
+            None => self.root = Some(sibling),
+        }
+
+        self.nodes[sibling].parent = grandparent;
+
+        self.free.push(parent);
+
+        self.rebalance(grandparent);
+    }
+
+    // Refits each node from `start` up to the root,
+    // rotating its grandchildren whenever that shrinks a child
+    fn rebalance(&mut self, start: Option<usize>) {
+        let mut curr = start;
+
+        while let Some(idx) = curr {
+            self.refit(idx);
+            self.rotate(idx);
+
+            curr = self.nodes[idx].parent;
+        }
+    }
+
+    fn refit(&mut self, idx: usize) {
+        if let Some([fst, snd]) = self.nodes[idx].children {
+            self.nodes[idx].bounds = self.nodes[fst].bounds.union(self.nodes[snd].bounds);
+        }
+    }
+
+    // Considers swapping each child with one of its nephews.
+    // The node's own bounds are unaffected, only the swapped child's shrink
+    fn rotate(&mut self, idx: usize) {
+        let Some(children) = self.nodes[idx].children else { return; };
+
+        // (The child that's swapped out, its nephew, the nephew's sibling)
+        let mut best: Option<(f32, usize, usize, usize)> = None;
+
+        for (child, other) in [(children[0], children[1]), (children[1], children[0])] {
+            let Some(nephews) = self.nodes[other].children else { continue; };
+
+            let area = self.nodes[other].bounds.area();
+
+            for (nephew, remaining) in [(nephews[0], nephews[1]), (nephews[1], nephews[0])] {
+                // After the swap, `other` encloses `child` and `remaining`
+                let rotated = self.nodes[child].bounds
+                    .union(self.nodes[remaining].bounds)
+                    .area();
+
+                let delta = rotated - area;
+
+                if delta < best.map(|(best, ..)| best).unwrap_or(0.) {
+                    best = Some((delta, child, nephew, other));
+                }
+            }
+        }
+
+        let Some((_, child, nephew, other)) = best else { return; };
+
+        self.replace_child(idx, child, nephew);
+        self.replace_child(other, nephew, child);
+
+        self.nodes[nephew].parent = Some(idx);
+        self.nodes[child].parent = Some(other);
+
+        self.refit(other);
+    }
+}
@@ -0,0 +1,71 @@
+// Divides the available threads among the subtrees of a top-down build.
+// There are no threads on wasm32, so subtrees are always built in sequence
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+pub struct Fork {
+    threads: usize,
+}
+
+impl Fork {
+    // Nodes with fewer items aren't worth a thread of their own
+    pub const THRESHOLD: usize = 2048;
+
+    pub fn new() -> Self {
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                Self { threads: 1 }
+            } else {
+                let threads = std::thread::available_parallelism()
+                    .map(|threads| threads.get())
+                    .unwrap_or(1);
+
+                Self { threads }
+            }
+        }
+    }
+
+    // Caps the build at `threads` threads. One thread builds in sequence
+    pub fn with_threads(threads: usize) -> Self {
+        Self { threads: threads.max(1) }
+    }
+
+    // Whether `join` would build a node with this many items in parallel
+    pub fn forks(&self, items: usize) -> bool {
+        cfg!(not(target_arch = "wasm32")) && //
+            self.threads > 1 && items >= Self::THRESHOLD
+    }
+
+    // Builds both children of a node with `items` items.
+    // If it forks, the second child is built on its own thread
+    // and each side gets half of the remaining threads
+    pub fn join<A, B, RA, RB>(self, items: usize, fst: A, snd: B) -> (RA, RB)
+        where A: FnOnce(Self) -> RA + Send,
+              B: FnOnce(Self) -> RB + Send,
+              RA: Send,
+              RB: Send {
+
+        if !self.forks(items) {
+            return (fst(self), snd(self));
+        }
+
+        let Self { threads } = self;
+
+        std::thread::scope(|scope| {
+            let snd = scope.spawn(move || snd(Self { threads: threads / 2 }));
+            let fst = fst(Self { threads: threads - threads / 2 });
+
+            // Panics in the builder are propagated to the caller
+            let snd = snd
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e));
+
+            (fst, snd)
+        })
+    }
+}
+
+impl Default for Fork {
+    fn default() -> Self {
+        Self::new()
+    }
+}
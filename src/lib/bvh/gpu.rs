@@ -0,0 +1,311 @@
+// Needed for `device.create_buffer_init`
+use wgpu::util::DeviceExt as _;
+
+use crate::{geom, shaders};
+
+// Every pass of the builder lives in this module
+const SOURCE: &str = include_str!("../shaders/lbvh.wgsl");
+
+// The workgroup size of every entry point
+const WORKGROUP: u32 = 256;
+
+// The radix sort handles 4 bits of the 32-bit keys per pass
+const RADIX_BITS: u32 = 4;
+const RADIX_PASSES: u32 = 32 / RADIX_BITS;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    count: u32,
+    shift: u32,
+    blocks: u32,
+    _padding: u32,
+}
+
+// A single dispatch, along with the bindings it needs
+struct Stage {
+    pipeline: usize,
+    group: wgpu::BindGroup,
+    workgroups: u32,
+}
+
+// Builds a Linear BVH (Karras, 2012) with compute shaders.
+// Primitives are sorted by the Morton codes of their centroids,
+// the hierarchy is emitted in parallel, then bounds are fit bottom-up.
+// The nodes are written straight into a handler's buffer of AabbUniforms.
+// Internal nodes come first, so unlike BvhData they aren't in pre-order.
+// Each leaf holds a single primitive, referenced by its original index
+pub struct GpuBuilder {
+    pipelines: Vec<wgpu::ComputePipeline>,
+    stages: Vec<Stage>,
+    // Reset before every build
+    extent: wgpu::Buffer,
+}
+
+impl GpuBuilder {
+    // The number of nodes in the BVH of `count` primitives
+    pub const fn nodes(count: usize) -> usize {
+        2 * count - 1
+    }
+
+    // An upper bound on the depth of the BVH of `count` primitives.
+    // Each node splits on a longer prefix of the 64-bit keys
+    // (the Morton code, then the index), so no path is longer
+    pub const fn depth_max(count: usize) -> usize {
+        if count - 1 < 64 { count - 1 } else { 64 }
+    }
+
+    // Prepares the build of a BVH over `prims` into `nodes`,
+    // which must hold `GpuBuilder::nodes` AabbUniforms.
+    // There must be at least two primitives
+    pub fn new(
+        device: &wgpu::Device,
+        prims: &[geom::Prim],
+        vertices: &[geom::PrimVertex],
+        nodes: &wgpu::Buffer,
+    ) -> anyhow::Result<Self> {
+        let count = prims.len() as u32;
+
+        if count < 2 {
+            anyhow::bail!("The GPU builder needs at least two primitives");
+        }
+
+        let node_count = Self::nodes(count as usize) as u32;
+
+        // Dispatches are one-dimensional
+        let limit = device.limits().max_compute_workgroups_per_dimension;
+        if node_count.div_ceil(WORKGROUP) > limit {
+            anyhow::bail!("The scene has too many primitives to build on the GPU ({count})");
+        }
+
+        let blocks = count.div_ceil(WORKGROUP);
+
+        let source = shaders::Composer::new()
+            .module(shaders::Module::new("lbvh", SOURCE).param("WORKGROUP", WORKGROUP))
+            .compose()?;
+
+        let module = device.create_shader_module(
+            wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            }
+        );
+
+        let entry_points = [
+            "extent_main",
+            "morton_main",
+            "histogram_main",
+            "scan_main",
+            "scatter_main",
+            "hierarchy_main",
+            "fit_main",
+            "finalize_main",
+        ];
+
+        // Each layout is derived from the bindings its entry point uses,
+        // which keeps every stage under the storage buffer limit
+        let pipelines = entry_points
+            .iter()
+            .map(|entry_point| {
+                device.create_compute_pipeline(
+                    &wgpu::ComputePipelineDescriptor {
+                        label: None,
+                        layout: None,
+                        module: &module,
+                        entry_point,
+                    }
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // Indices into `pipelines`, in the order of `entry_points`
+        let [
+            extent_main,
+            morton_main,
+            histogram_main,
+            scan_main,
+            scatter_main,
+            hierarchy_main,
+            fit_main,
+            finalize_main,
+        ]: [usize; 8] = std::array::from_fn(|idx| idx);
+
+        // Each pass of the radix sort gets its own digit
+        let params = (0..RADIX_PASSES)
+            .map(|pass| {
+                device.create_buffer_init(
+                    &wgpu::util::BufferInitDescriptor {
+                        label: None,
+                        contents: bytemuck::cast_slice(&[Params {
+                            count,
+                            shift: pass * RADIX_BITS,
+                            blocks,
+                            _padding: 0,
+                        }]),
+                        usage: wgpu::BufferUsages::UNIFORM,
+                    }
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let storage = |size: u64| {
+            device.create_buffer(
+                &wgpu::BufferDescriptor {
+                    label: None,
+                    size,
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }
+            )
+        };
+
+        let prims = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(prims),
+                usage: wgpu::BufferUsages::STORAGE,
+            }
+        );
+
+        let vertices = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(vertices),
+                usage: wgpu::BufferUsages::STORAGE,
+            }
+        );
+
+        let extent = storage(6 * 4);
+
+        // The sort ping-pongs between these
+        let keys = [storage(count as u64 * 4), storage(count as u64 * 4)];
+        let vals = [storage(count as u64 * 4), storage(count as u64 * 4)];
+
+        let histogram = storage((1 << RADIX_BITS) * blocks as u64 * 4);
+        let parents = storage(node_count as u64 * 4);
+        let fitted = storage(node_count as u64 * 6 * 4);
+
+        let stage = |pipeline: usize, items: u32, entries: &[(u32, &wgpu::Buffer)]| {
+            let layout = pipelines[pipeline].get_bind_group_layout(0);
+
+            let entries = entries
+                .iter()
+                .map(|&(binding, buffer)| wgpu::BindGroupEntry {
+                    binding,
+                    resource: buffer.as_entire_binding(),
+                })
+                .collect::<Vec<_>>();
+
+            let group = device.create_bind_group(
+                &wgpu::BindGroupDescriptor {
+                    label: None,
+                    layout: &layout,
+                    entries: &entries,
+                }
+            );
+
+            Stage { pipeline, group, workgroups: items.div_ceil(WORKGROUP) }
+        };
+
+        let mut stages = vec![
+            stage(extent_main, count, &[
+                (0, &params[0]),
+                (1, &prims),
+                (2, &vertices),
+                (3, &extent),
+            ]),
+            stage(morton_main, count, &[
+                (0, &params[0]),
+                (1, &prims),
+                (2, &vertices),
+                (3, &extent),
+                (4, &keys[0]),
+                (5, &vals[0]),
+            ]),
+        ];
+
+        for (pass, params) in params.iter().enumerate() {
+            let (src, dst) = (pass % 2, (pass + 1) % 2);
+
+            stages.push(stage(histogram_main, count, &[
+                (0, params),
+                (4, &keys[src]),
+                (8, &histogram),
+            ]));
+
+            // The scan is done by a single workgroup
+            stages.push(stage(scan_main, WORKGROUP, &[
+                (0, params),
+                (8, &histogram),
+            ]));
+
+            stages.push(stage(scatter_main, count, &[
+                (0, params),
+                (4, &keys[src]),
+                (5, &vals[src]),
+                (6, &keys[dst]),
+                (7, &vals[dst]),
+                (8, &histogram),
+            ]));
+        }
+
+        // There's an even number of passes, so the sorted keys are back in place
+        stages.extend([
+            stage(hierarchy_main, node_count, &[
+                (0, &params[0]),
+                (4, &keys[0]),
+                (5, &vals[0]),
+                (9, &parents),
+                (10, &fitted),
+                (11, nodes),
+            ]),
+            stage(fit_main, count, &[
+                (0, &params[0]),
+                (1, &prims),
+                (2, &vertices),
+                (9, &parents),
+                (10, &fitted),
+                (11, nodes),
+            ]),
+            stage(finalize_main, node_count, &[
+                (0, &params[0]),
+                (10, &fitted),
+                (11, nodes),
+            ]),
+        ]);
+
+        Ok(Self { pipelines, stages, extent })
+    }
+
+    // Submits every pass of the build. The queue orders them
+    // before any later work, so the nodes are ready by the next frame
+    pub fn build(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        // The centroids' extent starts out empty (see `ordered` in the shader)
+        queue.write_buffer(
+            &self.extent, 0,
+            bytemuck::cast_slice(&[u32::MAX, u32::MAX, u32::MAX, 0, 0, 0])
+        );
+
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor { label: None }
+        );
+
+        {
+            let mut pass = encoder.begin_compute_pass(
+                &wgpu::ComputePassDescriptor {
+                    label: None,
+                    timestamp_writes: None,
+                }
+            );
+
+            for Stage { pipeline, group, workgroups } in self.stages.iter() {
+                pass.set_pipeline(&self.pipelines[*pipeline]);
+                pass.set_bind_group(0, group, &[]);
+                pass.dispatch_workgroups(*workgroups, 1, 1);
+            }
+        }
+
+        queue.submit(Some(encoder.finish()));
+    }
+}
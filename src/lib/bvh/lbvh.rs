@@ -0,0 +1,174 @@
+use crate::geom;
+
+use super::{AabbUniform, Bounds, BvhData, Morton};
+
+// Spreads the lower 10 bits of `v` so there are 2 zeros between each
+fn expand_30(v: u64) -> u64 {
+    let mut v = v & 0x3FF;
+
+    v = (v * 0x00010001) & 0xFF0000FF;
+    v = (v * 0x00000101) & 0x0F00F00F;
+    v = (v * 0x00000011) & 0xC30C30C3;
+    v = (v * 0x00000005) & 0x49249249;
+    v
+}
+
+// Spreads the lower 21 bits of `v` so there are 2 zeros between each
+fn expand_63(v: u64) -> u64 {
+    let mut v = v & 0x1FFFFF;
+
+    v = (v | v << 32) & 0x001F00000000FFFF;
+    v = (v | v << 16) & 0x001F0000FF0000FF;
+    v = (v | v << 8) & 0x100F00F00F00F00F;
+    v = (v | v << 4) & 0x10C30C30C30C30C3;
+    v = (v | v << 2) & 0x1249249249249249;
+    v
+}
+
+// Interleaves a point (normalized to the unit cube) into a Morton code
+fn morton(point: [f32; 3], bits: Morton) -> u64 {
+    let (scale, expand): (f32, fn(u64) -> u64) = match bits {
+        Morton::Bits30 => ((1 << 10) as f32, expand_30),
+        Morton::Bits63 => ((1 << 21) as f32, expand_63),
+    };
+
+    let [x, y, z] = point.map(|v| {
+        expand((v * scale).clamp(0., scale - 1.) as u64)
+    });
+
+    (x << 2) | (y << 1) | z
+}
+
+// Finds the first index in the second half of the range,
+// using the highest bit at which the range's codes differ
+fn partition(codes: &[(u64, usize)], lo: usize, hi: usize) -> usize {
+    let fst = codes[lo].0;
+    let lst = codes[hi - 1].0;
+
+    // Identical codes are split down the middle
+    if fst == lst {
+        return (lo + hi) / 2;
+    }
+
+    let prefix = (fst ^ lst).leading_zeros();
+
+    // Binary search for the last code that shares a longer prefix with `fst`
+    // (Karras 2012)
+    let mut split = lo;
+    let mut step = hi - 1 - lo;
+
+    loop {
+        step = step.div_ceil(2);
+
+        let next = split + step;
+
+        if next < hi - 1 && (fst ^ codes[next].0).leading_zeros() > prefix {
+            split = next;
+        }
+
+        if step <= 1 { break; }
+    }
+
+    split + 1
+}
+
+struct Builder<'a> {
+    prims: &'a [geom::Prim],
+    vertices: &'a [geom::PrimVertex],
+    codes: Vec<(u64, usize)>,
+    target_item_count: usize,
+}
+
+impl<'a> Builder<'a> {
+    // Emits nodes in the same order as `BvhData::new`:
+    // each node is followed by its first subtree, then its second
+    fn emit(&self, data: &mut BvhData, lo: usize, hi: usize) -> u32 {
+        let node = data.uniforms.len();
+
+        data.uniforms.push(AabbUniform {
+            fst: 0,
+            snd: 0,
+            item_idx: 0,
+            item_count: 0,
+            bounds: Bounds::new([].into_iter(), &[]),
+        });
+
+        if hi - lo <= self.target_item_count {
+            let items = self.codes[lo..hi]
+                .iter()
+                .map(|&(_, idx)| self.prims[idx]);
+
+            data.uniforms[node].item_idx = lo as u32;
+            data.uniforms[node].item_count = (hi - lo) as u32;
+            data.uniforms[node].bounds = Bounds::new(items, self.vertices);
+        } else {
+            let split = partition(&self.codes, lo, hi);
+
+            let fst = self.emit(data, lo, split);
+            let snd = self.emit(data, split, hi);
+
+            let bounds = data.uniforms[fst as usize].bounds
+                .union(data.uniforms[snd as usize].bounds);
+
+            data.uniforms[node].fst = fst;
+            data.uniforms[node].snd = snd;
+            data.uniforms[node].bounds = bounds;
+        }
+
+        node as u32
+    }
+}
+
+// Builds a Linear BVH (LBVH) by sorting primitive centroids along a Morton curve.
+// Leaves cover contiguous ranges of the sorted primitives,
+// so the tree is emitted straight into a BvhData
+pub fn build(
+    prims: &[geom::Prim],
+    vertices: &[geom::PrimVertex],
+    target_item_count: usize,
+    bits: Morton,
+) -> BvhData {
+    if prims.is_empty() {
+        return BvhData::new(&super::Aabb::from_scene_unloaded());
+    }
+
+    let centroids = prims
+        .iter()
+        .map(|prim| prim.centroid(vertices))
+        .collect::<Vec<_>>();
+
+    // Codes are computed relative to the bounds of the centroids
+    let Bounds { min, max, .. } = Bounds::from_points(centroids.iter().copied());
+
+    let extent = [0, 1, 2].map(|axis| (max[axis] - min[axis]).max(f32::EPSILON));
+
+    let mut codes = centroids
+        .into_iter()
+        .map(|centroid| [0, 1, 2].map(|axis| {
+            (centroid[axis] - min[axis]) / extent[axis]
+        }))
+        .map(|point| morton(point, bits))
+        .zip(0..prims.len())
+        .collect::<Vec<_>>();
+
+    codes.sort_unstable();
+
+    let builder = Builder {
+        prims,
+        vertices,
+        codes,
+        target_item_count: target_item_count.max(1),
+    };
+
+    let mut data = BvhData {
+        uniforms: Vec::with_capacity(prims.len() * 2),
+        indices: builder.codes
+            .iter()
+            .map(|&(_, idx)| idx as u32)
+            .collect(),
+    };
+
+    builder.emit(&mut data, 0, prims.len());
+
+    data
+}
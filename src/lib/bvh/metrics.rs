@@ -0,0 +1,111 @@
+use std::fmt;
+
+use super::BvhData;
+
+// Summarizes the quality of a BVH,
+// so benchmark results can be explained by more than frame times
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(Default)]
+pub struct BvhMetrics {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub depth_max: usize,
+    // Averaged over all leaves
+    pub depth_avg: f32,
+    // The number of leaves holding each item count.
+    // The last bucket also counts every larger leaf
+    pub leaf_sizes: [usize; BvhMetrics::LEAF_SIZES],
+    // Expected cost of a random ray, relative to the root's surface area
+    pub sah: f32,
+    // Total volume shared by sibling nodes
+    pub overlap: f32,
+}
+
+impl BvhMetrics {
+    pub const LEAF_SIZES: usize = 8;
+
+    // Relative costs of visiting a node and testing a primitive
+    pub const COST_TRAVERSAL: f32 = 1.;
+    pub const COST_INTERSECTION: f32 = 1.;
+}
+
+impl fmt::Display for BvhMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            nodes,
+            leaves,
+            depth_max,
+            depth_avg,
+            leaf_sizes,
+            sah,
+            overlap,
+        } = self;
+
+        writeln!(f, "Nodes: {nodes} ({leaves} leaves)")?;
+        writeln!(f, "Depth: {depth_max} max, {depth_avg:.2} avg")?;
+        writeln!(f, "SAH cost: {sah:.3}")?;
+        writeln!(f, "Sibling overlap: {overlap:.3}")?;
+
+        write!(f, "Leaf sizes:")?;
+        for (count, leaves) in leaf_sizes.iter().enumerate() {
+            if count == Self::LEAF_SIZES - 1 {
+                write!(f, " {count}+: {leaves}")?;
+            } else {
+                write!(f, " {count}: {leaves},")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn metrics(data: &BvhData) -> BvhMetrics {
+    let BvhData { uniforms, .. } = data;
+
+    let mut metrics = BvhMetrics {
+        nodes: uniforms.len(),
+        ..Default::default()
+    };
+
+    let Some(root) = uniforms.first() else {
+        return metrics;
+    };
+
+    // Avoid dividing by zero on degenerate scenes
+    let area = root.bounds.area().max(f32::EPSILON);
+
+    let mut depth_total = 0;
+
+    // Pairs of node indices and their depths
+    let mut stack = vec![(0, 0)];
+    while let Some((idx, depth)) = stack.pop() {
+        let node = uniforms[idx];
+
+        let relative = node.bounds.area() / area;
+
+        if node.item_count > 0 || (node.fst == 0 && node.snd == 0) {
+            let count = node.item_count as usize;
+
+            metrics.leaves += 1;
+            metrics.depth_max = metrics.depth_max.max(depth);
+            metrics.leaf_sizes[count.min(BvhMetrics::LEAF_SIZES - 1)] += 1;
+            metrics.sah += relative * count as f32 * BvhMetrics::COST_INTERSECTION;
+
+            depth_total += depth;
+        } else {
+            let fst = uniforms[node.fst as usize].bounds;
+            let snd = uniforms[node.snd as usize].bounds;
+
+            metrics.sah += relative * BvhMetrics::COST_TRAVERSAL;
+            metrics.overlap += fst.intersection(snd).volume();
+
+            stack.push((node.fst as usize, depth + 1));
+            stack.push((node.snd as usize, depth + 1));
+        }
+    }
+
+    metrics.depth_avg = depth_total as f32 / metrics.leaves as f32;
+
+    metrics
+}
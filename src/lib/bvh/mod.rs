@@ -1,6 +1,70 @@
 mod aabb;
+mod sah;
+mod sbvh;
+mod lbvh;
+mod metrics;
+mod fork;
+mod dynamic;
+mod gpu;
+mod optimize;
 
 pub use aabb::{Aabb, Bounds};
+pub use fork::Fork;
+pub use dynamic::DynamicBvh;
+pub use gpu::GpuBuilder;
+pub use optimize::Budget;
+pub use metrics::BvhMetrics;
+
+// Determines how each node's items are divided among its children
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(serde::Deserialize)]
+#[derive(Default)]
+pub enum Strategy {
+    // Cut the longest axis at its spatial midpoint
+    #[default]
+    Midpoint,
+    // Bin centroids along each axis and take the cheapest plane
+    Sah { bins: usize },
+    // SAH, but straddling primitives may be clipped and referenced
+    // from both children. `budget` caps the number of duplicate
+    // references as a fraction of the primitive count
+    Spatial { bins: usize, budget: f32 },
+    // Sort centroids along a Morton curve and split on the highest
+    // differing bit. Much faster to build, but lower quality
+    Linear { bits: Morton },
+}
+
+// The precision of the Morton codes used by Strategy::Linear
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(serde::Deserialize)]
+#[derive(Default)]
+pub enum Morton {
+    // 10 bits per axis
+    #[default]
+    Bits30,
+    // 21 bits per axis
+    Bits63,
+}
+
+impl Morton {
+    pub const fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            30 => Some(Self::Bits30),
+            63 => Some(Self::Bits63),
+            _ => None,
+        }
+    }
+}
+
+impl Strategy {
+    // A reasonable bin count for most scenes
+    pub const BINS: usize = 16;
+
+    // Allow up to 30% more references than primitives
+    pub const BUDGET: f32 = 0.3;
+}
 
 // The Aabb tree gets rendered down into an array of AabbUniform structs
 // It's placed at the module root to avoid importing items from siblings
@@ -62,4 +126,103 @@ impl BvhData {
 
         data
     }
+
+    // Reports the tree's shape and expected traversal cost
+    pub fn metrics(&self) -> BvhMetrics {
+        metrics::metrics(self)
+    }
+
+    // Recomputes every node's bounds from updated vertex positions,
+    // keeping the topology intact. `prims` must be in their original order,
+    // the one that `indices` refers to.
+    // NOTE: Clipped bounds from spatial splits are replaced 
+    // by the bounds of the whole primitive
+    pub fn refit(
+        &mut self, 
+        prims: &[crate::geom::Prim], 
+        vertices: &[crate::geom::PrimVertex],
+    ) {
+        let Self { uniforms, indices } = self;
+
+        // Children are always emitted after their parents,
+        // so walking backwards visits them first
+        for idx in (0..uniforms.len()).rev() {
+            let AabbUniform {
+                fst,
+                snd,
+                item_idx,
+                item_count, ..
+            } = uniforms[idx];
+
+            uniforms[idx].bounds = if item_count > 0 {
+                let items = &indices[(item_idx as usize)..((item_idx + item_count) as usize)];
+
+                Bounds::new(items.iter().map(|&i| prims[i as usize]), vertices)
+            } else {
+                uniforms[fst as usize].bounds.union(uniforms[snd as usize].bounds)
+            };
+        }
+    }
+
+    // Refits after the primitives have been reordered to match `indices`,
+    // as the BVH handlers do to their scenes
+    pub fn refit_ordered(
+        &mut self, 
+        prims: &[crate::geom::Prim], 
+        vertices: &[crate::geom::PrimVertex],
+    ) {
+        // Spatial splits can reference a primitive more than once
+        let count = self.indices
+            .iter()
+            .max()
+            .map(|&idx| idx as usize + 1)
+            .unwrap_or(0);
+
+        let mut unordered = vec![bytemuck::Zeroable::zeroed(); count];
+        for (&idx, &prim) in self.indices.iter().zip(prims.iter()) {
+            unordered[idx as usize] = prim;
+        }
+
+        self.refit(&unordered, vertices);
+    }
+
+    // Builds the shader data for a scene with the given strategy.
+    // Strategy::Linear skips the intermediate Aabb tree entirely
+    pub fn from_scene(
+        eps: f32,
+        scene: &crate::scene::Scene,
+        target_item_count: usize,
+        strategy: Strategy,
+    ) -> Self {
+        let crate::scene::Scene::Active { 
+            prims, 
+            vertices, .. 
+        } = scene else {
+            return Self::new(&aabb::Aabb::from_scene_unloaded());
+        };
+
+        Self::from_prims(eps, prims, vertices, target_item_count, strategy)
+    }
+
+    // Builds the shader data over arbitrary bounds with the binned SAH.
+    // Leaves refer to indices into `bounds`
+    pub fn from_bounds(bounds: &[Bounds], eps: f32, bins: usize) -> Self {
+        Self::new(&sah::build(bounds, eps, bins))
+    }
+
+    // Builds the shader data over an arbitrary set of primitives
+    pub fn from_prims(
+        eps: f32,
+        prims: &[crate::geom::Prim],
+        vertices: &[crate::geom::PrimVertex],
+        target_item_count: usize,
+        strategy: Strategy,
+    ) -> Self {
+        match strategy {
+            Strategy::Linear { bits } => //
+                lbvh::build(prims, vertices, target_item_count, bits),
+            strategy => //
+                Self::new(&aabb::Aabb::from_prims(eps, prims, vertices, target_item_count, strategy)),
+        }
+    }
 }
\ No newline at end of file
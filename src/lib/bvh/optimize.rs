@@ -0,0 +1,206 @@
+use once_cell::sync::OnceCell;
+
+use super::{Aabb, Bounds, BvhMetrics};
+
+// Limits how much work `Aabb::optimize` does.
+// Optimization also stops once a pass no longer lowers the cost
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+pub enum Budget {
+    // The number of passes over the whole tree
+    Iterations(usize),
+    // Passes stop partway once this much time has elapsed.
+    // There's no clock on wasm32
+    #[cfg(not(target_arch = "wasm32"))]
+    Time(std::time::Duration),
+}
+
+impl Budget {
+    // Most of the improvement comes from the first few passes
+    pub const ITERATIONS: usize = 8;
+}
+
+// The number of subtrees that are rearranged at once.
+// The optimal arrangement is found exhaustively, so this stays small
+const TREELET: usize = 7;
+
+// Treelets that improve by less than this are left as they are
+const TOLERANCE: f32 = 1e-5;
+
+// Rearranges the tree to lower its SAH cost, following
+// "Fast Parallel Construction of High-Quality BVHs" (Karras & Aila, 2013).
+// Each pass visits the internal nodes bottom-up. The node and its largest
+// descendants form a treelet, whose leaves are rearranged optimally.
+// Items never move between leaves, so the tree stays valid at every step.
+// Returns the number of treelets that were improved
+pub fn optimize(aabb: &mut Aabb, budget: Budget) -> usize {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            let Budget::Iterations(passes) = budget;
+
+            let deadline = None;
+        } else {
+            let (passes, deadline) = match budget {
+                Budget::Iterations(passes) => (passes, None),
+                Budget::Time(duration) => {
+                    (usize::MAX, Some(std::time::Instant::now() + duration))
+                },
+            };
+        }
+    }
+
+    let mut improved = 0;
+
+    for _ in 0..passes {
+        let mut expired = false;
+
+        let count = pass(aabb, deadline, &mut expired);
+
+        improved += count;
+
+        if count == 0 || expired { break; }
+    }
+
+    improved
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "wasm32")] {
+        type Deadline = Option<()>;
+
+        fn expired(_deadline: Deadline) -> bool { false }
+    } else {
+        type Deadline = Option<std::time::Instant>;
+
+        fn expired(deadline: Deadline) -> bool {
+            deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline)
+        }
+    }
+}
+
+// Restructures every treelet below `node` before its own
+fn pass(node: &mut Aabb, deadline: Deadline, timeout: &mut bool) -> usize {
+    let mut improved = 0;
+
+    if let Some(fst) = node.fst.get_mut() {
+        improved += pass(fst, deadline, timeout);
+    }
+
+    if let Some(snd) = node.snd.get_mut() {
+        improved += pass(snd, deadline, timeout);
+    }
+
+    if *timeout || expired(deadline) {
+        *timeout = true;
+
+        return improved;
+    }
+
+    if restructure(node) {
+        improved += 1;
+    }
+
+    improved
+}
+
+// Returns true if the treelet rooted at `node` was improved
+fn restructure(node: &mut Aabb) -> bool {
+    let (Some(fst), Some(snd)) = (node.fst.take(), node.snd.take()) else {
+        return false;
+    };
+
+    // Every arrangement contains the same subtrees,
+    // so only the areas of the treelet's internal nodes are compared
+    let mut cost_old = 0.;
+
+    // Grow the treelet by expanding its largest leaf
+    let mut leaves = vec![fst, snd];
+    while leaves.len() < TREELET {
+        let largest = leaves
+            .iter()
+            .enumerate()
+            .filter(|(_, leaf)| leaf.fst.get().is_some() && leaf.snd.get().is_some())
+            .max_by(|(_, a), (_, b)| a.bounds.area().total_cmp(&b.bounds.area()))
+            .map(|(idx, _)| idx);
+
+        let Some(largest) = largest else { break; };
+
+        let mut leaf = leaves.swap_remove(largest);
+
+        cost_old += leaf.bounds.area() * BvhMetrics::COST_TRAVERSAL;
+
+        leaves.extend([leaf.fst.take().unwrap(), leaf.snd.take().unwrap()]);
+    }
+
+    // Subsets of the leaves are bitmasks
+    let count = leaves.len();
+    let full = (1 << count) - 1;
+
+    let mut bounds = vec![Bounds::new([].into_iter(), &[]); full + 1];
+    let mut costs = vec![0f32; full + 1];
+    let mut partitions = vec![0usize; full + 1];
+
+    for subset in 1..=full {
+        let lowest = subset & subset.wrapping_neg();
+
+        if subset == lowest {
+            bounds[subset] = leaves[subset.trailing_zeros() as usize].bounds;
+
+            continue;
+        }
+
+        bounds[subset] = bounds[lowest].union(bounds[subset ^ lowest]);
+
+        // Each partition is visited once, from the side with the lowest leaf
+        let mut best = f32::MAX;
+        let mut part = (subset - 1) & subset;
+        while part > 0 {
+            if part & lowest != 0 {
+                let cost = costs[part] + costs[subset ^ part];
+
+                if cost < best {
+                    best = cost;
+                    partitions[subset] = part;
+                }
+            }
+
+            part = (part - 1) & subset;
+        }
+
+        costs[subset] = best + bounds[subset].area() * BvhMetrics::COST_TRAVERSAL;
+    }
+
+    // The treelet's root keeps its bounds
+    let part = partitions[full];
+    let cost_new = costs[part] + costs[full ^ part];
+
+    let improved = cost_new < cost_old * (1. - TOLERANCE);
+
+    let mut leaves = leaves.into_iter().map(Some).collect::<Vec<_>>();
+
+    fn assemble(
+        subset: usize,
+        leaves: &mut [Option<Box<Aabb>>],
+        bounds: &[Bounds],
+        partitions: &[usize],
+    ) -> Box<Aabb> {
+        if subset.count_ones() == 1 {
+            return leaves[subset.trailing_zeros() as usize].take().unwrap();
+        }
+
+        let part = partitions[subset];
+
+        Box::new(Aabb {
+            fst: OnceCell::with_value(assemble(part, leaves, bounds, partitions)),
+            snd: OnceCell::with_value(assemble(subset ^ part, leaves, bounds, partitions)),
+            bounds: bounds[subset],
+            items: Vec::new(),
+        })
+    }
+
+    // Even without an improvement, the optimal arrangement costs no more
+    let _ = node.fst.set(assemble(part, &mut leaves, &bounds, &partitions));
+    let _ = node.snd.set(assemble(full ^ part, &mut leaves, &bounds, &partitions));
+
+    improved
+}
@@ -0,0 +1,247 @@
+use once_cell::sync::OnceCell;
+
+use crate::geom;
+
+use super::{Aabb, Bounds, BvhMetrics};
+
+// Nodes with more items than this are always split,
+// even where the SAH prefers a leaf
+const MAX_LEAF_SIZE: usize = 16;
+
+// Each bin tracks how many centroids fell into it
+// and the bounds of the corresponding primitives
+#[derive(Clone, Copy)]
+struct Bin {
+    count: usize,
+    bounds: Bounds,
+}
+
+impl Bin {
+    fn new() -> Self {
+        Self { count: 0, bounds: Bounds::new([].into_iter(), &[]), }
+    }
+}
+
+// The cheapest plane found by `plane`
+pub struct Plane {
+    pub axis: usize,
+    pub cost: f32,
+    bin: usize,
+    bins: usize,
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+impl Plane {
+    fn bin(&self, centroid: [f32; 3], axis: usize) -> usize {
+        let Self { bins, min, max, .. } = self;
+
+        let extent = max[axis] - min[axis];
+        let offset = (centroid[axis] - min[axis]) / extent;
+
+        ((offset * *bins as f32) as usize).min(bins - 1)
+    }
+
+    // Whether an item with the given centroid belongs to the first child
+    pub fn left(&self, centroid: [f32; 3]) -> bool {
+        self.bin(centroid, self.axis) <= self.bin
+    }
+}
+
+// Finds the cheapest binned SAH plane over a set of items,
+// each described by its centroid and bounds.
+// Returns None if every candidate plane leaves a side empty
+pub fn plane(
+    items: &[([f32; 3], Bounds)],
+    eps: f32,
+    bins: usize,
+) -> Option<Plane> {
+    if bins < 2 { return None; }
+
+    // Primitives are binned by centroid, not by their bounds
+    let mut min = [f32::MAX; 3];
+    let mut max = [-f32::MAX; 3];
+
+    for (centroid, _) in items.iter() {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(centroid[axis]);
+            max[axis] = max[axis].max(centroid[axis]);
+        }
+    }
+
+    let mut best: Option<Plane> = None;
+
+    for axis in 0..3 {
+        // Centroids are (nearly) coincident on this axis
+        if max[axis] - min[axis] < eps * 0.5 { continue; }
+
+        let candidate = Plane { axis, cost: f32::MAX, bin: 0, bins, min, max, };
+
+        let mut binned = vec![Bin::new(); bins];
+
+        for &(centroid, bounds) in items.iter() {
+            let bin = &mut binned[candidate.bin(centroid, axis)];
+
+            bin.count += 1;
+            bin.bounds = bin.bounds.union(bounds);
+        }
+
+        // Sweep from the right to accumulate the cost of each right side
+        let mut right = vec![0.; bins];
+        let mut acc = Bin::new();
+        for idx in (1..bins).rev() {
+            acc.count += binned[idx].count;
+            acc.bounds = acc.bounds.union(binned[idx].bounds);
+
+            right[idx - 1] = acc.bounds.area() * acc.count as f32;
+        }
+
+        // Then sweep from the left, completing the cost of each plane
+        let mut acc = Bin::new();
+        for idx in 0..(bins - 1) {
+            acc.count += binned[idx].count;
+            acc.bounds = acc.bounds.union(binned[idx].bounds);
+
+            // Planes that leave a side empty are not splits at all
+            if acc.count == 0 || acc.count == items.len() { continue; }
+
+            let cost = acc.bounds.area() * acc.count as f32 + right[idx];
+
+            if best.as_ref().map(|best| cost < best.cost).unwrap_or(true) {
+                best = Some(Plane { cost, bin: idx, ..candidate });
+            }
+        }
+    }
+
+    best
+}
+
+// Splits the node using the binned Surface Area Heuristic.
+// Like the midpoint split, nodes are only subdivided while they
+// hold more than `target_item_count` items,
+// and only while the cheapest plane costs less than a leaf
+pub fn split(
+    aabb: &mut Aabb,
+    eps: f32,
+    prims: &[geom::Prim],
+    vertices: &[geom::PrimVertex],
+    target_item_count: usize,
+    bins: usize,
+    fork: super::Fork,
+) {
+    if aabb.items.len() <= target_item_count {
+        return;
+    }
+
+    let items = aabb.items
+        .iter()
+        .map(|&idx| (
+            prims[idx].centroid(vertices),
+            Bounds::new(std::iter::once(prims[idx]), vertices),
+        )).collect::<Vec<_>>();
+
+    // No valid plane, so the node remains a leaf
+    let Some(plane) = plane(&items, eps, bins) else {
+        return;
+    };
+
+    // Both costs are relative to the node's surface area.
+    // The plane's cost sums the area of each child times its item count
+    let area = items
+        .iter()
+        .fold(Bounds::new([].into_iter(), &[]), |acc, &(_, bounds)| acc.union(bounds))
+        .area();
+
+    let cost_leaf = BvhMetrics::COST_INTERSECTION * area * items.len() as f32;
+    let cost_split = BvhMetrics::COST_TRAVERSAL * area
+        + BvhMetrics::COST_INTERSECTION * plane.cost;
+
+    // Splitting wouldn't make the node any cheaper to traverse
+    if cost_split >= cost_leaf && items.len() <= MAX_LEAF_SIZE {
+        return;
+    }
+
+    let mut fst = Vec::new();
+    let mut snd = Vec::new();
+
+    for (&idx, &(centroid, _)) in aabb.items.iter().zip(items.iter()) {
+        if plane.left(centroid) {
+            fst.push(idx);
+        } else {
+            snd.push(idx);
+        }
+    }
+
+    let mut fst = Aabb::leaf(fst, prims, vertices);
+    let mut snd = Aabb::leaf(snd, prims, vertices);
+
+    fork.join(aabb.items.len(),
+        |fork| split(&mut fst, eps, prims, vertices, target_item_count, bins, fork),
+        |fork| split(&mut snd, eps, prims, vertices, target_item_count, bins, fork),
+    );
+
+    aabb.items.clear();
+
+    aabb.fst = OnceCell::with_value(Box::new(fst));
+    aabb.snd = OnceCell::with_value(Box::new(snd));
+}
+
+// Builds a tree over arbitrary bounds (such as the instances of a scene)
+// rather than primitives. Items are split until each leaf holds one
+pub fn build(bounds: &[Bounds], eps: f32, bins: usize) -> Aabb {
+    use geom::V3Ops as _;
+
+    let mut aabb = Aabb {
+        fst: OnceCell::new(),
+        snd: OnceCell::new(),
+        bounds: Bounds::new([].into_iter(), &[]),
+        items: (0..bounds.len()).collect(),
+    };
+
+    // Nodes are split in place, so there's no need to recurse
+    let mut stack = vec![&mut aabb];
+    while let Some(node) = stack.pop() {
+        node.bounds = node.items
+            .iter()
+            .fold(node.bounds, |acc, &idx| acc.union(bounds[idx]));
+
+        if node.items.len() <= 1 { continue; }
+
+        let items = node.items
+            .iter()
+            .map(|&idx| {
+                let Bounds { min, max, .. } = bounds[idx];
+
+                (min.add(max).scale(0.5), bounds[idx])
+            }).collect::<Vec<_>>();
+
+        let Some(plane) = plane(&items, eps, bins) else { continue; };
+
+        let (fst, snd) = node.items
+            .iter()
+            .zip(items.iter())
+            .partition::<Vec<_>, _>(|(_, &(centroid, _))| plane.left(centroid));
+
+        let child = |items: Vec<(&usize, _)>| Box::new(Aabb {
+            fst: OnceCell::new(),
+            snd: OnceCell::new(),
+            bounds: Bounds::new([].into_iter(), &[]),
+            items: items.into_iter().map(|(&idx, _)| idx).collect(),
+        });
+
+        let fst = child(fst);
+        let snd = child(snd);
+
+        node.items.clear();
+
+        node.fst = OnceCell::with_value(fst);
+        node.snd = OnceCell::with_value(snd);
+
+        let Aabb { fst, snd, .. } = node;
+
+        stack.push(fst.get_mut().unwrap());
+        stack.push(snd.get_mut().unwrap());
+    }
+
+    aabb
+}
@@ -0,0 +1,359 @@
+use once_cell::sync::OnceCell;
+
+use crate::geom;
+
+use super::{sah, Aabb, Bounds};
+
+// Spatial splits are only attempted when the children of the best
+// object split overlap by more than this fraction of the root's area
+// (Stich et al. 2009)
+const ALPHA: f32 = 0.00001;
+
+// A (possibly clipped) reference to a primitive.
+// Spatial splits can place references to the same primitive in both children
+#[derive(Clone, Copy)]
+struct Reference {
+    idx: usize,
+    bounds: Bounds,
+}
+
+impl Reference {
+    fn centroid(&self) -> [f32; 3] {
+        let Bounds { min, max, .. } = self.bounds;
+
+        [
+            (min[0] + max[0]) * 0.5,
+            (min[1] + max[1]) * 0.5,
+            (min[2] + max[2]) * 0.5,
+        ]
+    }
+}
+
+// Each spatial bin counts the references that begin and end inside it
+#[derive(Clone, Copy)]
+struct Bin {
+    entry: usize,
+    exit: usize,
+    bounds: Bounds,
+}
+
+// The cheapest spatial plane found by `Builder::spatial`
+struct Plane {
+    axis: usize,
+    pos: f32,
+    cost: f32,
+}
+
+// The bounds of the portion of a triangle between `lo` and `hi` on `axis`
+fn clip(
+    prim: geom::Prim,
+    vertices: &[geom::PrimVertex],
+    axis: usize,
+    lo: f32,
+    hi: f32,
+) -> Bounds {
+    let [a, b, c] = prim.indices;
+
+    let tri = [
+        vertices[a as usize].pos,
+        vertices[b as usize].pos,
+        vertices[c as usize].pos,
+    ];
+
+    let mut points = Vec::with_capacity(9);
+
+    for idx in 0..3 {
+        let a = tri[idx];
+        let b = tri[(idx + 1) % 3];
+
+        if a[axis] >= lo && a[axis] <= hi {
+            points.push(a);
+        }
+
+        // Add the points where this edge crosses either plane
+        for plane in [lo, hi] {
+            if (a[axis] - plane) * (b[axis] - plane) < 0. {
+                let t = (plane - a[axis]) / (b[axis] - a[axis]);
+
+                let mut point = [
+                    a[0] + (b[0] - a[0]) * t,
+                    a[1] + (b[1] - a[1]) * t,
+                    a[2] + (b[2] - a[2]) * t,
+                ];
+
+                point[axis] = plane;
+
+                points.push(point);
+            }
+        }
+    }
+
+    Bounds::from_points(points.into_iter())
+}
+
+#[derive(Clone, Copy)]
+struct Builder<'a> {
+    eps: f32,
+    prims: &'a [geom::Prim],
+    vertices: &'a [geom::PrimVertex],
+    target_item_count: usize,
+    bins: usize,
+    // The surface area of the root node
+    area: f32,
+    // The number of additional references we are still allowed to create
+    budget: usize,
+}
+
+impl<'a> Builder<'a> {
+    fn leaf(refs: Vec<Reference>) -> Aabb {
+        Aabb {
+            fst: OnceCell::new(),
+            snd: OnceCell::new(),
+            bounds: bounds(&refs),
+            items: refs.into_iter().map(|r| r.idx).collect(),
+        }
+    }
+
+    // Finds the cheapest spatial split of the node's bounds
+    fn spatial(&self, refs: &[Reference], bounds: Bounds) -> Option<Plane> {
+        let Self { eps, prims, vertices, bins, .. } = *self;
+
+        if bins < 2 { return None; }
+
+        let mut best: Option<Plane> = None;
+
+        for axis in 0..3 {
+            let lo = bounds.min[axis];
+            let extent = bounds.max[axis] - lo;
+
+            if extent < eps * 0.5 { continue; }
+
+            let width = extent / bins as f32;
+
+            let bin = |pos: f32| -> usize {
+                (((pos - lo) / width) as usize).min(bins - 1)
+            };
+
+            let mut binned = vec![Bin {
+                entry: 0,
+                exit: 0,
+                bounds: Bounds::new([].into_iter(), &[]),
+            }; bins];
+
+            for r in refs.iter() {
+                let fst = bin(r.bounds.min[axis]);
+                let lst = bin(r.bounds.max[axis]);
+
+                // Each bin only receives the part of the triangle inside it
+                for (idx, bin) in binned.iter_mut().enumerate().take(lst + 1).skip(fst) {
+                    let slab_lo = lo + width * idx as f32;
+                    let slab_hi = slab_lo + width;
+
+                    let clipped = clip(prims[r.idx], vertices, axis, slab_lo, slab_hi)
+                        .intersection(r.bounds);
+
+                    if !clipped.is_empty() {
+                        bin.bounds = bin.bounds.union(clipped);
+                    }
+                }
+
+                binned[fst].entry += 1;
+                binned[lst].exit += 1;
+            }
+
+            let mut right = vec![(0, Bounds::new([].into_iter(), &[])); bins];
+            let mut acc = (0, Bounds::new([].into_iter(), &[]));
+            for idx in (1..bins).rev() {
+                acc.0 += binned[idx].exit;
+                acc.1 = acc.1.union(binned[idx].bounds);
+
+                right[idx - 1] = acc;
+            }
+
+            let mut acc = (0, Bounds::new([].into_iter(), &[]));
+            for idx in 0..(bins - 1) {
+                acc.0 += binned[idx].entry;
+                acc.1 = acc.1.union(binned[idx].bounds);
+
+                let (count, bounds) = right[idx];
+
+                if acc.0 == 0 || count == 0 { continue; }
+
+                let cost = acc.1.area() * acc.0 as f32 + bounds.area() * count as f32;
+
+                if best.as_ref().map(|best| cost < best.cost).unwrap_or(true) {
+                    best = Some(Plane {
+                        axis,
+                        pos: lo + width * (idx + 1) as f32,
+                        cost,
+                    });
+                }
+            }
+        }
+
+        best
+    }
+
+    // Divides references among two children,
+    // duplicating any that straddle the plane
+    fn partition(
+        &self,
+        refs: &[Reference],
+        plane: &Plane,
+    ) -> (Vec<Reference>, Vec<Reference>, usize) {
+        let Plane { axis, pos, .. } = *plane;
+
+        let mut fst = Vec::new();
+        let mut snd = Vec::new();
+
+        let mut duplicated = 0;
+
+        for &r in refs.iter() {
+            if r.bounds.max[axis] <= pos {
+                fst.push(r);
+            } else if r.bounds.min[axis] >= pos {
+                snd.push(r);
+            } else {
+                let prim = self.prims[r.idx];
+
+                let bounds_fst = clip(prim, self.vertices, axis, f32::MIN, pos)
+                    .intersection(r.bounds);
+                let bounds_snd = clip(prim, self.vertices, axis, pos, f32::MAX)
+                    .intersection(r.bounds);
+
+                match (bounds_fst.is_empty(), bounds_snd.is_empty()) {
+                    (false, false) => {
+                        fst.push(Reference { bounds: bounds_fst, ..r });
+                        snd.push(Reference { bounds: bounds_snd, ..r });
+
+                        duplicated += 1;
+                    },
+                    (false, true) => fst.push(Reference { bounds: bounds_fst, ..r }),
+                    (true, false) => snd.push(Reference { bounds: bounds_snd, ..r }),
+                    (true, true) => fst.push(r),
+                }
+            }
+        }
+
+        (fst, snd, duplicated)
+    }
+
+    fn split(&mut self, refs: Vec<Reference>, fork: super::Fork) -> Aabb {
+        if refs.len() <= self.target_item_count {
+            return Self::leaf(refs);
+        }
+
+        let items = refs
+            .iter()
+            .map(|r| (r.centroid(), r.bounds))
+            .collect::<Vec<_>>();
+
+        // Start with the best object split
+        let mut children = sah::plane(&items, self.eps, self.bins).map(|plane| {
+            let (fst, snd): (Vec<_>, Vec<_>) = refs
+                .iter()
+                .partition(|r| plane.left(r.centroid()));
+
+            (plane.cost, fst, snd)
+        });
+
+        // Only consider a spatial split if the object split's children overlap
+        let overlap = match children.as_ref() {
+            Some((_, fst, snd)) => bounds(fst).intersection(bounds(snd)).area(),
+            None => f32::MAX,
+        };
+
+        if self.budget > 0 && overlap > self.area * ALPHA {
+            if let Some(plane) = self.spatial(&refs, bounds(&refs)) {
+                let cheaper = children
+                    .as_ref()
+                    .map(|(cost, ..)| plane.cost < *cost)
+                    .unwrap_or(true);
+
+                if cheaper {
+                    let (fst, snd, duplicated) = self.partition(&refs, &plane);
+
+                    // Reject splits that exceed the budget or make no progress
+                    let progress = !fst.is_empty() && !snd.is_empty() &&
+                        fst.len() < refs.len() && snd.len() < refs.len();
+
+                    if progress && duplicated <= self.budget {
+                        self.budget -= duplicated;
+
+                        children = Some((plane.cost, fst, snd));
+                    }
+                }
+            }
+        }
+
+        let Some((_, fst, snd)) = children else {
+            return Self::leaf(refs);
+        };
+
+        // Each child draws from its own share of the remaining budget,
+        // in proportion to the references it starts with. The shares are
+        // the same whether or not the build forks, so the tree doesn't
+        // depend on the thread count. Whatever a child leaves unused
+        // isn't passed on to its sibling
+        let mut other = Builder {
+            budget: self.budget * snd.len() / (fst.len() + snd.len()),
+            ..*self
+        };
+
+        self.budget -= other.budget;
+
+        let (fst, snd) = fork.join(refs.len(), 
+            |fork| self.split(fst, fork), 
+            |fork| other.split(snd, fork),
+        );
+
+        Aabb {
+            fst: OnceCell::with_value(Box::new(fst)),
+            snd: OnceCell::with_value(Box::new(snd)),
+            bounds: bounds(&refs),
+            items: Vec::new(),
+        }
+    }
+}
+
+// The union of all reference bounds
+fn bounds(refs: &[Reference]) -> Bounds {
+    refs.iter().fold(Bounds::new([].into_iter(), &[]), |bounds, r| {
+        bounds.union(r.bounds)
+    })
+}
+
+// Builds a Split BVH (SBVH). Object splits are augmented with spatial splits,
+// which may reference the same primitive from both children.
+// `budget` limits the number of duplicated references
+// as a fraction of the initial item count
+#[allow(clippy::too_many_arguments)]
+pub fn split(
+    aabb: &mut Aabb,
+    eps: f32,
+    prims: &[geom::Prim],
+    vertices: &[geom::PrimVertex],
+    target_item_count: usize,
+    bins: usize,
+    budget: f32,
+    fork: super::Fork,
+) {
+    let refs = aabb.items
+        .iter()
+        .map(|&idx| Reference {
+            idx,
+            bounds: Bounds::new(std::iter::once(prims[idx]), vertices),
+        }).collect::<Vec<_>>();
+
+    let mut builder = Builder {
+        eps,
+        prims,
+        vertices,
+        target_item_count,
+        bins,
+        area: aabb.bounds.area(),
+        budget: (budget.max(0.) * refs.len() as f32) as usize,
+    };
+
+    let _ = std::mem::replace(aabb, builder.split(refs, fork));
+}
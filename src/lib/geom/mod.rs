@@ -1,4 +1,6 @@
 pub mod light;
+pub mod transform;
+pub mod tri;
 
 mod v3; pub use v3::V3Ops;
 
@@ -48,6 +50,24 @@ impl<'de> serde::Deserialize<'de> for Prim {
     }
 }
 
+impl Prim {
+    // The centroid is used to sort primitives during BVH construction
+    pub fn centroid(&self, vertices: &[PrimVertex]) -> [f32; 3] {
+        let [a, b, c] = self.indices;
+
+        let a = vertices[a as usize].pos;
+        let b = vertices[b as usize].pos;
+        let c = vertices[c as usize].pos;
+
+        let ab = a.add(b).scale(0.5);
+        let bc = b.add(c).scale(0.5);
+        let ca = c.add(a).scale(0.5);
+
+        // I'll let the compiler figure out the precision
+        (ab.add(bc).add(ca)).scale(1. / 3.)
+    }
+}
+
 #[repr(C)]
 #[derive(bytemuck::Pod, bytemuck::Zeroable)]
 #[derive(Clone, Copy)]
@@ -0,0 +1,79 @@
+// Column-major, to match WGSL's mat4x4<f32>
+pub type M4 = [[f32; 4]; 4];
+
+pub const IDENTITY: M4 = [
+    [1., 0., 0., 0.],
+    [0., 1., 0., 0.],
+    [0., 0., 1., 0.],
+    [0., 0., 0., 1.],
+];
+
+// A uniform scale followed by a translation
+pub fn from_translation_scale(translation: [f32; 3], scale: f32) -> M4 {
+    let [x, y, z] = translation;
+
+    [
+        [scale, 0., 0., 0.],
+        [0., scale, 0., 0.],
+        [0., 0., scale, 0.],
+        [x, y, z, 1.],
+    ]
+}
+
+pub fn point(m: &M4, p: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|row| {
+        m[0][row] * p[0] + m[1][row] * p[1] + m[2][row] * p[2] + m[3][row]
+    })
+}
+
+// Directions ignore the translation
+pub fn dir(m: &M4, d: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|row| {
+        m[0][row] * d[0] + m[1][row] * d[1] + m[2][row] * d[2]
+    })
+}
+
+// Normals are transformed by the inverse transpose
+pub fn normal(inverse: &M4, n: [f32; 3]) -> [f32; 3] {
+    use super::V3Ops as _;
+
+    [0, 1, 2].map(|col| {
+        inverse[col][0] * n[0] + inverse[col][1] * n[1] + inverse[col][2] * n[2]
+    }).normalize()
+}
+
+// Inverts an affine transform (the last row must be [0, 0, 0, 1]).
+// Returns None if the transform is singular
+pub fn inverse(m: &M4) -> Option<M4> {
+    // The upper-left 3x3, indexed [col][row]
+    let a = |col: usize, row: usize| m[col][row];
+
+    let det = a(0, 0) * (a(1, 1) * a(2, 2) - a(2, 1) * a(1, 2))
+        - a(1, 0) * (a(0, 1) * a(2, 2) - a(2, 1) * a(0, 2))
+        + a(2, 0) * (a(0, 1) * a(1, 2) - a(1, 1) * a(0, 2));
+
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let inv_det = det.recip();
+
+    // Inverse of the 3x3 via its adjugate
+    let mut inv = IDENTITY;
+    for (col, inv_col) in inv.iter_mut().enumerate().take(3) {
+        for (row, elem) in inv_col.iter_mut().enumerate().take(3) {
+            let (c0, c1) = ((row + 1) % 3, (row + 2) % 3);
+            let (r0, r1) = ((col + 1) % 3, (col + 2) % 3);
+
+            *elem = (a(c0, r0) * a(c1, r1) - a(c1, r0) * a(c0, r1)) * inv_det;
+        }
+    }
+
+    // The translation is undone after the linear part
+    let t = [m[3][0], m[3][1], m[3][2]];
+    let [x, y, z] = dir(&inv, t);
+
+    inv[3] = [-x, -y, -z, 1.];
+
+    Some(inv)
+}
@@ -0,0 +1,111 @@
+use super::V3Ops as _;
+
+// CPU counterpart of `intrs_moller_trumbore` in tri.wgsl.
+// Möller & Trumbore, Fast, Minimum Storage Ray/Triangle Intersection (1997).
+// Each triangle computes its own edges, so rays through an edge
+// that two triangles share can miss both. Returns the distance
+// along `dir` to the hit, if the triangle is in front of the origin
+pub fn moller_trumbore(
+    origin: [f32; 3],
+    dir: [f32; 3],
+    [a, b, c]: [[f32; 3]; 3],
+    eps: f32,
+) -> Option<f32> {
+    let e1 = b.sub(a);
+    let e2 = c.sub(a);
+
+    let p = dir.cross(e2);
+    let t = origin.sub(a);
+    let q = t.cross(e1);
+
+    let det = e1.dot(p);
+
+    if det > eps {
+        let u = t.dot(p);
+        if u < 0. || u > det { return None; }
+
+        let v = dir.dot(q);
+        if v < 0. || u + v > det { return None; }
+    } else if det < -eps {
+        let u = t.dot(p);
+        if u > 0. || u < det { return None; }
+
+        let v = dir.dot(q);
+        if v > 0. || u + v < det { return None; }
+    } else {
+        return None;
+    }
+
+    let dist = e2.dot(q) / det;
+
+    (dist > 0.).then_some(dist)
+}
+
+// CPU counterpart of `intrs_watertight` in tri.wgsl.
+// Woop et al., Watertight Ray/Triangle Intersection (2013).
+// Triangles that share an edge compute the same edge function,
+// so a ray that passes between them always hits one of the two.
+// Returns the distance along `dir` to the hit,
+// if the triangle is in front of the origin.
+//
+// Two parts of the paper are left out, here and in the shader:
+// - It recomputes the edge functions in double precision when one is
+//   exactly zero, so a ray through an edge hits only one of its triangles.
+//   WGSL has no f64, so such a ray hits both instead (it never leaks)
+// - It assumes `cx * by - cy * bx` is evaluated as written. GPU compilers
+//   may contract it into an FMA, which rounds the two products differently,
+//   so neighbours can disagree on an edge again. WGSL can't forbid that,
+//   so tests/watertight.rs also runs the shader on the adapter at hand
+pub fn watertight(
+    origin: [f32; 3],
+    dir: [f32; 3],
+    [a, b, c]: [[f32; 3]; 3],
+) -> Option<f32> {
+    // The largest component of the direction becomes z.
+    // Both windings are accepted, so the other axes are never swapped
+    let [dx, dy, dz] = dir.map(f32::abs);
+
+    let kz = if dx > dy && dx > dz {
+        0
+    } else if dy > dz {
+        1
+    } else {
+        2
+    };
+
+    let kx = (kz + 1) % 3;
+    let ky = (kx + 1) % 3;
+
+    // Shears the direction onto the z-axis
+    let sx = dir[kx] / dir[kz];
+    let sy = dir[ky] / dir[kz];
+    let sz = 1. / dir[kz];
+
+    let a = a.sub(origin);
+    let b = b.sub(origin);
+    let c = c.sub(origin);
+
+    let ax = a[kx] - sx * a[kz];
+    let ay = a[ky] - sy * a[kz];
+    let bx = b[kx] - sx * b[kz];
+    let by = b[ky] - sy * b[kz];
+    let cx = c[kx] - sx * c[kz];
+    let cy = c[ky] - sy * c[kz];
+
+    // Scaled barycentric coordinates. Rays through an edge give zero,
+    // which counts as a hit for both of the triangles that share it
+    let u = cx * by - cy * bx;
+    let v = ax * cy - ay * cx;
+    let w = bx * ay - by * ax;
+
+    if (u < 0. || v < 0. || w < 0.) && (u > 0. || v > 0. || w > 0.) {
+        return None;
+    }
+
+    let det = u + v + w;
+    if det == 0. { return None; }
+
+    let dist = sz * (u * a[kz] + v * b[kz] + w * c[kz]) / det;
+
+    (dist > 0.).then_some(dist)
+}
@@ -1,4 +1,4 @@
-use crate::scene;
+use crate::{scene, shaders};
 
 pub struct BasicIntrs;
 
@@ -10,7 +10,7 @@ impl super::IntrsHandler for BasicIntrs {
     fn vars<'a>(
         &self,
         _scene: &mut scene::Scene, device: &wgpu::Device,
-    ) -> (super::IntrsPack<'a>, super::IntrsStats) {
+    ) -> anyhow::Result<(super::IntrsPack<'a>, super::IntrsStats)> {
         let layout = device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
                 label: None,
@@ -35,74 +35,63 @@ impl super::IntrsHandler for BasicIntrs {
         let stats = super::IntrsStats { 
             name: "Naive",
             size: 0,
+            metrics: None,
+            compression: None,
         };
 
-        (pack, stats)
+        Ok((pack, stats))
     }
 
-    fn logic(&self) -> &'static str {"\
-        fn intrs_tri(r: Ray, s: Prim) -> Intrs {
-            let e1: vec3<f32> = vertices[s.b].pos - vertices[s.a].pos;
-            let e2: vec3<f32> = vertices[s.c].pos - vertices[s.a].pos;
-
-            let p: vec3<f32> = cross(r.dir, e2);
-            let t: vec3<f32> = r.origin - vertices[s.a].pos;
-            let q: vec3<f32> = cross(t, e1);
-
-            let det = dot(e1, p);
-
-            var u: f32 = 0.0;
-            var v: f32 = 0.0;
-            if(det > config.eps) {
-                u = dot(t, p);
-                if(u < 0.0 || u > det) { return intrs_empty(); }
-
-                v = dot(r.dir, q);
-                if(v < 0.0 || u + v > det) { return intrs_empty(); }
-            } else if(det < -1.0 * config.eps) {
-                u = dot(t, p);
-                if(u > 0.0 || u < det) { return intrs_empty(); }
-
-                v = dot(r.dir, q);
-                if(v > 0.0 || u + v < det) { return intrs_empty(); }
-            } else {
-                return intrs_empty();
-            }
-
-            let w: f32 = dot(e2, q) / det;
-            
-            if(w > config.t_max || w < config.t_min) {
-                return intrs_empty();
-            } else {
-                return Intrs(s, w);
-            }
-        }
-        
-        fn intrs(r: Ray, excl: Prim) -> Intrs {
-            var intrs: Intrs = Intrs(primitives[0], config.t_max + 1.0);
+    fn logic(&self) -> shaders::Module {
+        shaders::Module::new("naive", "\
+        fn intrs(r: Ray, excl: Excl) -> Intrs {
+            var intrs: Intrs = Intrs(primitives[0], config.t_max + 1.0, 0u);
 
             for(var i = 1i; i < i32(arrayLength(&primitives)); i = i + 1i) {
                 let prim: Prim = primitives[i];
 
-                var excluded: bool = false;
-                    excluded |= prim.a != excl.a;
-                    excluded |= prim.b != excl.b;
-                    excluded |= prim.c != excl.c;
+                if(eq(prim, excl.s)) { continue; }
 
-                if(excluded) {
-                    var intrs_temp: Intrs = intrs_tri(r, prim);
+                var intrs_temp: Intrs = intrs_tri(r, prim);
 
-                    var replace: bool = intrs_temp.t < intrs.t;
-                        replace &= intrs_temp.t > config.t_min;
-                        replace &= intrs_temp.t < config.t_max;
+                var replace: bool = intrs_temp.t < intrs.t;
+                    replace &= intrs_temp.t > config.t_min;
+                    replace &= intrs_temp.t < config.t_max;
 
-                    if(replace) {
-                        intrs = intrs_temp;
-                    }
+                if(replace) {
+                    intrs = intrs_temp;
                 }
             }
         
             return intrs;
         }
-    "}
+
+        // Stops at the first primitive between the origin and `t_max`
+        fn occluded(r: Ray, t_max: f32, excl: Excl) -> bool {
+            for(var i = 1i; i < i32(arrayLength(&primitives)); i = i + 1i) {
+                let prim: Prim = primitives[i];
+
+                if(eq(prim, excl.s)) { continue; }
+
+                let intrs_temp: Intrs = intrs_tri(r, prim);
+
+                if(intrs_valid(intrs_temp) && intrs_temp.t < t_max) {
+                    return true;
+                }
+            }
+
+            return false;
+        }
+    ").import(shaders::library::tri())
+    }
+
+    fn any_hit(&self) -> bool { true }
+
+    // Reads the scene's vertices directly, so there is nothing to update
+    fn refit(
+        &mut self,
+        _scene: &scene::Scene,
+        _queue: &wgpu::Queue,
+        _pack: &super::IntrsPack,
+    ) -> bool { true }
 }
\ No newline at end of file
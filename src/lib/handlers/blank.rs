@@ -1,4 +1,4 @@
-use crate::scene;
+use crate::{scene, shaders};
 
 // This handler just renders a blank screen
 // Used to test benchmarking baseline
@@ -12,7 +12,7 @@ impl super::IntrsHandler for BlankIntrs {
     fn vars<'a>(
         &self,
         _scene: &mut scene::Scene, device: &wgpu::Device,
-    ) -> (super::IntrsPack<'a>, super::IntrsStats) {
+    ) -> anyhow::Result<(super::IntrsPack<'a>, super::IntrsStats)> {
         let layout = device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
                 label: None,
@@ -37,12 +37,24 @@ impl super::IntrsHandler for BlankIntrs {
         let stats = super::IntrsStats { 
             name: "Blank",
             size: 0,
+            metrics: None,
+            compression: None,
         };
 
-        (pack, stats)
+        Ok((pack, stats))
     }
 
-    fn logic(&self) -> &'static str {
-        "fn intrs(r: Ray, excl: Prim) -> Intrs { return intrs_empty(); }"
+    fn logic(&self) -> shaders::Module {
+        shaders::Module::new("blank", "\
+            fn intrs(r: Ray, excl: Excl) -> Intrs { return intrs_empty(); }
+        ")
     }
+
+    // Nothing is ever intersected, so there is nothing to update
+    fn refit(
+        &mut self,
+        _scene: &scene::Scene,
+        _queue: &wgpu::Queue,
+        _pack: &super::IntrsPack,
+    ) -> bool { true }
 }
\ No newline at end of file
@@ -5,35 +5,48 @@ use wgpu::util::DeviceExt as _;
 
 use once_cell::unsync;
 
-use crate::bvh;
+use crate::{bvh, shaders};
 
 // This stores all configuration options 
 // for construction of the BVH and its intersection logic
+#[derive(serde::Deserialize)]
+#[derive(Default)]
 pub enum BvhConfig {
-    Bytes(Vec<u8>),
-    Runtime { eps: f32, },
+    Bytes(Vec<u8>, super::Traversal),
+    // Prebuilt data, such as a bvh::DynamicBvh after scene edits
+    Data(bvh::BvhData, super::Traversal),
+    Runtime { eps: f32, strategy: bvh::Strategy, traversal: super::Traversal, },
+    // Build the BVH on the GPU with bvh::GpuBuilder.
+    // Much faster for large scenes, but lower quality. Never the default,
+    // e.g. `{ "name": "bvh", "config": { "Gpu": "Unordered" } }` opts in
+    Gpu(super::Traversal),
+    #[default]
     Default,
 }
 
-impl Default for BvhConfig {
-    fn default() -> Self { Self::Default }
-}
-
 pub struct BvhIntrs {
     pub eps: f32,
+    pub strategy: bvh::Strategy,
+    pub traversal: super::Traversal,
+    pub gpu: bool,
 
     // These members are private, 
     // binaries should access them through BvhConfig
     data: unsync::OnceCell<bvh::BvhData>,
     nodes: unsync::OnceCell<usize>,
+    builder: unsync::OnceCell<bvh::GpuBuilder>,
 }
 
 impl Default for BvhIntrs {
     fn default() -> Self {
         Self { 
             eps: 0.02, 
+            strategy: bvh::Strategy::Midpoint,
+            traversal: super::Traversal::Unordered,
+            gpu: false,
             data: unsync::OnceCell::new(),
             nodes: unsync::OnceCell::new(),
+            builder: unsync::OnceCell::new(),
         }
     }
 }
@@ -51,19 +64,37 @@ impl super::IntrsHandler for BvhIntrs {
 
     fn new(config: Self::Config) -> anyhow::Result<Self> {
         let intrs = match config {
-            BvhConfig::Bytes(bytes) => {
+            BvhConfig::Bytes(bytes, traversal) => {
                 let data = serde_json::from_slice::<bvh::BvhData>(&bytes)?;
 
                 let nodes = data.uniforms.len();
 
                 Self {
+                    traversal,
                     data: unsync::OnceCell::with_value(data),
                     nodes: unsync::OnceCell::with_value(nodes),
                     ..Default::default()
                 }
             },
-            BvhConfig::Runtime { eps } => Self {
+            BvhConfig::Data(data, traversal) => {
+                let nodes = data.uniforms.len();
+
+                Self {
+                    traversal,
+                    data: unsync::OnceCell::with_value(data),
+                    nodes: unsync::OnceCell::with_value(nodes),
+                    ..Default::default()
+                }
+            },
+            BvhConfig::Runtime { eps, strategy, traversal } => Self {
                 eps,
+                strategy,
+                traversal,
+                ..Default::default()
+            },
+            BvhConfig::Gpu(traversal) => Self {
+                traversal,
+                gpu: true,
                 ..Default::default()
             },
             BvhConfig::Default => Self::default(),
@@ -76,38 +107,65 @@ impl super::IntrsHandler for BvhIntrs {
         &self,
         scene: &mut crate::scene::Scene, 
         device: &wgpu::Device
-    ) -> (super::IntrsPack<'a>, super::IntrsStats) {
-        // Build the BVH if we haven't already
-        let data = self.data.get_or_init(|| {
-            let aabb = bvh::Aabb::from_scene(self.eps, scene, 2);
+    ) -> anyhow::Result<(super::IntrsPack<'a>, super::IntrsStats)> {
+        // The GPU builder needs at least two primitives,
+        // smaller scenes are cheap enough to build here anyway
+        let gpu_prims = match scene {
+            crate::scene::Scene::Active { prims, vertices, .. } //
+                if self.gpu && self.data.get().is_none() && prims.len() > 1 => {
+                Some((prims.as_slice(), vertices.as_slice()))
+            },
+            _ => None,
+        };
 
-            bvh::BvhData::new(&aabb)
-        });
+        let (aabb_uniforms, metrics) = if let Some((prims, vertices)) = gpu_prims {
+            let nodes = *self.nodes.get_or_init(|| bvh::GpuBuilder::nodes(prims.len()));
 
-        let bvh::BvhData {
-            uniforms,
-            indices, ..
-        } = data;
+            // Filled in by `build`
+            let aabb_uniforms = device.create_buffer(
+                &wgpu::BufferDescriptor {
+                    label: None,
+                    size: (mem::size_of::<bvh::AabbUniform>() * nodes) as u64,
+                    usage: wgpu::BufferUsages::STORAGE | Self::COPY_USAGES,
+                    mapped_at_creation: false,
+                }
+            );
 
-        // Set the node count if we haven't already
-        self.nodes.get_or_init(|| uniforms.len());
+            let builder = bvh::GpuBuilder::new(device, prims, vertices, &aabb_uniforms)?;
 
-        let aabb_uniforms = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: None,
-                contents: bytemuck::cast_slice(uniforms),
-                usage: wgpu::BufferUsages::STORAGE | Self::COPY_USAGES,
-            }
-        );
+            let _ = self.builder.set(builder);
 
-        if let crate::scene::Scene::Active { prims, .. } = scene {
-            let ordered = indices
-                .iter()
-                .map(|&idx| prims[idx as usize])
-                .collect::<Vec<_>>();
+            // The leaves refer to the primitives in their original order,
+            // so there's nothing to reorder. The tree never reaches the CPU
+            (aabb_uniforms, None)
+        } else {
+            // Build the BVH if we haven't already
+            let data = self.data.get_or_init(|| {
+                bvh::BvhData::from_scene(self.eps, scene, 2, self.strategy)
+            });
+
+            let bvh::BvhData {
+                uniforms,
+                indices, ..
+            } = data;
+
+            // Set the node count if we haven't already
+            self.nodes.get_or_init(|| uniforms.len());
+
+            let aabb_uniforms = device.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::cast_slice(uniforms),
+                    usage: wgpu::BufferUsages::STORAGE | Self::COPY_USAGES,
+                }
+            );
 
-            let _ = mem::replace(prims, ordered);
-        }
+            scene.reorder(indices);
+
+            (aabb_uniforms, Some(data.metrics()))
+        };
+
+        let nodes = self.nodes.get().copied().unwrap_or_default();
 
         let layout = device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
@@ -158,37 +216,79 @@ impl super::IntrsHandler for BvhIntrs {
         };
 
         let stats = super::IntrsStats {
-            name: "BVH",
-            size: mem::size_of::<bvh::AabbUniform>() * uniforms.len(),
+            name: match (self.traversal, self.builder.get().is_some()) {
+                (super::Traversal::Unordered, false) => "BVH",
+                (super::Traversal::Ordered, false) => "BVH (Ordered)",
+                (super::Traversal::Unordered, true) => "BVH (GPU)",
+                (super::Traversal::Ordered, true) => "BVH (GPU, Ordered)",
+            },
+            size: mem::size_of::<bvh::AabbUniform>() * nodes,
+            metrics,
+            compression: None,
         };
 
-        (pack, stats)
+        Ok((pack, stats))
     }
 
-    fn logic(&self) -> &'static str {
-        // In the shader code below, this line is incomplete.
-        // It needs to be given a type
-        const DECL: &str = "var<private> aabb_stack;";
-
+    fn logic(&self) -> shaders::Module {
         // IntrsHandler::logic is always called after IntrsHandler::vars,
         // so the diverging case is truly unreachable
-        let Some(nodes) = self.nodes.get().copied() else { 
-            unreachable!();
+        let depth = match (self.data.get(), self.nodes.get()) {
+            (Some(data), _) => data.metrics().depth_max,
+            // Trees built on the GPU never reach the CPU
+            (None, Some(&nodes)) => bvh::GpuBuilder::depth_max(nodes.div_ceil(2)),
+            (None, None) => unreachable!(),
         };
 
-        // Perform the replacement
-        let mut logic = String::from(LOGIC); logic.insert_str(
-            LOGIC.find(DECL).unwrap() + DECL.len() - 1, 
-            format!(": array<u32, {nodes}>",).as_str()
-        );
-        
-        // We have to return a static string, so we leak it
-        Box::leak(logic.into_boxed_str())
+        // Each level leaves at most one sibling on the stack
+        let size = depth + 1;
+
+        shaders::Module::new("bvh", LOGIC)
+            .param("ORDERED", self.traversal == super::Traversal::Ordered)
+            .import(shaders::library::tri())
+            .import(shaders::library::slab())
+            .import(shaders::library::stack(size))
+            .import(shaders::library::stack_named("ordered", "OrderedEntry", size))
+    }
+
+    fn build(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if let Some(builder) = self.builder.get() {
+            builder.build(device, queue);
+        }
+    }
+
+    fn any_hit(&self) -> bool { true }
+
+    fn refit(
+        &mut self,
+        scene: &crate::scene::Scene,
+        queue: &wgpu::Queue,
+        pack: &super::IntrsPack,
+    ) -> bool {
+        // Trees built on the GPU have no BvhData, so they're rebuilt instead
+        let (
+            Some(data), 
+            crate::scene::Scene::Active { prims, vertices, .. },
+        ) = (self.data.get_mut(), scene) else { return false; };
+
+        // `vars` reordered the scene's primitives to match `indices`
+        data.refit_ordered(prims, vertices);
+
+        // The node count is unchanged, so the buffer can be reused
+        let Some(super::IntrsVar { buffer, .. }) = pack.vars
+            .iter()
+            .find(|var| var.var_name == "aabb_uniforms") else { return false; };
+
+        queue.write_buffer(buffer, 0, bytemuck::cast_slice(&data.uniforms));
+
+        true
     }
 }
 
 // The intersection logic
 const LOGIC: &str = "\
+    const ORDERED: bool = {{ORDERED}};
+
     struct Bounds {
         min: vec3<f32>,
         max: vec3<f32>,
@@ -202,69 +302,8 @@ const LOGIC: &str = "\
         bounds: Bounds,
     }
 
-    fn intrs_tri(r: Ray, s: Prim) -> Intrs {
-        let e1: vec3<f32> = vertices[s.b].pos - vertices[s.a].pos;
-        let e2: vec3<f32> = vertices[s.c].pos - vertices[s.a].pos;
-
-        let p: vec3<f32> = cross(r.dir, e2);
-        let t: vec3<f32> = r.origin - vertices[s.a].pos;
-        let q: vec3<f32> = cross(t, e1);
-
-        let det = dot(e1, p);
-
-        var u: f32 = 0.0;
-        var v: f32 = 0.0;
-        if(det > config.eps) {
-            u = dot(t, p);
-            if(u < 0.0 || u > det) { return intrs_empty(); }
-
-            v = dot(r.dir, q);
-            if(v < 0.0 || u + v > det) { return intrs_empty(); }
-        } else if(det < -1.0 * config.eps) {
-            u = dot(t, p);
-            if(u > 0.0 || u < det) { return intrs_empty(); }
-
-            v = dot(r.dir, q);
-            if(v > 0.0 || u + v < det) { return intrs_empty(); }
-        } else {
-            return intrs_empty();
-        }
-
-        let w: f32 = dot(e2, q) / det;
-        
-        if(w > config.t_max || w < config.t_min) {
-            return intrs_empty();
-        } else {
-            return Intrs(s, w);
-        }
-    }
-
-    const INF_POS: f32 = 0x1.p+38f;
-    const INF_NEG: f32 = -1.0 * INF_POS;
-
-    // Wobble for the intersection test below
-    const EPS: f32 = 0.000002;
-
     fn collides(bb: Aabb, ray: Ray) -> bool {
-        var t0 = (bb.bounds.min.x - EPS - ray.origin.x) / ray.dir.x;
-        var t1 = (bb.bounds.max.x + EPS - ray.origin.x) / ray.dir.x;
-
-        var t_min = min(t0, t1);
-        var t_max = max(t0, t1);
-        
-        t0 = (bb.bounds.min.y - EPS - ray.origin.y) / ray.dir.y;
-        t1 = (bb.bounds.max.y + EPS - ray.origin.y) / ray.dir.y;
-
-        t_min = max(t_min, min(min(t0, t1), INF_NEG));
-        t_max = min(t_max, max(max(t0, t1), INF_POS));
-        
-        t0 = (bb.bounds.min.z - EPS - ray.origin.z) / ray.dir.z;
-        t1 = (bb.bounds.max.z + EPS - ray.origin.z) / ray.dir.z;
-
-        t_min = max(t_min, min(min(t0, t1), INF_NEG));
-        t_max = min(t_max, max(max(t0, t1), INF_POS));
-
-        return (t_min < t_max);
+        return box_collides(bb.bounds.min, bb.bounds.max, ray);
     }
 
     // TODO: The vector-based collision method is faster,
@@ -283,11 +322,18 @@ const LOGIC: &str = "\
         return (t_min < t_max);
     }
 
-    fn intrs_bvh(bb: Aabb, ray: Ray, excl: Prim) -> Intrs {
+    fn entry(bb: Aabb, ray: Ray) -> f32 {
+        return box_entry(bb.bounds.min, bb.bounds.max, ray);
+    }
+
+    fn intrs_bvh(bb: Aabb, ray: Ray, excl: Excl) -> Intrs {
         var intrs: Intrs = intrs_empty();
 
         for(var i: u32 = bb.item_idx; i < (bb.item_idx + bb.item_count); i = i + 1u) {
-            let prim: Prim = primitives[i];
+            // Offset by one to skip the 'null' primitive
+            let prim: Prim = primitives[i + 1u];
+
+            if(eq(prim, excl.s)) { continue; }
 
             let temp: Intrs = intrs_tri(ray, prim);
 
@@ -299,35 +345,72 @@ const LOGIC: &str = "\
         return intrs;
     }
 
-    // NOTE: The type is specified by BvhIntrs::logic
-    var<private> aabb_stack;
+    // A node on the ordered stack, with the distance at which the ray enters it
+    struct OrderedEntry {
+        node: u32,
+        t: f32,
+    }
 
-    fn pop(idx: ptr<function, u32>, empty: ptr<function, bool>) -> u32 {
-        if(*idx == 1u) {
-            *empty = true;
-        }
+    fn intrs_ordered(r: Ray, excl: Excl) -> Intrs {
+        var intrs = intrs_empty();
 
-        *idx = *idx - 1u;
+        let t_root = entry(aabb_uniforms[0], r);
+        if(t_root == INF_POS) { return intrs; }
 
-        return aabb_stack[*idx];
-    }
+        var stack_idx = 0u;
+        ordered_push(&stack_idx, OrderedEntry(0u, t_root));
+
+        while(stack_idx > 0u) {
+            let top = ordered_pop(&stack_idx);
+
+            // Nodes beyond the closest hit can't contain a closer one
+            if(top.t > intrs.t) { continue; }
+
+            let bb = aabb_uniforms[top.node];
+
+            if(bb.item_count > 0u) {
+                let temp = intrs_bvh(bb, r, excl);
+
+                if(temp.t < intrs.t) {
+                    intrs = temp;
+                }
+            } else {
+                var near = bb.fst;
+                var far = bb.snd;
+
+                var t_near = entry(aabb_uniforms[near], r);
+                var t_far = entry(aabb_uniforms[far], r);
+
+                if(t_far < t_near) {
+                    near = bb.snd; far = bb.fst;
+
+                    let temp = t_near; t_near = t_far; t_far = temp;
+                }
+
+                // The nearer child is pushed last, so it's visited first
+                if(t_far < intrs.t) {
+                    ordered_push(&stack_idx, OrderedEntry(far, t_far));
+                }
 
-    fn push(idx: ptr<function, u32>, bb: u32) {
-        aabb_stack[*idx] = bb;
+                if(t_near < intrs.t) {
+                    ordered_push(&stack_idx, OrderedEntry(near, t_near));
+                }
+            }
+        }
 
-        *idx = *idx + 1u;
+        return intrs;
     }
 
-    fn intrs(r: Ray, excl: Prim) -> Intrs {
-        var stack_idx = 0u;
-        var stack_empty = false;
+    fn intrs(r: Ray, excl: Excl) -> Intrs {
+        if(ORDERED) { return intrs_ordered(r, excl); }
 
+        var stack_idx = 0u;
         push(&stack_idx, 0u);
 
         var intrs = intrs_empty();
 
-        while(!stack_empty) {
-            let bb_idx = pop(&stack_idx, &stack_empty);
+        while(stack_idx > 0u) {
+            let bb_idx = pop(&stack_idx);
             let bb = aabb_uniforms[bb_idx];
 
             if(collides(bb, r)) {
@@ -340,12 +423,46 @@ const LOGIC: &str = "\
                 } else {
                     push(&stack_idx, bb.fst);
                     push(&stack_idx, bb.snd);
-
-                    stack_empty = false;
                 }
             }
         }
 
         return intrs;
+    }
+
+    // Stops at the first primitive between the origin and `t_max`,
+    // so the traversal order doesn't matter
+    fn occluded(r: Ray, t_max: f32, excl: Excl) -> bool {
+        var stack_idx = 0u;
+        push(&stack_idx, 0u);
+
+        while(stack_idx > 0u) {
+            let bb_idx = pop(&stack_idx);
+            let bb = aabb_uniforms[bb_idx];
+
+            // Nodes beyond `t_max` can't occlude anything
+            if(entry(bb, r) < t_max) {
+                if(bb.item_count > 0u) {
+                    let start = bb.item_idx;
+                    for(var i = start; i < start + bb.item_count; i = i + 1u) {
+                        // Offset by one to skip the 'null' primitive
+                        let prim: Prim = primitives[i + 1u];
+
+                        if(eq(prim, excl.s)) { continue; }
+
+                        let temp = intrs_tri(r, prim);
+
+                        if(intrs_valid(temp) && temp.t < t_max) {
+                            return true;
+                        }
+                    }
+                } else {
+                    push(&stack_idx, bb.fst);
+                    push(&stack_idx, bb.snd);
+                }
+            }
+        }
+
+        return false;
     }\
 ";
\ No newline at end of file
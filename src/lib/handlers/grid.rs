@@ -0,0 +1,418 @@
+use std::mem;
+
+use once_cell::unsync;
+use wgpu::util::DeviceExt as _;
+
+use crate::{bvh, geom, scene, shaders};
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy)]
+struct GridUniform {
+    min: [f32; 3],
+    _padding0: u32,
+    max: [f32; 3],
+    _padding1: u32,
+    dims: [u32; 3],
+    _padding2: u32,
+}
+
+// The grid is stored in compressed rows:
+// the items in cell `i` are `items[cells[i]..cells[i + 1]]`
+struct GridData {
+    uniform: GridUniform,
+    cells: Vec<u32>,
+    items: Vec<u32>,
+}
+
+#[derive(serde::Deserialize)]
+#[derive(Default)]
+pub enum GridConfig {
+    // A fixed number of cells along each axis, up to MAX_RESOLUTION
+    Resolution([u32; 3]),
+    // The desired number of cells per primitive.
+    // Cells are kept as close to cubes as possible (Cleary & Wyvill 1988)
+    Density(f32),
+    #[default]
+    Default,
+}
+
+pub struct GridIntrs {
+    pub config: GridConfig,
+    data: unsync::OnceCell<GridData>,
+}
+
+impl GridIntrs {
+    // A reasonable density for most scenes
+    pub const DENSITY: f32 = 2.;
+
+    // Bounds the memory used by the grid, whatever the config
+    pub const MAX_RESOLUTION: u32 = 128;
+}
+
+// Determines the number of cells along each axis
+fn resolution(config: &GridConfig, extent: [f32; 3], count: usize) -> [u32; 3] {
+    let density = match config {
+        GridConfig::Resolution(dims) => {
+            return dims.map(|dim| dim.clamp(1, GridIntrs::MAX_RESOLUTION));
+        },
+        GridConfig::Density(density) => *density,
+        GridConfig::Default => GridIntrs::DENSITY,
+    };
+
+    let volume = extent[0] * extent[1] * extent[2];
+
+    // Cells per unit length
+    let scale = (density * count as f32 / volume).cbrt();
+
+    extent.map(|extent| {
+        ((extent * scale).round() as u32).clamp(1, GridIntrs::MAX_RESOLUTION)
+    })
+}
+
+fn voxelize(config: &GridConfig, scene: &scene::Scene) -> GridData {
+    let empty = GridData {
+        uniform: GridUniform {
+            min: [0.; 3],
+            _padding0: 0,
+            max: [0.; 3],
+            _padding1: 0,
+            dims: [1; 3],
+            _padding2: 0,
+        },
+        cells: vec![0, 0],
+        items: Vec::new(),
+    };
+
+    let scene::Scene::Active { prims, vertices, .. } = scene else {
+        return empty;
+    };
+
+    if prims.is_empty() {
+        return empty;
+    }
+
+    let bvh::Bounds { min, max, .. } = bvh::Bounds::new(prims.iter().copied(), vertices);
+
+    // Pad the bounds so flat scenes still have volume
+    let pad = {
+        use geom::V3Ops as _;
+
+        let [dx, dy, dz] = max.sub(min);
+
+        (dx * dx + dy * dy + dz * dz).sqrt() * 0.001 + f32::EPSILON
+    };
+
+    let min = min.map(|v| v - pad);
+    let max = max.map(|v| v + pad);
+
+    let extent = [0, 1, 2].map(|axis| max[axis] - min[axis]);
+
+    let dims = resolution(config, extent, prims.len());
+
+    // The range of cells along each axis that overlap the bounds
+    let range = |bounds: bvh::Bounds| -> [(u32, u32); 3] {
+        [0, 1, 2].map(|axis| {
+            let cell = |v: f32| {
+                let offset = (v - min[axis]) / extent[axis] * dims[axis] as f32;
+
+                (offset.max(0.) as u32).min(dims[axis] - 1)
+            };
+
+            (cell(bounds.min[axis]), cell(bounds.max[axis]))
+        })
+    };
+
+    let flat = |x: u32, y: u32, z: u32| -> usize {
+        (x + dims[0] * (y + dims[1] * z)) as usize
+    };
+
+    // Each primitive is placed in every cell its bounds overlap
+    let mut occupancy: Vec<Vec<u32>> = vec![Vec::new(); (dims[0] * dims[1] * dims[2]) as usize];
+
+    for (idx, &prim) in prims.iter().enumerate() {
+        let [
+            (x_min, x_max),
+            (y_min, y_max),
+            (z_min, z_max),
+        ] = range(bvh::Bounds::new(std::iter::once(prim), vertices));
+
+        for z in z_min..=z_max {
+            for y in y_min..=y_max {
+                for x in x_min..=x_max {
+                    occupancy[flat(x, y, z)].push(idx as u32);
+                }
+            }
+        }
+    }
+
+    let mut cells = Vec::with_capacity(occupancy.len() + 1);
+    let mut items = Vec::new();
+
+    cells.push(0);
+    for cell in occupancy.into_iter() {
+        items.extend(cell);
+        cells.push(items.len() as u32);
+    }
+
+    GridData {
+        uniform: GridUniform {
+            min,
+            _padding0: 0,
+            max,
+            _padding1: 0,
+            dims,
+            _padding2: 0,
+        },
+        cells,
+        items,
+    }
+}
+
+impl super::IntrsHandler for GridIntrs {
+    type Config = GridConfig;
+
+    fn new(config: Self::Config) -> anyhow::Result<Self> {
+        if let GridConfig::Density(density) = config {
+            if density <= 0. {
+                anyhow::bail!("Grid density must be positive");
+            }
+        }
+
+        Ok(Self {
+            config,
+            data: unsync::OnceCell::new(),
+        })
+    }
+
+    fn vars<'a>(
+        &self,
+        scene: &mut scene::Scene,
+        device: &wgpu::Device,
+    ) -> anyhow::Result<(super::IntrsPack<'a>, super::IntrsStats)> {
+        // Build the grid if we haven't already
+        let GridData {
+            uniform,
+            cells,
+            items,
+        } = self.data.get_or_init(|| voxelize(&self.config, scene));
+
+        let grid = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&[*uniform]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            }
+        );
+
+        let grid_cells = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(cells),
+                usage: wgpu::BufferUsages::STORAGE,
+            }
+        );
+
+        // Empty buffers can't be bound, so there is always at least one item.
+        // Empty cells never read it
+        let grid_items = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: if items.is_empty() {
+                    bytemuck::cast_slice(&[0u32])
+                } else {
+                    bytemuck::cast_slice(items)
+                },
+                usage: wgpu::BufferUsages::STORAGE,
+            }
+        );
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            count: None,
+            ty: wgpu::BindingType::Buffer {
+                has_dynamic_offset: false,
+                min_binding_size: None,
+                ty,
+            },
+        };
+
+        let storage = wgpu::BufferBindingType::Storage { read_only: true };
+
+        let layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    entry(0, wgpu::BufferBindingType::Uniform),
+                    entry(1, storage),
+                    entry(2, storage),
+                ]
+            }
+        );
+
+        let group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: grid.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: grid_cells.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: grid_items.as_entire_binding(),
+                    },
+                ],
+            }
+        );
+
+        let pack = super::IntrsPack {
+            vars: vec![
+                super::IntrsVar {
+                    var_name: "grid",
+                    var_ty: "Grid",
+                    buffer: grid,
+                    buffer_ty: wgpu::BufferBindingType::Uniform,
+                },
+                super::IntrsVar {
+                    var_name: "grid_cells",
+                    var_ty: "array<u32>",
+                    buffer: grid_cells,
+                    buffer_ty: storage,
+                },
+                super::IntrsVar {
+                    var_name: "grid_items",
+                    var_ty: "array<u32>",
+                    buffer: grid_items,
+                    buffer_ty: storage,
+                },
+            ],
+            group,
+            layout,
+        };
+
+        let stats = super::IntrsStats {
+            name: "Grid",
+            size: mem::size_of::<GridUniform>() + //
+                mem::size_of::<u32>() * (cells.len() + items.len()),
+            metrics: None,
+            compression: None,
+        };
+
+        Ok((pack, stats))
+    }
+
+    fn logic(&self) -> shaders::Module {
+        shaders::Module::new("grid", LOGIC)
+            .import(shaders::library::tri())
+            .import(shaders::library::slab())
+    }
+}
+
+// The intersection logic
+const LOGIC: &str = "\
+    struct Grid {
+        min: vec3<f32>,
+        max: vec3<f32>,
+        dims: vec3<u32>,
+    }
+
+    fn intrs_cell(cell: u32, ray: Ray, excl: Excl) -> Intrs {
+        var intrs: Intrs = intrs_empty();
+
+        for(var i: u32 = grid_cells[cell]; i < grid_cells[cell + 1u]; i = i + 1u) {
+            // Offset by one to skip the 'null' primitive
+            let prim: Prim = primitives[grid_items[i] + 1u];
+
+            if(eq(prim, excl.s)) { continue; }
+
+            let temp: Intrs = intrs_tri(ray, prim);
+
+            if(temp.t < intrs.t) {
+                intrs = temp;
+            }
+        }
+
+        return intrs;
+    }
+
+    // Walks the cells along the ray with a 3D-DDA (Amanatides & Woo 1987)
+    fn intrs(r: Ray, excl: Excl) -> Intrs {
+        var intrs = intrs_empty();
+
+        // Axis-aligned rays never cross the planes of the other axes
+        let inv = select(vec3<f32>(INF_POS), 1.0 / r.dir, r.dir != vec3<f32>(0.0));
+
+        let t0 = (grid.min - r.origin) * inv;
+        let t1 = (grid.max - r.origin) * inv;
+
+        let t_mins = min(t0, t1);
+        let t_maxs = max(t0, t1);
+
+        let t_enter = max(max(t_mins.x, t_mins.y), max(t_mins.z, 0.0));
+        let t_leave = min(t_maxs.x, min(t_maxs.y, t_maxs.z));
+
+        if(t_enter > t_leave) { return intrs; }
+
+        let dims = vec3<i32>(grid.dims);
+        let size = (grid.max - grid.min) / vec3<f32>(grid.dims);
+
+        let entry = r.origin + r.dir * t_enter;
+
+        var cell = clamp(
+            vec3<i32>(floor((entry - grid.min) / size)),
+            vec3<i32>(0),
+            dims - vec3<i32>(1)
+        );
+
+        let step = vec3<i32>(sign(r.dir));
+
+        // The distance to the next plane crossing along each axis
+        let upper = select(vec3<f32>(0.0), vec3<f32>(1.0), r.dir > vec3<f32>(0.0));
+        var t_next = select(
+            vec3<f32>(INF_POS),
+            (grid.min + (vec3<f32>(cell) + upper) * size - r.origin) * inv,
+            r.dir != vec3<f32>(0.0)
+        );
+
+        let t_delta = abs(size * inv);
+
+        // A ray crosses at most one cell per plane
+        let steps = grid.dims.x + grid.dims.y + grid.dims.z;
+        for(var i: u32 = 0u; i < steps; i = i + 1u) {
+            let idx = u32(cell.x + dims.x * (cell.y + dims.y * cell.z));
+
+            let temp = intrs_cell(idx, r, excl);
+            if(temp.t < intrs.t) {
+                intrs = temp;
+            }
+
+            let t_exit = min(t_next.x, min(t_next.y, t_next.z));
+
+            // Primitives span multiple cells,
+            // so only hits within this cell are final
+            if(intrs.t <= t_exit) { break; }
+
+            if(t_next.x == t_exit) {
+                cell.x = cell.x + step.x;
+                t_next.x = t_next.x + t_delta.x;
+            } else if(t_next.y == t_exit) {
+                cell.y = cell.y + step.y;
+                t_next.y = t_next.y + t_delta.y;
+            } else {
+                cell.z = cell.z + step.z;
+                t_next.z = t_next.z + t_delta.z;
+            }
+
+            if(any(cell < vec3<i32>(0)) || any(cell >= dims)) { break; }
+        }
+
+        return intrs;
+    }\
+";
@@ -0,0 +1,337 @@
+use std::mem;
+
+use once_cell::unsync;
+use wgpu::util::DeviceExt as _;
+
+use crate::{kd, scene, shaders};
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy)]
+struct KdTreeUniform {
+    min: [f32; 3],
+    _padding0: u32,
+    max: [f32; 3],
+    _padding1: u32,
+}
+
+// This stores all configuration options for construction of the Kd-tree
+#[derive(serde::Deserialize)]
+#[derive(Default)]
+pub enum KdTreeConfig {
+    Bytes(Vec<u8>),
+    Runtime { max_depth: Option<usize>, },
+    #[default]
+    Default,
+}
+
+pub struct KdTreeIntrs {
+    // Defaults to `kd::KdData::max_depth` when None
+    pub max_depth: Option<usize>,
+
+    // These members are private,
+    // binaries should access them through KdTreeConfig
+    data: unsync::OnceCell<kd::KdData>,
+    depth: unsync::OnceCell<usize>,
+}
+
+impl super::IntrsHandler for KdTreeIntrs {
+    type Config = KdTreeConfig;
+
+    fn new(config: Self::Config) -> anyhow::Result<Self> {
+        let intrs = match config {
+            KdTreeConfig::Bytes(bytes) => {
+                let data = serde_json::from_slice::<kd::KdData>(&bytes)?;
+
+                Self {
+                    max_depth: None,
+                    data: unsync::OnceCell::with_value(data),
+                    depth: unsync::OnceCell::new(),
+                }
+            },
+            KdTreeConfig::Runtime { max_depth } => Self {
+                max_depth,
+                data: unsync::OnceCell::new(),
+                depth: unsync::OnceCell::new(),
+            },
+            KdTreeConfig::Default => Self {
+                max_depth: None,
+                data: unsync::OnceCell::new(),
+                depth: unsync::OnceCell::new(),
+            },
+        };
+
+        Ok(intrs)
+    }
+
+    fn vars<'a>(
+        &self,
+        scene: &mut scene::Scene,
+        device: &wgpu::Device,
+    ) -> anyhow::Result<(super::IntrsPack<'a>, super::IntrsStats)> {
+        // Build the Kd-tree if we haven't already
+        let kd::KdData {
+            bounds,
+            nodes,
+            indices,
+        } = self.data.get_or_init(|| kd::KdData::from_scene(scene, self.max_depth));
+
+        // Set the depth if we haven't already
+        self.depth.get_or_init(|| self.data.get().unwrap().depth());
+
+        let kd_tree = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&[KdTreeUniform {
+                    min: bounds.min,
+                    _padding0: 0,
+                    max: bounds.max,
+                    _padding1: 0,
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            }
+        );
+
+        let kd_nodes = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(nodes),
+                usage: wgpu::BufferUsages::STORAGE,
+            }
+        );
+
+        // Empty buffers can't be bound, so there is always at least one item.
+        // Empty leaves never read it
+        let kd_indices = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: if indices.is_empty() {
+                    bytemuck::cast_slice(&[0u32])
+                } else {
+                    bytemuck::cast_slice(indices)
+                },
+                usage: wgpu::BufferUsages::STORAGE,
+            }
+        );
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            count: None,
+            ty: wgpu::BindingType::Buffer {
+                has_dynamic_offset: false,
+                min_binding_size: None,
+                ty,
+            },
+        };
+
+        let storage = wgpu::BufferBindingType::Storage { read_only: true };
+
+        let layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    entry(0, wgpu::BufferBindingType::Uniform),
+                    entry(1, storage),
+                    entry(2, storage),
+                ]
+            }
+        );
+
+        let group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: kd_tree.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: kd_nodes.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: kd_indices.as_entire_binding(),
+                    },
+                ],
+            }
+        );
+
+        let pack = super::IntrsPack {
+            vars: vec![
+                super::IntrsVar {
+                    var_name: "kd_tree",
+                    var_ty: "KdTree",
+                    buffer: kd_tree,
+                    buffer_ty: wgpu::BufferBindingType::Uniform,
+                },
+                super::IntrsVar {
+                    var_name: "kd_nodes",
+                    var_ty: "array<KdNode>",
+                    buffer: kd_nodes,
+                    buffer_ty: storage,
+                },
+                super::IntrsVar {
+                    var_name: "kd_indices",
+                    var_ty: "array<u32>",
+                    buffer: kd_indices,
+                    buffer_ty: storage,
+                },
+            ],
+            group,
+            layout,
+        };
+
+        let stats = super::IntrsStats {
+            name: "Kd-tree",
+            size: mem::size_of::<KdTreeUniform>() + //
+                mem::size_of::<kd::KdNode>() * nodes.len() + //
+                mem::size_of::<u32>() * indices.len(),
+            metrics: None,
+            compression: None,
+        };
+
+        Ok((pack, stats))
+    }
+
+    fn logic(&self) -> shaders::Module {
+        // IntrsHandler::logic is always called after IntrsHandler::vars,
+        // so the diverging case is truly unreachable
+        let Some(depth) = self.depth.get().copied() else {
+            unreachable!();
+        };
+
+        // At most one node is deferred per level
+        shaders::Module::new("kd", LOGIC)
+            .import(shaders::library::tri())
+            .import(shaders::library::slab())
+            .import(shaders::library::stack_named("kd", "KdEntry", depth + 1))
+    }
+}
+
+// The intersection logic
+const LOGIC: &str = "\
+    struct KdTree {
+        min: vec3<f32>,
+        max: vec3<f32>,
+    }
+
+    // See kd::KdNode for the encoding
+    struct KdNode {
+        data: u32,
+        tag: u32,
+    }
+
+    // A node deferred for later, along with the ray's extent inside it
+    struct KdEntry {
+        node: u32,
+        t_min: f32,
+        t_max: f32,
+    }
+
+    const LEAF: u32 = 3u;
+
+    // Relative wobble applied to the distance of each split plane
+    const WOBBLE: f32 = 0.00001;
+
+    fn intrs_leaf(node: KdNode, ray: Ray, excl: Excl) -> Intrs {
+        var intrs: Intrs = intrs_empty();
+
+        let count = node.tag >> 2u;
+        for(var i: u32 = node.data; i < node.data + count; i = i + 1u) {
+            // Offset by one to skip the 'null' primitive
+            let prim: Prim = primitives[kd_indices[i] + 1u];
+
+            if(eq(prim, excl.s)) { continue; }
+
+            let temp: Intrs = intrs_tri(ray, prim);
+
+            if(temp.t < intrs.t) {
+                intrs = temp;
+            }
+        }
+
+        return intrs;
+    }
+
+    // Visits the leaves along the ray from front to back,
+    // so traversal can stop at the first leaf with a hit in front of it
+    fn intrs(r: Ray, excl: Excl) -> Intrs {
+        var intrs = intrs_empty();
+
+        // Axis-aligned rays never cross the planes of the other axes
+        let inv = select(vec3<f32>(INF_POS), 1.0 / r.dir, r.dir != vec3<f32>(0.0));
+
+        let t0 = (kd_tree.min - EPS - r.origin) * inv;
+        let t1 = (kd_tree.max + EPS - r.origin) * inv;
+
+        let t_mins = min(t0, t1);
+        let t_maxs = max(t0, t1);
+
+        var t_min = max(max(t_mins.x, t_mins.y), max(t_mins.z, 0.0));
+        var t_max = min(t_maxs.x, min(t_maxs.y, t_maxs.z));
+
+        if(t_min > t_max) { return intrs; }
+
+        var stack_idx = 0u;
+        var idx = 0u;
+
+        loop {
+            // Nothing left to visit can be closer than the current hit
+            if(intrs.t < t_min) { break; }
+
+            let node = kd_nodes[idx];
+            let axis = node.tag & 3u;
+
+            if(axis == LEAF) {
+                let temp = intrs_leaf(node, r, excl);
+
+                if(temp.t < intrs.t) {
+                    intrs = temp;
+                }
+
+                if(stack_idx == 0u) { break; }
+
+                let entry = kd_pop(&stack_idx);
+
+                idx = entry.node;
+                t_min = entry.t_min;
+                t_max = entry.t_max;
+            } else {
+                let split = bitcast<f32>(node.data);
+
+                let origin = r.origin[axis];
+                let t_split = (split - origin) * inv[axis];
+
+                // The child containing the origin is visited first
+                let below = origin < split || (origin == split && r.dir[axis] <= 0.0);
+
+                var near = idx + 1u;
+                var far = node.tag >> 2u;
+                if(!below) {
+                    near = far;
+                    far = idx + 1u;
+                }
+
+                // Rays passing through vertices on the plane
+                // have to visit both children
+                let wobble = abs(t_split) * WOBBLE + EPS;
+
+                if(t_split - wobble > t_max || t_split + wobble <= 0.0) {
+                    idx = near;
+                } else if(t_split + wobble < t_min) {
+                    idx = far;
+                } else {
+                    kd_push(&stack_idx, KdEntry(far, max(t_split - wobble, t_min), t_max));
+
+                    idx = near;
+                    t_max = min(t_split + wobble, t_max);
+                }
+            }
+        }
+
+        return intrs;
+    }\
+";
@@ -5,13 +5,36 @@ mod bvh;
 pub use bvh::{BvhIntrs, BvhConfig};
 
 mod rf;
-pub use rf::{RfBvhIntrs, RfBvhConfig};
+pub use rf::{RfBvhIntrs, RfBvhConfig, RfEncoding};
+
+mod wide;
+pub use wide::{WideBvhIntrs, WideBvhConfig};
+
+mod quantized;
+pub use quantized::{QuantizedBvhIntrs, QuantizedBvhConfig};
+
+mod threaded;
+pub use threaded::{ThreadedBvhIntrs, ThreadedBvhConfig};
+
+mod grid;
+pub use grid::{GridIntrs, GridConfig};
+
+mod kd;
+pub use kd::{KdTreeIntrs, KdTreeConfig};
+
+mod octree;
+pub use octree::{OctreeIntrs, OctreeConfig};
+
+mod tlas;
+pub use tlas::{TlasIntrs, TlasConfig};
 
 mod blank;
 // NOTE: Dummy intersection handler used for benchmarking
 pub use blank::BlankIntrs;
 
-use crate::scene;
+pub mod registry;
+
+use crate::{scene, shaders};
 
 #[derive(Debug)]
 pub struct IntrsVar<'a> {
@@ -42,26 +65,151 @@ impl<'a> IntrsPack<'a> {
     }
 }
 
+// The order in which the BVH handlers visit each node's children
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(serde::Deserialize)]
+#[derive(Default)]
+#[derive(PartialEq, Eq)]
+pub enum Traversal {
+    // Always push the first child, then the second
+    #[default]
+    Unordered,
+    // Visit the nearer child first, 
+    // and skip nodes that begin beyond the closest hit
+    Ordered,
+}
+
 #[derive(Clone, Copy)]
 #[derive(Debug)]
 pub struct IntrsStats {
     pub name: &'static str,
     pub size: usize,
+    // Only present for handlers that build a BVH
+    pub metrics: Option<crate::bvh::BvhMetrics>,
+    // Only present for handlers that compress their nodes
+    pub compression: Option<Compression>,
+}
+
+// Compressed nodes are cheaper to fetch, 
+// but their looser bounds are entered more often
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+pub struct Compression {
+    // The size of the equivalent uncompressed BVH
+    pub uncompressed: usize,
+    // Relative growth in the surface area of the decoded bounds
+    pub slack: f32,
 }
 
-pub trait IntrsHandler {
-    type Config: Default;
+// NOTE: Handlers are held by State for its whole lifetime
+pub trait IntrsHandler: 'static {
+    // Deserializable, so handlers can be configured at runtime.
+    // See `registry`
+    type Config: Default + serde::de::DeserializeOwned;
 
     fn new(config: Self::Config) -> anyhow::Result<Self> 
         where Self: Sized;
 
-    // Builds all the requisite buffers and groups
+    // Builds all the requisite buffers and groups.
+    // Fails if the scene can't be represented by the handler
     fn vars<'a>(
         &self,
         scene: &mut scene::Scene, 
         device: &wgpu::Device,
-    ) -> (IntrsPack<'a>, IntrsStats);
+    ) -> anyhow::Result<(IntrsPack<'a>, IntrsStats)>;
+
+    // Contains the traversal logic, with any stack sizes
+    // filled in as template parameters. Shared building blocks
+    // (see `shaders::library`) are added as imports
+    fn logic(&self) -> shaders::Module;
+
+    // Submits any work that fills the handler's buffers on the GPU.
+    // Called once, right after `vars`
+    fn build(&self, _device: &wgpu::Device, _queue: &wgpu::Queue) {}
+
+    // Whether the handler traverses the scene's instances itself.
+    // Otherwise, instanced scenes are flattened before `vars` is called
+    fn instanced(&self) -> bool { false }
+
+    // Whether the logic also contains an any-hit query for shadow rays,
+    // `fn occluded(r: Ray, t_max: f32, excl: Excl) -> bool`.
+    // Otherwise, one is built on top of the closest-hit `intrs`
+    fn any_hit(&self) -> bool { false }
 
-    // Contains all of the intersection logic
-    fn logic(&self) -> &'static str;
+    // Updates the handler's buffers after the scene's vertices have moved.
+    // Returns false if the handler can't be refit (the default),
+    // in which case the State must be rebuilt
+    fn refit(
+        &mut self,
+        _scene: &scene::Scene,
+        _queue: &wgpu::Queue,
+        _pack: &IntrsPack,
+    ) -> bool { false }
+}
+
+// An object-safe view of IntrsHandler,
+// so State can keep its handler without knowing its type
+pub trait DynIntrsHandler {
+    fn vars<'a>(
+        &self,
+        scene: &mut scene::Scene, 
+        device: &wgpu::Device,
+    ) -> anyhow::Result<(IntrsPack<'a>, IntrsStats)>;
+
+    fn logic(&self) -> shaders::Module;
+
+    fn build(&self, device: &wgpu::Device, queue: &wgpu::Queue);
+
+    fn instanced(&self) -> bool;
+
+    fn any_hit(&self) -> bool;
+
+    fn refit(
+        &mut self,
+        scene: &scene::Scene,
+        queue: &wgpu::Queue,
+        pack: &IntrsPack,
+    ) -> bool;
+}
+
+impl<H: IntrsHandler> DynIntrsHandler for H {
+    fn vars<'a>(
+        &self,
+        scene: &mut scene::Scene, 
+        device: &wgpu::Device,
+    ) -> anyhow::Result<(IntrsPack<'a>, IntrsStats)> {
+        IntrsHandler::vars(self, scene, device)
+    }
+
+    fn logic(&self) -> shaders::Module {
+        IntrsHandler::logic(self)
+    }
+
+    fn build(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        IntrsHandler::build(self, device, queue)
+    }
+
+    fn instanced(&self) -> bool {
+        IntrsHandler::instanced(self)
+    }
+
+    fn any_hit(&self) -> bool {
+        IntrsHandler::any_hit(self)
+    }
+
+    fn refit(
+        &mut self,
+        scene: &scene::Scene,
+        queue: &wgpu::Queue,
+        pack: &IntrsPack,
+    ) -> bool {
+        IntrsHandler::refit(self, scene, queue, pack)
+    }
+}
+
+impl std::fmt::Debug for dyn DynIntrsHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DynIntrsHandler")
+    }
 }
\ No newline at end of file
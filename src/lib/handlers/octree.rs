@@ -0,0 +1,468 @@
+use std::mem;
+
+use once_cell::unsync;
+use wgpu::util::DeviceExt as _;
+
+use crate::{bvh, geom, scene, shaders};
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy)]
+struct OctreeUniform {
+    min: [f32; 3],
+    // The root is a cube, so one edge length suffices
+    size: f32,
+}
+
+// The low 8 bits of `mask` flag which octants have children.
+// Only those children are stored, contiguously and in octant order,
+// starting at `offset`. Leaves have an empty mask,
+// and keep their item count in the remaining bits
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy)]
+struct OctreeNode {
+    mask: u32,
+    offset: u32,
+}
+
+impl OctreeNode {
+    fn leaf(item_idx: usize, item_count: usize) -> Self {
+        Self {
+            mask: (item_count as u32) << 8,
+            offset: item_idx as u32,
+        }
+    }
+}
+
+struct OctreeData {
+    uniform: OctreeUniform,
+    nodes: Vec<OctreeNode>,
+    items: Vec<u32>,
+    depth: usize,
+}
+
+#[derive(serde::Deserialize)]
+#[derive(Default)]
+pub enum OctreeConfig {
+    Runtime { max_depth: usize, leaf_size: usize, },
+    #[default]
+    Default,
+}
+
+pub struct OctreeIntrs {
+    pub max_depth: usize,
+    // Nodes with at most this many primitives aren't subdivided
+    pub leaf_size: usize,
+    data: unsync::OnceCell<OctreeData>,
+}
+
+impl Default for OctreeIntrs {
+    fn default() -> Self {
+        Self {
+            max_depth: 8,
+            leaf_size: 8,
+            data: unsync::OnceCell::new(),
+        }
+    }
+}
+
+struct Builder<'a> {
+    // The bounds of each primitive
+    bounds: Vec<bvh::Bounds>,
+    intrs: &'a OctreeIntrs,
+}
+
+impl<'a> Builder<'a> {
+    // Reserves slots for all present children before descending,
+    // so siblings are always contiguous
+    fn build(
+        &self,
+        data: &mut OctreeData,
+        node: usize,
+        items: Vec<u32>,
+        min: [f32; 3],
+        size: f32,
+        depth: usize,
+    ) {
+        data.depth = data.depth.max(depth);
+
+        let leaf = OctreeNode::leaf(data.items.len(), items.len());
+
+        if items.len() <= self.intrs.leaf_size || depth >= self.intrs.max_depth {
+            data.nodes[node] = leaf;
+            data.items.extend(items);
+
+            return;
+        }
+
+        let half = size * 0.5;
+
+        let octants = (0..8).map(|octant| {
+            let min: [f32; 3] = std::array::from_fn(|axis| {
+                min[axis] + ((octant >> axis) & 1) as f32 * half
+            });
+
+            // Primitives are placed in every octant their bounds overlap
+            let items = items
+                .iter()
+                .copied()
+                .filter(|&item| {
+                    let bounds = self.bounds[item as usize];
+
+                    (0..3).all(|axis| {
+                        bounds.min[axis] <= min[axis] + half && //
+                            bounds.max[axis] >= min[axis]
+                    })
+                }).collect::<Vec<_>>();
+
+            (min, items)
+        }).collect::<Vec<_>>();
+
+        // Subdividing is pointless if no octant has fewer primitives
+        if octants.iter().all(|(_, octant)| octant.len() == items.len()) {
+            data.nodes[node] = leaf;
+            data.items.extend(items);
+
+            return;
+        }
+
+        let mut mask = 0;
+        for (octant, (_, items)) in octants.iter().enumerate() {
+            if !items.is_empty() { mask |= 1 << octant; }
+        }
+
+        let offset = data.nodes.len();
+
+        data.nodes[node] = OctreeNode { mask, offset: offset as u32 };
+        data.nodes.extend((0..mask.count_ones()).map(|_| OctreeNode::leaf(0, 0)));
+
+        let children = octants
+            .into_iter()
+            .filter(|(_, items)| !items.is_empty())
+            .enumerate();
+
+        for (child, (min, items)) in children {
+            self.build(data, offset + child, items, min, half, depth + 1);
+        }
+    }
+}
+
+fn subdivide(intrs: &OctreeIntrs, scene: &scene::Scene) -> OctreeData {
+    let mut data = OctreeData {
+        uniform: OctreeUniform {
+            min: [0.; 3],
+            size: 0.,
+        },
+        nodes: vec![OctreeNode::leaf(0, 0)],
+        items: Vec::new(),
+        depth: 0,
+    };
+
+    let scene::Scene::Active { prims, vertices, .. } = scene else {
+        return data;
+    };
+
+    if prims.is_empty() {
+        return data;
+    }
+
+    let bvh::Bounds { min, max, .. } = bvh::Bounds::new(prims.iter().copied(), vertices);
+
+    // Pad the bounds so flat scenes still have volume
+    let pad = {
+        use geom::V3Ops as _;
+
+        let [dx, dy, dz] = max.sub(min);
+
+        (dx * dx + dy * dy + dz * dz).sqrt() * 0.001 + f32::EPSILON
+    };
+
+    let min = min.map(|v| v - pad);
+    let size = (0..3)
+        .map(|axis| max[axis] + pad - min[axis])
+        .fold(0., f32::max);
+
+    data.uniform = OctreeUniform { min, size };
+
+    let builder = Builder {
+        bounds: prims
+            .iter()
+            .map(|&prim| bvh::Bounds::new(std::iter::once(prim), vertices))
+            .collect(),
+        intrs,
+    };
+
+    builder.build(&mut data, 0, (0..prims.len() as u32).collect(), min, size, 0);
+
+    data
+}
+
+impl super::IntrsHandler for OctreeIntrs {
+    type Config = OctreeConfig;
+
+    fn new(config: Self::Config) -> anyhow::Result<Self> {
+        let intrs = match config {
+            OctreeConfig::Runtime { max_depth, leaf_size } => Self {
+                max_depth,
+                leaf_size,
+                ..Default::default()
+            },
+            OctreeConfig::Default => Self::default(),
+        };
+
+        Ok(intrs)
+    }
+
+    fn vars<'a>(
+        &self,
+        scene: &mut scene::Scene,
+        device: &wgpu::Device,
+    ) -> anyhow::Result<(super::IntrsPack<'a>, super::IntrsStats)> {
+        // Build the octree if we haven't already
+        let OctreeData {
+            uniform,
+            nodes,
+            items, ..
+        } = self.data.get_or_init(|| subdivide(self, scene));
+
+        let octree = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&[*uniform]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            }
+        );
+
+        let octree_nodes = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(nodes),
+                usage: wgpu::BufferUsages::STORAGE,
+            }
+        );
+
+        // Empty buffers can't be bound, so there is always at least one item.
+        // Empty leaves never read it
+        let octree_items = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: if items.is_empty() {
+                    bytemuck::cast_slice(&[0u32])
+                } else {
+                    bytemuck::cast_slice(items)
+                },
+                usage: wgpu::BufferUsages::STORAGE,
+            }
+        );
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            count: None,
+            ty: wgpu::BindingType::Buffer {
+                has_dynamic_offset: false,
+                min_binding_size: None,
+                ty,
+            },
+        };
+
+        let storage = wgpu::BufferBindingType::Storage { read_only: true };
+
+        let layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    entry(0, wgpu::BufferBindingType::Uniform),
+                    entry(1, storage),
+                    entry(2, storage),
+                ]
+            }
+        );
+
+        let group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: octree.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: octree_nodes.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: octree_items.as_entire_binding(),
+                    },
+                ],
+            }
+        );
+
+        let pack = super::IntrsPack {
+            vars: vec![
+                super::IntrsVar {
+                    var_name: "octree",
+                    var_ty: "Octree",
+                    buffer: octree,
+                    buffer_ty: wgpu::BufferBindingType::Uniform,
+                },
+                super::IntrsVar {
+                    var_name: "octree_nodes",
+                    var_ty: "array<OctreeNode>",
+                    buffer: octree_nodes,
+                    buffer_ty: storage,
+                },
+                super::IntrsVar {
+                    var_name: "octree_items",
+                    var_ty: "array<u32>",
+                    buffer: octree_items,
+                    buffer_ty: storage,
+                },
+            ],
+            group,
+            layout,
+        };
+
+        let stats = super::IntrsStats {
+            name: "Octree",
+            size: mem::size_of::<OctreeUniform>() + //
+                mem::size_of::<OctreeNode>() * nodes.len() + //
+                mem::size_of::<u32>() * items.len(),
+            metrics: None,
+            compression: None,
+        };
+
+        Ok((pack, stats))
+    }
+
+    fn logic(&self) -> shaders::Module {
+        // IntrsHandler::logic is always called after IntrsHandler::vars,
+        // so the diverging case is truly unreachable
+        let Some(OctreeData { depth, .. }) = self.data.get() else {
+            unreachable!();
+        };
+
+        // Each subdivided node replaces itself with up to 8 children
+        shaders::Module::new("octree", LOGIC)
+            .import(shaders::library::tri())
+            .import(shaders::library::slab())
+            .import(shaders::library::stack_named("octree", "OctreeEntry", depth * 7 + 1))
+    }
+}
+
+// The intersection logic
+const LOGIC: &str = "\
+    struct Octree {
+        min: vec3<f32>,
+        size: f32,
+    }
+
+    struct OctreeNode {
+        mask: u32,
+        offset: u32,
+    }
+
+    // A node yet to be visited, along with its cell
+    struct OctreeEntry {
+        min: vec3<f32>,
+        size: f32,
+        idx: u32,
+        t_enter: f32,
+    }
+
+    // Returns the distance at which the ray enters the cell,
+    // or INF_POS if it misses
+    fn enter(lo: vec3<f32>, size: f32, ray: Ray, inv: vec3<f32>) -> f32 {
+        let t0 = (lo - EPS - ray.origin) * inv;
+        let t1 = (lo + size + EPS - ray.origin) * inv;
+
+        let t_mins = min(t0, t1);
+        let t_maxs = max(t0, t1);
+
+        let t_enter = max(max(t_mins.x, t_mins.y), max(t_mins.z, 0.0));
+        let t_leave = min(t_maxs.x, min(t_maxs.y, t_maxs.z));
+
+        return select(t_enter, INF_POS, t_enter > t_leave);
+    }
+
+    fn intrs_leaf(node: OctreeNode, ray: Ray, excl: Excl) -> Intrs {
+        var intrs: Intrs = intrs_empty();
+
+        let count = node.mask >> 8u;
+        for(var i: u32 = node.offset; i < node.offset + count; i = i + 1u) {
+            // Offset by one to skip the 'null' primitive
+            let prim: Prim = primitives[octree_items[i] + 1u];
+
+            if(eq(prim, excl.s)) { continue; }
+
+            let temp: Intrs = intrs_tri(ray, prim);
+
+            if(temp.t < intrs.t) {
+                intrs = temp;
+            }
+        }
+
+        return intrs;
+    }
+
+    fn intrs(r: Ray, excl: Excl) -> Intrs {
+        var intrs = intrs_empty();
+
+        // Axis-aligned rays never cross the planes of the other axes
+        let inv = select(vec3<f32>(INF_POS), 1.0 / r.dir, r.dir != vec3<f32>(0.0));
+
+        let t_root = enter(octree.min, octree.size, r, inv);
+        if(t_root == INF_POS) { return intrs; }
+
+        // Flipping these bits orders the octants from near to far
+        let flip = dot(vec3<u32>(r.dir < vec3<f32>(0.0)), vec3<u32>(1u, 2u, 4u));
+
+        var stack_idx = 0u;
+        octree_push(&stack_idx, OctreeEntry(octree.min, octree.size, 0u, t_root));
+
+        while(stack_idx > 0u) {
+            let entry = octree_pop(&stack_idx);
+
+            // Cells beyond the closest hit can't contain a closer one
+            if(entry.t_enter > intrs.t) { continue; }
+
+            let node = octree_nodes[entry.idx];
+            let children = node.mask & 0xFFu;
+
+            if(children == 0u) {
+                let temp = intrs_leaf(node, r, excl);
+
+                if(temp.t < intrs.t) {
+                    intrs = temp;
+                }
+
+                continue;
+            }
+
+            let half = entry.size * 0.5;
+
+            // Pushed from far to near, so the nearest is visited first
+            for(var i: u32 = 0u; i < 8u; i = i + 1u) {
+                let octant = (7u - i) ^ flip;
+
+                if(((children >> octant) & 1u) == 0u) { continue; }
+
+                let upper = vec3<u32>(octant, octant >> 1u, octant >> 2u) & vec3<u32>(1u);
+                let lo = entry.min + vec3<f32>(upper) * half;
+
+                let t_enter = enter(lo, half, r, inv);
+                if(t_enter > intrs.t) { continue; }
+
+                // Children of absent octants aren't stored
+                let idx = node.offset + countOneBits(children & ((1u << octant) - 1u));
+
+                octree_push(&stack_idx, OctreeEntry(lo, half, idx, t_enter));
+            }
+        }
+
+        return intrs;
+    }\
+";
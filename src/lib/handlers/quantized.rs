@@ -0,0 +1,518 @@
+use std::mem;
+
+use once_cell::unsync;
+use wgpu::util::DeviceExt as _;
+
+use crate::{bvh, shaders};
+
+// Each internal node of the binary tree becomes a quantized node,
+// which holds both of its children's bounds.
+// Children are stored as 8-bit offsets from the minimum of their union,
+// in steps of a power of two chosen per axis
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy)]
+struct QuantizedNode {
+    origin: [f32; 3],
+    // The biased exponent of each axis' step, one byte each
+    exps: u32,
+    // The quantized x, y, z of each child, one byte each
+    lo: [u32; 2],
+    hi: [u32; 2],
+    // Index of the child's quantized node, or of its first item
+    links: [u32; 2],
+    // Each child's item count, 16 bits each.
+    // QuantizedNode::INTERNAL if the child is not a leaf
+    counts: u32,
+}
+
+impl QuantizedNode {
+    const INTERNAL: u32 = 0xFFFF;
+
+    // Matches the bias of the f32 exponent,
+    // so the shader can rebuild each step with a bitcast
+    const BIAS: i32 = 127;
+
+    // The largest offset along each axis
+    const STEPS: f32 = 255.;
+}
+
+#[derive(serde::Deserialize)]
+#[derive(Default)]
+pub enum QuantizedBvhConfig {
+    Runtime { eps: f32, strategy: bvh::Strategy, },
+    #[default]
+    Default,
+}
+
+pub struct QuantizedBvhIntrs {
+    pub eps: f32,
+    pub strategy: bvh::Strategy,
+    data: unsync::OnceCell<bvh::BvhData>,
+}
+
+impl Default for QuantizedBvhIntrs {
+    fn default() -> Self {
+        Self {
+            eps: 0.02,
+            strategy: bvh::Strategy::Midpoint,
+            data: unsync::OnceCell::new(),
+        }
+    }
+}
+
+impl QuantizedBvhIntrs {
+    // When reloading scenes, we may want to write into our previous buffers
+    const COPY_USAGES: wgpu::BufferUsages = {
+        wgpu::BufferUsages::COPY_SRC //
+            .union(wgpu::BufferUsages::COPY_DST) //
+    };
+}
+
+fn is_leaf(uniform: &bvh::AabbUniform) -> bool {
+    uniform.fst == 0 && uniform.snd == 0
+}
+
+// The binary node each quantized node was built from,
+// along with the children it holds.
+// A root leaf gets a node of its own, with an empty second slot
+fn parents(data: &bvh::BvhData) -> Vec<(usize, [Option<usize>; 2])> {
+    let bvh::BvhData { uniforms, .. } = data;
+
+    let parents = uniforms
+        .iter()
+        .enumerate()
+        .filter(|(_, uniform)| !is_leaf(uniform))
+        .map(|(idx, &bvh::AabbUniform { fst, snd, .. })| {
+            (idx, [Some(fst as usize), Some(snd as usize)])
+        }).collect::<Vec<_>>();
+
+    if parents.is_empty() {
+        vec![(0, [Some(0), None])]
+    } else {
+        parents
+    }
+}
+
+// The smallest power of two that spans `extent` in QuantizedNode::STEPS.
+// The decoded maximum must reach `max`, so rounding is checked in f32
+fn exponent(origin: f32, max: f32) -> i32 {
+    let mut exp = ((max - origin) / QuantizedNode::STEPS)
+        .log2()
+        .ceil()
+        .clamp(1. - QuantizedNode::BIAS as f32, QuantizedNode::BIAS as f32) as i32;
+
+    while exp < QuantizedNode::BIAS && decode(origin, exp, u8::MAX) < max {
+        exp += 1;
+    }
+
+    exp
+}
+
+// Performs the same arithmetic as the shader.
+// The product is exact, so only the sum is rounded
+fn decode(origin: f32, exp: i32, q: u8) -> f32 {
+    let step = f32::from_bits(((exp + QuantizedNode::BIAS) as u32) << 23);
+
+    origin + q as f32 * step
+}
+
+// Rounds outwards, then corrects for any rounding in `decode`
+fn quantize(origin: f32, exp: i32, min: f32, max: f32) -> (u8, u8) {
+    let step = f32::from_bits(((exp + QuantizedNode::BIAS) as u32) << 23);
+
+    let mut lo = ((min - origin) / step).floor().clamp(0., QuantizedNode::STEPS) as u8;
+    while lo > 0 && decode(origin, exp, lo) > min {
+        lo -= 1;
+    }
+
+    let mut hi = ((max - origin) / step).ceil().clamp(0., QuantizedNode::STEPS) as u8;
+    while hi < u8::MAX && decode(origin, exp, hi) < max {
+        hi += 1;
+    }
+
+    (lo, hi)
+}
+
+// Unpacks a child's bounds from its parent
+fn decode_child(node: &QuantizedNode, child: usize) -> bvh::Bounds {
+    let exps = node.exps.to_le_bytes();
+    let lo = node.lo[child].to_le_bytes();
+    let hi = node.hi[child].to_le_bytes();
+
+    let exp = |axis: usize| exps[axis] as i32 - QuantizedNode::BIAS;
+
+    let min = [0, 1, 2].map(|axis| decode(node.origin[axis], exp(axis), lo[axis]));
+    let max = [0, 1, 2].map(|axis| decode(node.origin[axis], exp(axis), hi[axis]));
+
+    bvh::Bounds::from_points([min, max].into_iter())
+}
+
+// Converts the binary tree into its GPU representation
+fn compress(data: &bvh::BvhData) -> anyhow::Result<Vec<QuantizedNode>> {
+    let bvh::BvhData { uniforms, .. } = data;
+
+    let parents = parents(data);
+
+    // Maps each internal binary node to its quantized node
+    let mut links = vec![0; uniforms.len()];
+    for (node, &(idx, _)) in parents.iter().enumerate() {
+        links[idx] = node as u32;
+    }
+
+    let mut nodes = Vec::with_capacity(parents.len());
+    for (_, children) in parents {
+        // Children aren't always enclosed by their parent (i.e. midpoint splits),
+        // so they are quantized against their union instead
+        let bvh::Bounds { min: origin, max, .. } = children
+            .iter()
+            .flatten()
+            .map(|&child| uniforms[child].bounds)
+            .reduce(bvh::Bounds::union)
+            .unwrap();
+
+        let exps = [0, 1, 2].map(|axis| exponent(origin[axis], max[axis]));
+
+        let mut node = QuantizedNode {
+            origin,
+            exps: u32::from_le_bytes([
+                (exps[0] + QuantizedNode::BIAS) as u8,
+                (exps[1] + QuantizedNode::BIAS) as u8,
+                (exps[2] + QuantizedNode::BIAS) as u8,
+                0,
+            ]),
+            lo: [0; 2],
+            hi: [0; 2],
+            links: [0; 2],
+            counts: 0,
+        };
+
+        for (slot, child) in children.into_iter().enumerate() {
+            // Empty slots hold a leaf without any items
+            let Some(child) = child else { continue; };
+
+            let uniform = uniforms[child];
+
+            let bvh::Bounds { min, max, .. } = uniform.bounds;
+
+            let mut lo = [0; 4];
+            let mut hi = [0; 4];
+            for axis in 0..3 {
+                (lo[axis], hi[axis]) = quantize(
+                    origin[axis],
+                    exps[axis],
+                    min[axis],
+                    max[axis],
+                );
+            }
+
+            node.lo[slot] = u32::from_le_bytes(lo);
+            node.hi[slot] = u32::from_le_bytes(hi);
+
+            let (link, count) = if is_leaf(&uniform) {
+                if uniform.item_count >= QuantizedNode::INTERNAL {
+                    anyhow::bail!("\
+                        Quantized BVH leaves hold at most {} items\
+                    ", QuantizedNode::INTERNAL - 1);
+                }
+
+                (uniform.item_idx, uniform.item_count)
+            } else {
+                (links[child], QuantizedNode::INTERNAL)
+            };
+
+            node.links[slot] = link;
+            node.counts |= count << (slot * 16);
+        }
+
+        nodes.push(node);
+    }
+
+    Ok(nodes)
+}
+
+// Checks that every decoded child encloses its original bounds.
+// Returns the relative growth in surface area caused by quantization
+fn verify(data: &bvh::BvhData, nodes: &[QuantizedNode]) -> anyhow::Result<f32> {
+    let bvh::BvhData { uniforms, .. } = data;
+
+    let mut area_exact = 0.;
+    let mut area_decoded = 0.;
+
+    for ((idx, children), node) in parents(data).into_iter().zip(nodes.iter()) {
+        for (slot, child) in children.into_iter().enumerate() {
+            let Some(child) = child else { continue; };
+
+            let exact = uniforms[child].bounds;
+
+            if exact.is_empty() { continue; }
+
+            let decoded = decode_child(node, slot);
+
+            if !decoded.contains(exact.min) || !decoded.contains(exact.max) {
+                anyhow::bail!("\
+                    The quantized bounds of node {child} (a child of node {idx}) \
+                    don't enclose its original bounds\
+                ");
+            }
+
+            area_exact += exact.area();
+            area_decoded += decoded.area();
+        }
+    }
+
+    Ok(if area_exact > 0. { area_decoded / area_exact - 1. } else { 0. })
+}
+
+impl super::IntrsHandler for QuantizedBvhIntrs {
+    type Config = QuantizedBvhConfig;
+
+    fn new(config: Self::Config) -> anyhow::Result<Self> {
+        let intrs = match config {
+            QuantizedBvhConfig::Runtime { eps, strategy } => Self {
+                eps,
+                strategy,
+                ..Default::default()
+            },
+            QuantizedBvhConfig::Default => Self::default(),
+        };
+
+        Ok(intrs)
+    }
+
+    fn vars<'a>(
+        &self,
+        scene: &mut crate::scene::Scene,
+        device: &wgpu::Device,
+    ) -> anyhow::Result<(super::IntrsPack<'a>, super::IntrsStats)> {
+        // Build the BVH if we haven't already
+        let data = self.data.get_or_init(|| {
+            bvh::BvhData::from_scene(self.eps, scene, 2, self.strategy)
+        });
+
+        let nodes = compress(data)?;
+
+        // The quantized bounds must never cull a primitive
+        let slack = verify(data, &nodes)?;
+
+        let quantized_uniforms = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&nodes),
+                usage: wgpu::BufferUsages::STORAGE | Self::COPY_USAGES,
+            }
+        );
+
+        // Leaves refer to contiguous ranges of the reordered primitives
+        scene.reorder(&data.indices);
+
+        let layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        count: None,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                            ty: wgpu::BufferBindingType::Storage {
+                                read_only: true
+                            },
+                        },
+                    },
+                ]
+            }
+        );
+
+        let group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: quantized_uniforms.as_entire_binding(),
+                    },
+                ],
+            }
+        );
+
+        let pack = super::IntrsPack {
+            vars: vec![
+                super::IntrsVar {
+                    var_name: "quantized_uniforms",
+                    var_ty: "array<QuantizedNode>",
+                    buffer: quantized_uniforms,
+                    buffer_ty: wgpu::BufferBindingType::Storage {
+                        read_only: true,
+                    },
+                },
+            ],
+            group,
+            layout,
+        };
+
+        let stats = super::IntrsStats {
+            name: "Quantized BVH",
+            size: mem::size_of::<QuantizedNode>() * nodes.len(),
+            metrics: Some(data.metrics()),
+            compression: Some(super::Compression {
+                uncompressed: mem::size_of::<bvh::AabbUniform>() * data.uniforms.len(),
+                slack,
+            }),
+        };
+
+        Ok((pack, stats))
+    }
+
+    fn logic(&self) -> shaders::Module {
+        // IntrsHandler::logic is always called after IntrsHandler::vars,
+        // so the diverging case is truly unreachable
+        let Some(data) = self.data.get() else {
+            unreachable!();
+        };
+
+        // Each level leaves at most one sibling on the stack
+        let size = data.metrics().depth_max + 1;
+
+        shaders::Module::new("bvh-quantized", LOGIC)
+            .import(shaders::library::tri())
+            .import(shaders::library::slab())
+            .import(shaders::library::stack(size))
+    }
+
+    fn refit(
+        &mut self,
+        scene: &crate::scene::Scene,
+        queue: &wgpu::Queue,
+        pack: &super::IntrsPack,
+    ) -> bool {
+        let (
+            Some(data),
+            crate::scene::Scene::Active { prims, vertices, .. },
+        ) = (self.data.get_mut(), scene) else { return false; };
+
+        // `vars` reordered the scene's primitives to match `indices`
+        data.refit_ordered(prims, vertices);
+
+        // Every node is requantized against its parent's new bounds
+        let Ok(nodes) = compress(data) else { return false; };
+
+        if verify(data, &nodes).is_err() { return false; }
+
+        let Some(super::IntrsVar { buffer, .. }) = pack.vars
+            .iter()
+            .find(|var| var.var_name == "quantized_uniforms") else { return false; };
+
+        queue.write_buffer(buffer, 0, bytemuck::cast_slice(&nodes));
+
+        true
+    }
+}
+
+// The intersection logic
+const LOGIC: &str = "\
+    struct QuantizedNode {
+        origin: array<f32, 3>,
+        exps: u32,
+        lo: array<u32, 2>,
+        hi: array<u32, 2>,
+        links: array<u32, 2>,
+        counts: u32,
+    }
+
+    const INTERNAL: u32 = 0xFFFFu;
+
+    // Matches decode in quantized.rs.
+    // Each step is a power of two, so the product is exact
+    fn decode(node: QuantizedNode, q: u32) -> vec3<f32> {
+        let origin = vec3<f32>(node.origin[0], node.origin[1], node.origin[2]);
+
+        let step = vec3<f32>(
+            bitcast<f32>((node.exps & 0xFFu) << 23u),
+            bitcast<f32>(((node.exps >> 8u) & 0xFFu) << 23u),
+            bitcast<f32>(((node.exps >> 16u) & 0xFFu) << 23u),
+        );
+
+        let offset = vec3<f32>(
+            f32(q & 0xFFu),
+            f32((q >> 8u) & 0xFFu),
+            f32((q >> 16u) & 0xFFu),
+        );
+
+        return origin + offset * step;
+    }
+
+    // The distance at which the ray enters a child's box,
+    // or INF_POS if the box is missed entirely
+    fn entry(node: QuantizedNode, lo: u32, hi: u32, ray: Ray) -> f32 {
+        return box_entry(decode(node, lo), decode(node, hi), ray);
+    }
+
+    fn intrs_leaf(link: u32, count: u32, ray: Ray, excl: Excl) -> Intrs {
+        var intrs: Intrs = intrs_empty();
+
+        for(var i: u32 = link; i < (link + count); i = i + 1u) {
+            // Offset by one to skip the 'null' primitive
+            let prim: Prim = primitives[i + 1u];
+
+            if(eq(prim, excl.s)) { continue; }
+
+            let temp: Intrs = intrs_tri(ray, prim);
+
+            if(temp.t < intrs.t) {
+                intrs = temp;
+            }
+        }
+
+        return intrs;
+    }
+
+    fn intrs(r: Ray, excl: Excl) -> Intrs {
+        var stack_idx = 0u;
+        push(&stack_idx, 0u);
+
+        var intrs = intrs_empty();
+
+        while(stack_idx > 0u) {
+            // Declared as a var, so its arrays can be indexed dynamically
+            var node = quantized_uniforms[pop(&stack_idx)];
+
+            var t = array<f32, 2>(
+                entry(node, node.lo[0], node.hi[0], r), 
+                entry(node, node.lo[1], node.hi[1], r),
+            );
+
+            // Leaves are tested immediately, nearer child first
+            var first = 0u;
+            if(t[1] < t[0]) { first = 1u; }
+
+            for(var i = 0u; i < 2u; i = i + 1u) {
+                let child = first ^ i;
+                let count = (node.counts >> (child * 16u)) & 0xFFFFu;
+
+                if(count == INTERNAL || t[child] >= intrs.t) { continue; }
+
+                let temp = intrs_leaf(node.links[child], count, r, excl);
+
+                if(temp.t < intrs.t) {
+                    intrs = temp;
+                }
+            }
+
+            // The nearer internal child is pushed last, so it's visited first
+            for(var i = 0u; i < 2u; i = i + 1u) {
+                let child = first ^ (1u - i);
+                let count = (node.counts >> (child * 16u)) & 0xFFFFu;
+
+                if(count != INTERNAL || t[child] >= intrs.t) { continue; }
+
+                push(&stack_idx, node.links[child]);
+            }
+        }
+
+        return intrs;
+    }\
+";
@@ -0,0 +1,99 @@
+// Every intersection handler by name,
+// so one can be chosen at runtime rather than as a type parameter
+
+use super::IntrsHandler;
+
+// Builds a handler from its config, given as JSON.
+// A null config stands for the handler's default
+type Constructor = fn(
+    serde_json::Value,
+) -> anyhow::Result<Box<dyn super::DynIntrsHandler>>;
+
+const HANDLERS: &[(&str, Constructor)] = &[
+    ("naive", construct::<super::BasicIntrs>),
+    ("bvh", construct::<super::BvhIntrs>),
+    ("bvh-rf", construct::<super::RfBvhIntrs>),
+    ("bvh-wide", construct::<super::WideBvhIntrs>),
+    ("bvh-quantized", construct::<super::QuantizedBvhIntrs>),
+    ("bvh-threaded", construct::<super::ThreadedBvhIntrs>),
+    ("grid", construct::<super::GridIntrs>),
+    ("kd", construct::<super::KdTreeIntrs>),
+    ("octree", construct::<super::OctreeIntrs>),
+    ("tlas", construct::<super::TlasIntrs>),
+    ("blank", construct::<super::BlankIntrs>),
+];
+
+fn construct<H: IntrsHandler>(
+    config: serde_json::Value,
+) -> anyhow::Result<Box<dyn super::DynIntrsHandler>> {
+    let config = match config {
+        serde_json::Value::Null => H::Config::default(),
+        config => serde_json::from_value(config)?,
+    };
+
+    Ok(Box::new(H::new(config)?))
+}
+
+// The names of all registered handlers
+pub fn names() -> impl Iterator<Item = &'static str> {
+    HANDLERS.iter().map(|(name, _)| *name)
+}
+
+pub fn build(
+    name: &str,
+    config: serde_json::Value,
+) -> anyhow::Result<Box<dyn super::DynIntrsHandler>> {
+    let Some((_, constructor)) = HANDLERS.iter()
+        .find(|(handler, _)| *handler == name) else {
+
+        anyhow::bail!(
+            "Unknown intersection handler '{name}', expected one of: {}",
+            names().collect::<Vec<_>>().join(", "),
+        );
+    };
+
+    constructor(config)
+        .map_err(|e| e.context(format!("Invalid config for handler '{name}'")))
+}
+
+// Names a handler, along with its config.
+// Either `"bvh"` or `{ "name": "bvh", "config": { ... } }`,
+// where the config is the handler's Config in serde's default representation
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum HandlerDesc {
+    Name(String),
+    Configured {
+        name: String,
+        #[serde(default)]
+        config: serde_json::Value,
+    },
+}
+
+impl HandlerDesc {
+    // Accepts either JSON or a bare name
+    pub fn parse(desc: &str) -> anyhow::Result<Self> {
+        match serde_json::from_str(desc) {
+            Ok(desc) => Ok(desc),
+            Err(_) if !desc.trim_start().starts_with(['{', '"']) => {
+                Ok(Self::Name(desc.trim().to_owned()))
+            },
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Name(name) | Self::Configured { name, .. } => name,
+        }
+    }
+
+    pub fn build(&self) -> anyhow::Result<Box<dyn super::DynIntrsHandler>> {
+        match self {
+            Self::Name(name) => build(name, serde_json::Value::Null),
+            Self::Configured { name, config } => build(name, config.clone()),
+        }
+    }
+}
@@ -1,10 +1,13 @@
-use std::mem;
-
 use once_cell::unsync;
 use wgpu::util::DeviceExt as _;
 
-use crate::bvh;
+use crate::{bvh, shaders};
 
+// Bounds are packed as f16 pairs.
+// Internal nodes store the link to their second child 
+// (the first always follows its parent).
+// Leaves pack their item count into bits 24..31 
+// and the index of their first item into bits 0..24
 #[repr(C)]
 #[derive(bytemuck::Pod, bytemuck::Zeroable)]
 #[derive(Clone, Copy)]
@@ -13,25 +16,82 @@ struct RfAabbUniform {
     tag: u32,
 }
 
+// The fallback for scenes that exceed the compact encoding.
+// Leaves keep the whole tag for their first item's index
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy)]
+struct RfAabbUniformWide {
+    bounds: [u32; 3],
+    tag: u32,
+    count: u32,
+    // Matches the alignment of the WGSL struct
+    _padding: [u32; 3],
+}
+
+// How the nodes of the RF-BVH are packed
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(serde::Deserialize)]
+#[derive(Default)]
+#[derive(PartialEq, Eq)]
+pub enum RfEncoding {
+    // Compact, unless the scene exceeds its limits
+    #[default]
+    Auto,
+    // 16 bytes per node
+    Compact,
+    // 32 bytes per node
+    Wide,
+}
+
+impl RfEncoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(Self::Auto),
+            "compact" => Some(Self::Compact),
+            "wide" => Some(Self::Wide),
+            _ => None,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[derive(Default)]
 pub enum RfBvhConfig {
+    Bytes(Vec<u8>, super::Traversal, RfEncoding),
     Eps(f32),
+    Runtime { 
+        eps: f32, 
+        strategy: bvh::Strategy, 
+        traversal: super::Traversal, 
+        encoding: RfEncoding, 
+    },
+    #[default]
     Default,
 }
 
-impl Default for RfBvhConfig {
-    fn default() -> Self { Self::Default }
-}
-
 pub struct RfBvhIntrs {
-    eps: f32,
+    pub eps: f32,
+    pub strategy: bvh::Strategy,
+    pub traversal: super::Traversal,
+    pub encoding: RfEncoding,
+    data: unsync::OnceCell<bvh::BvhData>,
     nodes: unsync::OnceCell<usize>,
+    // The encoding that was actually used, never RfEncoding::Auto
+    encoded: unsync::OnceCell<RfEncoding>,
 }
 
 impl Default for RfBvhIntrs {
     fn default() -> Self {
         Self { 
             eps: 0.02, 
+            strategy: bvh::Strategy::Midpoint,
+            traversal: super::Traversal::Unordered,
+            encoding: RfEncoding::Auto,
+            data: unsync::OnceCell::new(),
             nodes: unsync::OnceCell::new(),
+            encoded: unsync::OnceCell::new(),
         }
     }
 }
@@ -42,6 +102,13 @@ impl RfBvhIntrs {
         wgpu::BufferUsages::COPY_SRC //
             .union(wgpu::BufferUsages::COPY_DST) //
     };
+
+    // Limits of the compact encoding
+    pub const COMPACT_ITEMS: usize = 1 << 24;
+    pub const COMPACT_ITEM_COUNT: usize = (1 << 7) - 1;
+
+    // Links and item indices never use the leaf flag
+    pub const LINKS: usize = 1 << 31;
 }
 
 impl super::IntrsHandler for RfBvhIntrs {
@@ -51,7 +118,26 @@ impl super::IntrsHandler for RfBvhIntrs {
         where Self: Sized {
 
         Ok(match config {
+            RfBvhConfig::Bytes(bytes, traversal, encoding) => {
+                let data = serde_json::from_slice::<bvh::BvhData>(&bytes)?;
+
+                let nodes = data.uniforms.len();
+
+                Self {
+                    traversal,
+                    encoding,
+                    data: unsync::OnceCell::with_value(data),
+                    nodes: unsync::OnceCell::with_value(nodes),
+                    ..Default::default()
+                }
+            },
             RfBvhConfig::Eps(eps) => Self { eps, ..Default::default() },
+            RfBvhConfig::Runtime { eps, strategy, traversal, encoding } => Self { 
+                eps, 
+                strategy, 
+                traversal, 
+                encoding, ..Default::default() 
+            },
             RfBvhConfig::Default => Self::default(),
         })
     }
@@ -60,111 +146,30 @@ impl super::IntrsHandler for RfBvhIntrs {
         &self,
         scene: &mut crate::scene::Scene, 
         device: &wgpu::Device,
-    ) -> (super::IntrsPack<'a>, super::IntrsStats) {
-        let aabb = bvh::Aabb::from_scene(self.eps, scene, 4);
-
-        let data = bvh::BvhData::new(&aabb);
-
-        let bvh::BvhData {
-            uniforms,
-            indices, ..
-        } = data;
+    ) -> anyhow::Result<(super::IntrsPack<'a>, super::IntrsStats)> {
+        // Build the BVH if we haven't already
+        let data = self.data.get_or_init(|| {
+            bvh::BvhData::from_scene(self.eps, scene, 4, self.strategy)
+        });
 
         // Set the node count if we haven't already
-        self.nodes.get_or_init(|| uniforms.len());
+        self.nodes.get_or_init(|| data.uniforms.len());
 
-        let mut uniforms_rf = Vec::with_capacity(uniforms.len());
+        let (encoding, uniforms_rf) = encode(data, self.encoding)?;
 
-        for uniform in uniforms.iter() {
-            let bvh::AabbUniform {
-                fst,
-                snd,
-                item_idx,
-                item_count,
-                bounds: bvh::Bounds { min, max, .. },
-            } = *uniform;
-
-            fn pack(a: f32, b: f32) -> u32 {
-                let a = half::f16::from_f32(a);
-                let b = half::f16::from_f32(b);
-
-                bytemuck::cast_slice::<half::f16, u32>(&[a, b])[0]
-            }
-
-            // If it is a leaf
-            if fst == 0 && snd == 0 {
-                uniforms_rf.push(RfAabbUniform {
-                    bounds: [
-                        pack(min[0], max[0]),
-                        pack(min[1], max[1]),
-                        pack(min[2], max[2]),
-                    ],
-                    tag: 1 << 31,
-                });
-
-                let item_idx = item_idx as usize;
-                let item_count = item_count as usize;
-
-                let mut items = indices[item_idx..(item_idx + item_count)]
-                    .iter()
-                    .map(|&idx| idx as u16)
-                    .collect::<Vec<_>>();
-
-                items.extend(std::iter::repeat(0).take(8 - items.len()));
-
-                uniforms_rf.push({
-                    bytemuck::cast_slice::<u16, RfAabbUniform>(&items)[0]
-                });
-            } else { // Internal node
-                uniforms_rf.push(RfAabbUniform {
-                    bounds: [
-                        pack(min[0], max[0]),
-                        pack(min[1], max[1]),
-                        pack(min[2], max[2]),
-                    ],
-                    tag: ((fst) << 16) | ((snd) & 0xFFFF),
-                });
-            };
-        }
-
-        for RfAabbUniform { tag, .. } in uniforms_rf.iter_mut() {
-            if (*tag >> 31) & 1 == 0 {
-                let [
-                    mut fst, 
-                    mut snd
-                ] = bytemuck::cast::<u32, [u16; 2]>(*tag);
-
-                let mut idx = 0;
-                let mut offset = 0;
-                while idx < fst as usize {
-                    if uniforms[idx].item_count > 0 {
-                        offset += 1;
-                    }
-    
-                    idx += 1;
-                } fst += offset;
-
-                idx = 0; offset = 0;
-                while idx < snd as usize {
-                    if uniforms[idx].item_count > 0 {
-                        offset += 1;
-                    }
-    
-                    idx += 1;
-                } snd += offset;
-
-                *tag = bytemuck::cast::<[u16; 2], u32>([fst, snd]);
-            }
-        }
+        self.encoded.get_or_init(|| encoding);
 
         let aabb_uniforms = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: None,
-                contents: bytemuck::cast_slice(&uniforms_rf),
+                contents: &uniforms_rf,
                 usage: wgpu::BufferUsages::STORAGE | Self::COPY_USAGES,
             }
         );
 
+        // Leaves refer to contiguous ranges of the reordered primitives
+        scene.reorder(&data.indices);
+
         let layout = device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
                 label: None,
@@ -214,35 +219,211 @@ impl super::IntrsHandler for RfBvhIntrs {
         };
 
         let stats = super::IntrsStats {
-            name: "RF-BVH",
-            size: mem::size_of::<RfAabbUniform>() * uniforms_rf.len(),
+            name: match (self.traversal, encoding) {
+                (super::Traversal::Unordered, RfEncoding::Wide) => "RF-BVH (Wide)",
+                (super::Traversal::Ordered, RfEncoding::Wide) => "RF-BVH (Wide, Ordered)",
+                (super::Traversal::Unordered, _) => "RF-BVH",
+                (super::Traversal::Ordered, _) => "RF-BVH (Ordered)",
+            },
+            size: uniforms_rf.len(),
+            metrics: Some(data.metrics()),
+            compression: None,
         };
 
-        (pack, stats)
+        Ok((pack, stats))
     }
 
-    fn logic(&self) -> &'static str {
-        // In the shader code below, this line is incomplete.
-        // It needs to be given a type
-        const DECL: &str = "var<private> aabb_stack;";
-
+    fn logic(&self) -> shaders::Module {
         // IntrsHandler::logic is always called after IntrsHandler::vars,
         // so the diverging case is truly unreachable
-        let Some(nodes) = self.nodes.get().copied() else { 
+        let (
+            Some(data), 
+            Some(encoding),
+        ) = (self.data.get(), self.encoded.get()) else { 
             unreachable!();
         };
 
-        // Perform the replacement
-        let mut logic = String::from(LOGIC); logic.insert_str(
-            LOGIC.find(DECL).unwrap() + DECL.len() - 1, 
-            format!(": array<u32, {nodes}>",).as_str()
-        );
-        
-        // We have to return a static string, so we leak it
-        Box::leak(logic.into_boxed_str())
+        // Each level leaves at most one sibling on the stack
+        let size = data.metrics().depth_max + 1;
+
+        // The node layout is chosen by the encoding
+        let layout = match encoding {
+            RfEncoding::Wide => LOGIC_WIDE,
+            _ => LOGIC_COMPACT,
+        };
+
+        shaders::Module::new("bvh-rf", format!("{layout}{LOGIC}"))
+            .param("ORDERED", self.traversal == super::Traversal::Ordered)
+            .import(shaders::library::tri())
+            .import(shaders::library::slab())
+            .import(shaders::library::stack(size))
+            .import(shaders::library::stack_named("ordered", "OrderedEntry", size))
+    }
+
+    fn any_hit(&self) -> bool { true }
+
+    fn refit(
+        &mut self,
+        scene: &crate::scene::Scene,
+        queue: &wgpu::Queue,
+        pack: &super::IntrsPack,
+    ) -> bool {
+        let (
+            Some(data), 
+            Some(&encoding),
+            crate::scene::Scene::Active { prims, vertices, .. },
+        ) = (self.data.get_mut(), self.encoded.get(), scene) else { return false; };
+
+        // `vars` reordered the scene's primitives to match `indices`
+        data.refit_ordered(prims, vertices);
+
+        let Some(super::IntrsVar { buffer, .. }) = pack.vars
+            .iter()
+            .find(|var| var.var_name == "aabb_uniforms") else { return false; };
+
+        // The topology is unchanged, so the encoding still fits
+        let Ok((_, uniforms_rf)) = encode(data, encoding) else { return false; };
+
+        queue.write_buffer(buffer, 0, &uniforms_rf);
+
+        true
     }
 }
 
+fn pack(a: f32, b: f32) -> u32 {
+    let a = half::f16::from_f32(a);
+    let b = half::f16::from_f32(b);
+
+    bytemuck::cast_slice::<half::f16, u32>(&[a, b])[0]
+}
+
+fn is_leaf(uniform: &bvh::AabbUniform) -> bool {
+    uniform.fst == 0 && uniform.snd == 0
+}
+
+// Packs the BVH into its reduced-footprint layout
+fn compress(data: &bvh::BvhData) -> anyhow::Result<Vec<RfAabbUniform>> {
+    let mut uniforms_rf = Vec::with_capacity(data.uniforms.len());
+
+    for (idx, uniform) in data.uniforms.iter().enumerate() {
+        let bvh::AabbUniform {
+            fst,
+            snd,
+            item_idx,
+            item_count,
+            bounds: bvh::Bounds { min, max, .. },
+        } = *uniform;
+
+        let tag = if is_leaf(uniform) {
+            let (item_idx, item_count) = (item_idx as usize, item_count as usize);
+
+            if item_count > RfBvhIntrs::COMPACT_ITEM_COUNT {
+                anyhow::bail!("\
+                    RF-BVH leaf holds {item_count} items, but the compact encoding \
+                    allows at most {}. Use the wide encoding instead\
+                ", RfBvhIntrs::COMPACT_ITEM_COUNT);
+            }
+
+            if item_idx + item_count > RfBvhIntrs::COMPACT_ITEMS {
+                anyhow::bail!("\
+                    RF-BVH references {} primitives, but the compact encoding \
+                    allows at most {}. Use the wide encoding instead\
+                ", item_idx + item_count, RfBvhIntrs::COMPACT_ITEMS);
+            }
+
+            (1 << 31) | ((item_count as u32) << 24) | item_idx as u32
+        } else {
+            link(idx, fst, snd)?
+        };
+
+        uniforms_rf.push(RfAabbUniform {
+            bounds: [
+                pack(min[0], max[0]),
+                pack(min[1], max[1]),
+                pack(min[2], max[2]),
+            ],
+            tag,
+        });
+    }
+
+    Ok(uniforms_rf)
+}
+
+// Packs the BVH with full-width item indices and counts
+fn compress_wide(data: &bvh::BvhData) -> anyhow::Result<Vec<RfAabbUniformWide>> {
+    let mut uniforms_rf = Vec::with_capacity(data.uniforms.len());
+
+    for (idx, uniform) in data.uniforms.iter().enumerate() {
+        let bvh::AabbUniform {
+            fst,
+            snd,
+            item_idx,
+            item_count,
+            bounds: bvh::Bounds { min, max, .. },
+        } = *uniform;
+
+        let (tag, count) = if is_leaf(uniform) {
+            if (item_idx as usize) >= RfBvhIntrs::LINKS {
+                anyhow::bail!("\
+                    RF-BVH references {item_idx} primitives, \
+                    which exceeds the limits of the wide encoding\
+                ");
+            }
+
+            ((1 << 31) | item_idx, item_count)
+        } else {
+            (link(idx, fst, snd)?, 0)
+        };
+
+        uniforms_rf.push(RfAabbUniformWide {
+            bounds: [
+                pack(min[0], max[0]),
+                pack(min[1], max[1]),
+                pack(min[2], max[2]),
+            ],
+            tag,
+            count,
+            _padding: [0; 3],
+        });
+    }
+
+    Ok(uniforms_rf)
+}
+
+// Only the second child is stored, 
+// so the tree must be laid out in pre-order
+fn link(idx: usize, fst: u32, snd: u32) -> anyhow::Result<u32> {
+    if fst as usize != idx + 1 {
+        anyhow::bail!("RF-BVH nodes must be laid out in pre-order");
+    }
+
+    if snd as usize >= RfBvhIntrs::LINKS {
+        anyhow::bail!("RF-BVH has {snd} nodes, which exceeds the limits of its encoding");
+    }
+
+    Ok(snd)
+}
+
+// Encodes the BVH, resolving RfEncoding::Auto.
+// Returns the encoding that was used and the packed nodes
+fn encode(
+    data: &bvh::BvhData, 
+    encoding: RfEncoding,
+) -> anyhow::Result<(RfEncoding, Vec<u8>)> {
+    fn bytes<T: bytemuck::Pod>(uniforms: Vec<T>) -> Vec<u8> {
+        bytemuck::cast_slice(&uniforms).to_vec()
+    }
+
+    Ok(match encoding {
+        RfEncoding::Compact => (RfEncoding::Compact, bytes(compress(data)?)),
+        RfEncoding::Wide => (RfEncoding::Wide, bytes(compress_wide(data)?)),
+        RfEncoding::Auto => match compress(data) {
+            Ok(uniforms_rf) => (RfEncoding::Compact, bytes(uniforms_rf)),
+            Err(_) => (RfEncoding::Wide, bytes(compress_wide(data)?)),
+        },
+    })
+}
+
 #[allow(dead_code)]
 fn debug_aabb(data: &bvh::BvhData) {
     fn debug_aabb_inner(data: &bvh::BvhData, curr: usize, indent: usize) {
@@ -302,8 +483,6 @@ fn debug_rf_aabb(bbs: &[RfAabbUniform]) {
         let [z_min, z_max] = bytemuck::cast::<u32, [half::f16; 2]>(bounds[2]);
     
         if (tag >> 31) & 1 == 0 {
-            let [fst, snd] = bytemuck::cast::<u32, [u16; 2]>(tag);
-    
             println!(
                 "{} Node [{:.3}, {:.3}, {:.3}] [{:.3}, {:.3}, {:.3}]", 
                 " ".repeat(indent), 
@@ -311,31 +490,18 @@ fn debug_rf_aabb(bbs: &[RfAabbUniform]) {
                 x_max, y_max, z_max,
             );
     
-            debug_rf_aabb_inner(bbs, fst as usize, indent + 1);
-            debug_rf_aabb_inner(bbs, snd as usize, indent + 1);
+            debug_rf_aabb_inner(bbs, curr + 1, indent + 1);
+            debug_rf_aabb_inner(bbs, tag as usize, indent + 1);
         } else {
-            let RfAabbUniform { 
-                bounds, 
-                tag, ..
-            } = bbs[curr + 1];
-    
-            let mut indices = vec![];
-            indices.extend_from_slice(&bytemuck::cast::<u32, [u16; 2]>(bounds[0]));
-            indices.extend_from_slice(&bytemuck::cast::<u32, [u16; 2]>(bounds[1]));
-            indices.extend_from_slice(&bytemuck::cast::<u32, [u16; 2]>(bounds[2]));
-            indices.extend_from_slice(&bytemuck::cast::<u32, [u16; 2]>(tag));
-    
-            let indices = indices
-                .into_iter()
-                .filter(|&x| x != 0)
-                .collect::<Vec<_>>();
+            let item_idx = tag & 0xFFFFFF;
+            let item_count = (tag >> 24) & 0x7F;
     
             println!(
                 "{} Leaf [{:.3}, {:.3}, {:.3}] [{:.3}, {:.3}, {:.3}]: {:?}", 
                 " ".repeat(indent), 
                 x_min, y_min, z_min, 
                 x_max, y_max, z_max, 
-                indices,
+                item_idx..(item_idx + item_count),
             );
         }
     }
@@ -343,174 +509,201 @@ fn debug_rf_aabb(bbs: &[RfAabbUniform]) {
     debug_rf_aabb_inner(bbs, 0, 0);
 }
 
-const LOGIC: &str = "\
-    struct Bounds {
-        min: vec3<f32>,
-        max: vec3<f32>,
-    }
-
+// Compact nodes: 16 bytes
+const LOGIC_COMPACT: &str = "\
     struct Aabb {
         bounds: vec3<u32>,
         tag: u32
     }
 
-    fn intrs_tri(r: Ray, s: Prim) -> Intrs {
-        let e1: vec3<f32> = vertices[s.b].pos - vertices[s.a].pos;
-        let e2: vec3<f32> = vertices[s.c].pos - vertices[s.a].pos;
+    fn is_leaf(bb: Aabb) -> bool { return (bb.tag >> 31u) == 1u; }
+    fn snd(bb: Aabb) -> u32 { return bb.tag & 0x7FFFFFFFu; }
+    fn item_idx(bb: Aabb) -> u32 { return bb.tag & 0xFFFFFFu; }
+    fn item_count(bb: Aabb) -> u32 { return (bb.tag >> 24u) & 0x7Fu; }
+";
 
-        let p: vec3<f32> = cross(r.dir, e2);
-        let t: vec3<f32> = r.origin - vertices[s.a].pos;
-        let q: vec3<f32> = cross(t, e1);
-
-        let det = dot(e1, p);
-
-        var u: f32 = 0.0;
-        var v: f32 = 0.0;
-        if(det > config.eps) {
-            u = dot(t, p);
-            if(u < 0.0 || u > det) { return intrs_empty(); }
+// Wide nodes: 32 bytes
+const LOGIC_WIDE: &str = "\
+    struct Aabb {
+        bounds: vec3<u32>,
+        tag: u32,
+        count: u32,
+    }
 
-            v = dot(r.dir, q);
-            if(v < 0.0 || u + v > det) { return intrs_empty(); }
-        } else if(det < -1.0 * config.eps) {
-            u = dot(t, p);
-            if(u > 0.0 || u < det) { return intrs_empty(); }
+    fn is_leaf(bb: Aabb) -> bool { return (bb.tag >> 31u) == 1u; }
+    fn snd(bb: Aabb) -> u32 { return bb.tag & 0x7FFFFFFFu; }
+    fn item_idx(bb: Aabb) -> u32 { return bb.tag & 0x7FFFFFFFu; }
+    fn item_count(bb: Aabb) -> u32 { return bb.count; }
+";
 
-            v = dot(r.dir, q);
-            if(v > 0.0 || u + v < det) { return intrs_empty(); }
-        } else {
-            return intrs_empty();
-        }
+const LOGIC: &str = "\
+    const ORDERED: bool = {{ORDERED}};
 
-        let w: f32 = dot(e2, q) / det;
-        
-        if(w > config.t_max || w < config.t_min) {
-            return intrs_empty();
-        } else {
-            return Intrs(s, w);
-        }
+    struct Bounds {
+        min: vec3<f32>,
+        max: vec3<f32>,
     }
 
-    const INF_POS: f32 = 0x1.p+38f;
-    const INF_NEG: f32 = -1.0 * INF_POS;
-
-    // Wobble for the intersection test below
-    const EPS: f32 = 0.000002;
-
-    fn collides(bb: Aabb, ray: Ray) -> bool {
+    // Unpacks the half-precision bounds
+    fn bounds(bb: Aabb) -> Bounds {
         let a: vec2<f32> = unpack2x16float(bb.bounds.x);
         let b: vec2<f32> = unpack2x16float(bb.bounds.y);
         let c: vec2<f32> = unpack2x16float(bb.bounds.z);
 
-        let minima: vec3<f32> = vec3<f32>(a.x, b.x, c.x);
-        let maxima: vec3<f32> = vec3<f32>(a.y, b.y, c.y);
-        
-        var t0 = (minima.x - EPS - ray.origin.x) / ray.dir.x;
-        var t1 = (maxima.x + EPS - ray.origin.x) / ray.dir.x;
+        return Bounds(vec3<f32>(a.x, b.x, c.x), vec3<f32>(a.y, b.y, c.y));
+    }
 
-        var t_min = min(t0, t1);
-        var t_max = max(t0, t1);
-        
-        t0 = (minima.y - EPS - ray.origin.y) / ray.dir.y;
-        t1 = (maxima.y + EPS - ray.origin.y) / ray.dir.y;
+    fn collides(bb: Aabb, ray: Ray) -> bool {
+        let bounds = bounds(bb);
 
-        t_min = max(t_min, min(min(t0, t1), INF_NEG));
-        t_max = min(t_max, max(max(t0, t1), INF_POS));
-        
-        t0 = (minima.z - EPS - ray.origin.z) / ray.dir.z;
-        t1 = (maxima.z + EPS - ray.origin.z) / ray.dir.z;
+        return box_collides(bounds.min, bounds.max, ray);
+    }
 
-        t_min = max(t_min, min(min(t0, t1), INF_NEG));
-        t_max = min(t_max, max(max(t0, t1), INF_POS));
+    fn entry(bb: Aabb, ray: Ray) -> f32 {
+        let bounds = bounds(bb);
 
-        return (t_min < t_max);
+        return box_entry(bounds.min, bounds.max, ray);
     }
 
-    fn intrs_bvh_helper(idx: u32, ray: Ray, curr: Intrs) -> Intrs {
-        if(idx != 0u) {
-            let prim: Prim = primitives[idx];
+    // Leaves refer to a contiguous range of the reordered primitives
+    fn intrs_bvh(bb: Aabb, ray: Ray, excl: Excl) -> Intrs {
+        var intrs: Intrs = intrs_empty();
+
+        let start = item_idx(bb);
+        for(var i = start; i < start + item_count(bb); i = i + 1u) {
+            // Offset by one to skip the 'null' primitive
+            let prim: Prim = primitives[i + 1u];
+
+            if(eq(prim, excl.s)) { continue; }
 
             let temp: Intrs = intrs_tri(ray, prim);
 
-            if(temp.t < curr.t) {
-                return temp;
+            if(temp.t < intrs.t) {
+                intrs = temp;
             }
         }
-        
-        return curr;
+
+        return intrs;
     }
 
-    fn intrs_bvh(bb: Aabb, ray: Ray, excl: Prim) -> Intrs {
-        var intrs: Intrs = intrs_empty();
+    // A node on the ordered stack, with the distance at which the ray enters it
+    struct OrderedEntry {
+        node: u32,
+        t: f32,
+    }
 
-        var t = bb.bounds.x & 0xFFFF;
-        intrs = intrs_bvh_helper(t, ray, intrs);
-        t = (bb.bounds.x >> 16) & 0xFFFF;
-        intrs = intrs_bvh_helper(t, ray, intrs);
+    fn intrs_ordered(r: Ray, excl: Excl) -> Intrs {
+        var intrs = intrs_empty();
 
-        t = bb.bounds.y & 0xFFFF;
-        intrs = intrs_bvh_helper(t, ray, intrs);
-        t = (bb.bounds.y >> 16) & 0xFFFF;
-        intrs = intrs_bvh_helper(t, ray, intrs);
+        let t_root = entry(aabb_uniforms[0], r);
+        if(t_root == INF_POS) { return intrs; }
 
-        t = bb.bounds.z & 0xFFFF;
-        intrs = intrs_bvh_helper(t, ray, intrs);
-        t = (bb.bounds.z >> 16) & 0xFFFF;
-        intrs = intrs_bvh_helper(t, ray, intrs);
+        var stack_idx = 0u;
+        ordered_push(&stack_idx, OrderedEntry(0u, t_root));
 
-        return intrs;
-    }
+        while(stack_idx > 0u) {
+            let top = ordered_pop(&stack_idx);
 
-    // NOTE: The type is specified by BvhIntrs::logic
-    var<private> aabb_stack;
+            // Nodes beyond the closest hit can't contain a closer one
+            if(top.t > intrs.t) { continue; }
 
-    fn pop(idx: ptr<function, u32>, empty: ptr<function, bool>) -> u32 {
-        if(*idx == 1u) {
-            *empty = true;
-        }
+            let bb_idx = top.node;
+            let bb = aabb_uniforms[bb_idx];
 
-        *idx = *idx - 1u;
+            if(is_leaf(bb)) {
+                let temp = intrs_bvh(bb, r, excl);
 
-        return aabb_stack[*idx];
-    }
+                if(temp.t < intrs.t) {
+                    intrs = temp;
+                }
+            } else {
+                var near: u32 = bb_idx + 1u;
+                var far: u32 = snd(bb);
+
+                var t_near = entry(aabb_uniforms[near], r);
+                var t_far = entry(aabb_uniforms[far], r);
+
+                if(t_far < t_near) {
+                    let temp_idx = near; near = far; far = temp_idx;
+                    let temp_t = t_near; t_near = t_far; t_far = temp_t;
+                }
 
-    fn push(idx: ptr<function, u32>, bb: u32) {
-        aabb_stack[*idx] = bb;
+                // The nearer child is pushed last, so it's visited first
+                if(t_far < intrs.t) {
+                    ordered_push(&stack_idx, OrderedEntry(far, t_far));
+                }
 
-        *idx = *idx + 1u;
+                if(t_near < intrs.t) {
+                    ordered_push(&stack_idx, OrderedEntry(near, t_near));
+                }
+            }
+        }
+
+        return intrs;
     }
 
-    fn intrs(r: Ray, excl: Prim) -> Intrs {
-        var stack_idx = 0u;
-        var stack_empty = false;
+    fn intrs(r: Ray, excl: Excl) -> Intrs {
+        if(ORDERED) { return intrs_ordered(r, excl); }
 
+        var stack_idx = 0u;
         push(&stack_idx, 0u);
 
         var intrs = intrs_empty();
 
-        while(!stack_empty) {
-            let bb_idx = pop(&stack_idx, &stack_empty);
+        while(stack_idx > 0u) {
+            let bb_idx = pop(&stack_idx);
             let bb = aabb_uniforms[bb_idx];
 
             if(collides(bb, r)) {
-                if((bb.tag >> 31 & 1) == 1u) {
-                    let temp = intrs_bvh(aabb_uniforms[bb_idx + 1u], r, excl);
+                if(is_leaf(bb)) {
+                    let temp = intrs_bvh(bb, r, excl);
 
                     if(temp.t < intrs.t) {
                         intrs = temp;
                     }
                 } else {
-                    let fst: u32 = bb.tag & 0xFFFF;
-                    push(&stack_idx, fst);
+                    push(&stack_idx, bb_idx + 1u);
+                    push(&stack_idx, snd(bb));
+                }
+            }
+        }
+
+        return intrs;
+    }
 
-                    let snd: u32 = (bb.tag >> 16) & 0xFFFF;
-                    push(&stack_idx, snd);
+    // Stops at the first primitive between the origin and `t_max`,
+    // so the traversal order doesn't matter
+    fn occluded(r: Ray, t_max: f32, excl: Excl) -> bool {
+        var stack_idx = 0u;
+        push(&stack_idx, 0u);
+
+        while(stack_idx > 0u) {
+            let bb_idx = pop(&stack_idx);
+            let bb = aabb_uniforms[bb_idx];
+
+            // Nodes beyond `t_max` can't occlude anything
+            if(entry(bb, r) < t_max) {
+                if(is_leaf(bb)) {
+                    let start = item_idx(bb);
+                    for(var i = start; i < start + item_count(bb); i = i + 1u) {
+                        // Offset by one to skip the 'null' primitive
+                        let prim: Prim = primitives[i + 1u];
 
-                    stack_empty = false;
+                        if(eq(prim, excl.s)) { continue; }
+
+                        let temp = intrs_tri(r, prim);
+
+                        if(intrs_valid(temp) && temp.t < t_max) {
+                            return true;
+                        }
+                    }
+                } else {
+                    push(&stack_idx, bb_idx + 1u);
+                    push(&stack_idx, snd(bb));
                 }
             }
         }
 
-        return intrs;
+        return false;
     }\
 ";
\ No newline at end of file
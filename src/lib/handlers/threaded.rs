@@ -0,0 +1,324 @@
+use std::mem;
+
+use once_cell::unsync;
+use wgpu::util::DeviceExt as _;
+
+use crate::{bvh, shaders};
+
+// Nodes are laid out in pre-order, so the 'hit' link of an internal node
+// is always the next node. Only the 'miss' link has to be stored
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy)]
+struct ThreadedNode {
+    min: [f32; 3],
+    // The first node after this one's subtree
+    skip: u32,
+    max: [f32; 3],
+    item_idx: u32,
+    // ThreadedNode::INTERNAL if the node is not a leaf
+    item_count: u32,
+    // Matches the alignment of the WGSL struct
+    _padding: [u32; 3],
+}
+
+impl ThreadedNode {
+    const INTERNAL: u32 = u32::MAX;
+}
+
+// The binary tree's nodes in pre-order, with their miss links
+struct Threads {
+    order: Vec<u32>,
+    skips: Vec<u32>,
+}
+
+#[derive(serde::Deserialize)]
+#[derive(Default)]
+pub enum ThreadedBvhConfig {
+    Runtime { eps: f32, strategy: bvh::Strategy, },
+    #[default]
+    Default,
+}
+
+pub struct ThreadedBvhIntrs {
+    pub eps: f32,
+    pub strategy: bvh::Strategy,
+    data: unsync::OnceCell<(bvh::BvhData, Threads)>,
+}
+
+impl Default for ThreadedBvhIntrs {
+    fn default() -> Self {
+        Self {
+            eps: 0.02,
+            strategy: bvh::Strategy::Midpoint,
+            data: unsync::OnceCell::new(),
+        }
+    }
+}
+
+impl ThreadedBvhIntrs {
+    // When reloading scenes, we may want to write into our previous buffers
+    const COPY_USAGES: wgpu::BufferUsages = {
+        wgpu::BufferUsages::COPY_SRC //
+            .union(wgpu::BufferUsages::COPY_DST) //
+    };
+}
+
+fn is_leaf(uniform: &bvh::AabbUniform) -> bool {
+    uniform.fst == 0 && uniform.snd == 0
+}
+
+// Walks the tree in pre-order, recording where each subtree ends.
+// This doesn't assume anything about the layout of BvhData
+fn thread(data: &bvh::BvhData) -> Threads {
+    let bvh::BvhData { uniforms, .. } = data;
+
+    let mut threads = Threads {
+        order: Vec::with_capacity(uniforms.len()),
+        skips: Vec::with_capacity(uniforms.len()),
+    };
+
+    // Binary nodes to emit, along with their position 
+    // once their subtree has been emitted
+    let mut stack = vec![(0, None)];
+    while let Some((idx, emitted)) = stack.pop() {
+        if let Some(node) = emitted {
+            threads.skips[node] = threads.order.len() as u32;
+
+            continue;
+        }
+
+        stack.push((idx, Some(threads.order.len())));
+
+        threads.order.push(idx);
+        threads.skips.push(0);
+
+        let uniform = uniforms[idx as usize];
+        if !is_leaf(&uniform) {
+            stack.push((uniform.snd, None));
+            stack.push((uniform.fst, None));
+        }
+    }
+
+    threads
+}
+
+// Converts the threaded tree into its GPU representation
+fn flatten(data: &bvh::BvhData, threads: &Threads) -> Vec<ThreadedNode> {
+    threads.order
+        .iter()
+        .zip(threads.skips.iter())
+        .map(|(&idx, &skip)| {
+            let uniform = data.uniforms[idx as usize];
+
+            let bvh::Bounds { min, max, .. } = uniform.bounds;
+
+            ThreadedNode {
+                min,
+                skip,
+                max,
+                item_idx: uniform.item_idx,
+                item_count: if is_leaf(&uniform) {
+                    uniform.item_count
+                } else {
+                    ThreadedNode::INTERNAL
+                },
+                _padding: [0; 3],
+            }
+        }).collect()
+}
+
+impl super::IntrsHandler for ThreadedBvhIntrs {
+    type Config = ThreadedBvhConfig;
+
+    fn new(config: Self::Config) -> anyhow::Result<Self> {
+        let intrs = match config {
+            ThreadedBvhConfig::Runtime { eps, strategy } => Self {
+                eps,
+                strategy,
+                ..Default::default()
+            },
+            ThreadedBvhConfig::Default => Self::default(),
+        };
+
+        Ok(intrs)
+    }
+
+    fn vars<'a>(
+        &self,
+        scene: &mut crate::scene::Scene,
+        device: &wgpu::Device,
+    ) -> anyhow::Result<(super::IntrsPack<'a>, super::IntrsStats)> {
+        // Build the BVH if we haven't already
+        let (data, threads) = self.data.get_or_init(|| {
+            let data = bvh::BvhData::from_scene(self.eps, scene, 2, self.strategy);
+            let threads = thread(&data);
+
+            (data, threads)
+        });
+
+        let uniforms_threaded = flatten(data, threads);
+
+        let threaded_uniforms = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&uniforms_threaded),
+                usage: wgpu::BufferUsages::STORAGE | Self::COPY_USAGES,
+            }
+        );
+
+        // Leaves refer to contiguous ranges of the reordered primitives
+        scene.reorder(&data.indices);
+
+        let layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        count: None,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                            ty: wgpu::BufferBindingType::Storage {
+                                read_only: true
+                            },
+                        },
+                    },
+                ]
+            }
+        );
+
+        let group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: threaded_uniforms.as_entire_binding(),
+                    },
+                ],
+            }
+        );
+
+        let pack = super::IntrsPack {
+            vars: vec![
+                super::IntrsVar {
+                    var_name: "threaded_uniforms",
+                    var_ty: "array<ThreadedNode>",
+                    buffer: threaded_uniforms,
+                    buffer_ty: wgpu::BufferBindingType::Storage {
+                        read_only: true,
+                    },
+                },
+            ],
+            group,
+            layout,
+        };
+
+        let stats = super::IntrsStats {
+            name: "Threaded BVH",
+            size: mem::size_of::<ThreadedNode>() * uniforms_threaded.len(),
+            metrics: Some(data.metrics()),
+            compression: None,
+        };
+
+        Ok((pack, stats))
+    }
+
+    // Unlike the other BVH handlers, nothing depends on the node count
+    fn logic(&self) -> shaders::Module {
+        shaders::Module::new("bvh-threaded", LOGIC)
+            .import(shaders::library::tri())
+            .import(shaders::library::slab())
+    }
+
+    fn refit(
+        &mut self,
+        scene: &crate::scene::Scene,
+        queue: &wgpu::Queue,
+        pack: &super::IntrsPack,
+    ) -> bool {
+        let (
+            Some((data, threads)),
+            crate::scene::Scene::Active { prims, vertices, .. },
+        ) = (self.data.get_mut(), scene) else { return false; };
+
+        // `vars` reordered the scene's primitives to match `indices`
+        data.refit_ordered(prims, vertices);
+
+        let Some(super::IntrsVar { buffer, .. }) = pack.vars
+            .iter()
+            .find(|var| var.var_name == "threaded_uniforms") else { return false; };
+
+        queue.write_buffer(buffer, 0, bytemuck::cast_slice(&flatten(data, threads)));
+
+        true
+    }
+}
+
+// The intersection logic
+const LOGIC: &str = "\
+    struct ThreadedNode {
+        min: vec3<f32>,
+        skip: u32,
+        max: vec3<f32>,
+        item_idx: u32,
+        item_count: u32,
+    }
+
+    const INTERNAL: u32 = 0xFFFFFFFFu;
+
+    fn collides(node: ThreadedNode, ray: Ray) -> bool {
+        return box_collides(node.min, node.max, ray);
+    }
+
+    fn intrs_leaf(node: ThreadedNode, ray: Ray, excl: Excl) -> Intrs {
+        var intrs: Intrs = intrs_empty();
+
+        for(var i: u32 = node.item_idx; i < (node.item_idx + node.item_count); i = i + 1u) {
+            // Offset by one to skip the 'null' primitive
+            let prim: Prim = primitives[i + 1u];
+
+            if(eq(prim, excl.s)) { continue; }
+
+            let temp: Intrs = intrs_tri(ray, prim);
+
+            if(temp.t < intrs.t) {
+                intrs = temp;
+            }
+        }
+
+        return intrs;
+    }
+
+    fn intrs(r: Ray, excl: Excl) -> Intrs {
+        let count = arrayLength(&threaded_uniforms);
+
+        var intrs = intrs_empty();
+
+        // Traversal ends when the root's miss link is followed
+        var idx = 0u;
+        while(idx < count) {
+            let node = threaded_uniforms[idx];
+
+            if(!collides(node, r)) {
+                idx = node.skip;
+            } else if(node.item_count == INTERNAL) {
+                idx = idx + 1u;
+            } else {
+                let temp = intrs_leaf(node, r, excl);
+
+                if(temp.t < intrs.t) {
+                    intrs = temp;
+                }
+
+                idx = node.skip;
+            }
+        }
+
+        return intrs;
+    }\
+";
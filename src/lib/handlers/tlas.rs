@@ -0,0 +1,529 @@
+use std::mem;
+
+use once_cell::unsync;
+use wgpu::util::DeviceExt as _;
+
+use crate::{bvh, geom, scene, shaders};
+
+// Each TLAS item places a BLAS in the scene.
+// Items are stored in the order of the TLAS's indices
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy)]
+struct TlasItem {
+    // Index into the scene's instances
+    instance: u32,
+    // The BLAS root in `blas_uniforms`
+    root: u32,
+}
+
+// The bottom level holds one BVH per mesh, built in object space.
+// The top level is built over the world-space bounds of each instance
+struct TlasData {
+    blas: Vec<bvh::BvhData>,
+    // Where each mesh's reordered primitives begin
+    bases: Vec<usize>,
+    tlas: bvh::BvhData,
+    instances: Vec<scene::Instance>,
+    // Traversal stack sizes
+    depth_tlas: usize,
+    depth_blas: usize,
+}
+
+#[derive(serde::Deserialize)]
+#[derive(Default)]
+pub enum TlasConfig {
+    Runtime { eps: f32, strategy: bvh::Strategy, },
+    #[default]
+    Default,
+}
+
+pub struct TlasIntrs {
+    pub eps: f32,
+    pub strategy: bvh::Strategy,
+    data: unsync::OnceCell<TlasData>,
+}
+
+impl Default for TlasIntrs {
+    fn default() -> Self {
+        Self {
+            eps: 0.02,
+            strategy: bvh::Strategy::Midpoint,
+            data: unsync::OnceCell::new(),
+        }
+    }
+}
+
+impl TlasIntrs {
+    // When reloading scenes, we may want to write into our previous buffers
+    const COPY_USAGES: wgpu::BufferUsages = {
+        wgpu::BufferUsages::COPY_SRC //
+            .union(wgpu::BufferUsages::COPY_DST) //
+    };
+}
+
+fn is_leaf(uniform: &bvh::AabbUniform) -> bool {
+    uniform.fst == 0 && uniform.snd == 0
+}
+
+// The world-space bounds of a transformed BLAS
+fn instance_bounds(blas: &bvh::BvhData, instance: &scene::Instance) -> bvh::Bounds {
+    let bvh::Bounds { min, max, .. } = blas.uniforms[0].bounds;
+
+    let corners = (0..8).map(|corner| {
+        let point = [0, 1, 2].map(|axis| {
+            if (corner >> axis) & 1 == 0 { min[axis] } else { max[axis] }
+        });
+
+        geom::transform::point(&instance.transform, point)
+    });
+
+    bvh::Bounds::from_points(corners)
+}
+
+// Recomputes the TLAS's bounds without changing its topology
+fn refit_tlas(data: &mut TlasData) {
+    let TlasData { blas, tlas, instances, .. } = data;
+
+    let bvh::BvhData { uniforms, indices } = tlas;
+
+    for idx in (0..uniforms.len()).rev() {
+        let bvh::AabbUniform {
+            fst,
+            snd,
+            item_idx,
+            item_count, ..
+        } = uniforms[idx];
+
+        uniforms[idx].bounds = if item_count > 0 {
+            indices[(item_idx as usize)..((item_idx + item_count) as usize)]
+                .iter()
+                .map(|&inst| &instances[inst as usize])
+                .map(|inst| instance_bounds(&blas[inst.mesh], inst))
+                .fold(bvh::Bounds::new([].into_iter(), &[]), bvh::Bounds::union)
+        } else {
+            uniforms[fst as usize].bounds.union(uniforms[snd as usize].bounds)
+        };
+    }
+}
+
+// Packs every BLAS into a single array of nodes.
+// Returns the nodes and the root of each BLAS
+fn flatten(data: &TlasData) -> (Vec<bvh::AabbUniform>, Vec<u32>) {
+    let TlasData { blas, bases, .. } = data;
+
+    let mut uniforms = Vec::new();
+    let mut roots = Vec::with_capacity(blas.len());
+
+    for (blas, &base) in blas.iter().zip(bases.iter()) {
+        let root = uniforms.len() as u32;
+
+        roots.push(root);
+
+        uniforms.extend(blas.uniforms.iter().map(|&uniform| {
+            if is_leaf(&uniform) {
+                bvh::AabbUniform {
+                    item_idx: uniform.item_idx + base as u32,
+                    ..uniform
+                }
+            } else {
+                bvh::AabbUniform {
+                    fst: uniform.fst + root,
+                    snd: uniform.snd + root,
+                    ..uniform
+                }
+            }
+        }));
+    }
+
+    (uniforms, roots)
+}
+
+impl super::IntrsHandler for TlasIntrs {
+    type Config = TlasConfig;
+
+    fn new(config: Self::Config) -> anyhow::Result<Self> {
+        let intrs = match config {
+            TlasConfig::Runtime { eps, strategy } => Self {
+                eps,
+                strategy,
+                ..Default::default()
+            },
+            TlasConfig::Default => Self::default(),
+        };
+
+        Ok(intrs)
+    }
+
+    fn vars<'a>(
+        &self,
+        scene: &mut scene::Scene,
+        device: &wgpu::Device,
+    ) -> anyhow::Result<(super::IntrsPack<'a>, super::IntrsStats)> {
+        // Build both levels if we haven't already
+        let data = self.data.get_or_init(|| {
+            let scene::Scene::Active {
+                prims,
+                vertices,
+                meshes,
+                instances, ..
+            } = scene else {
+                let blas = bvh::BvhData::new(&bvh::Aabb::from_scene_unloaded());
+                let tlas = bvh::BvhData::new(&bvh::Aabb::from_scene_unloaded());
+
+                return TlasData {
+                    blas: vec![blas],
+                    bases: vec![0],
+                    tlas,
+                    instances: Vec::new(),
+                    depth_tlas: 1,
+                    depth_blas: 1,
+                };
+            };
+
+            // Without instances, the whole scene is a single mesh
+            let instanced = !instances.is_empty();
+
+            let (meshes_blas, instances) = if !instanced {
+                let mesh = scene::Mesh {
+                    prim_idx: 0,
+                    prim_count: prims.len(),
+                    vertex_idx: 0,
+                    vertex_count: vertices.len(),
+                };
+
+                let instance = scene::Instance {
+                    mesh: 0,
+                    transform: geom::transform::IDENTITY,
+                };
+
+                (vec![mesh], vec![instance])
+            } else {
+                (meshes.clone(), instances.clone())
+            };
+
+            let blas = meshes_blas
+                .iter()
+                .map(|&scene::Mesh { prim_idx, prim_count, .. }| {
+                    let prims = &prims[prim_idx..(prim_idx + prim_count)];
+
+                    bvh::BvhData::from_prims(self.eps, prims, vertices, 2, self.strategy)
+                }).collect::<Vec<_>>();
+
+            // Each mesh's primitives are reordered to match its BLAS.
+            // Spatial splits may duplicate references, so ranges can grow
+            let mut ordered = Vec::with_capacity(prims.len());
+            let mut bases = Vec::with_capacity(meshes_blas.len());
+            for (mesh, blas) in meshes_blas.iter().zip(blas.iter()) {
+                bases.push(ordered.len());
+
+                ordered.extend(blas.indices.iter().map(|&idx| {
+                    (mesh.prim_idx + idx as usize) as u32
+                }));
+            }
+
+            // The scene's meshes keep their original ranges,
+            // `Scene::flatten` restores the order they refer to
+            scene.reorder(&ordered);
+
+            let bounds = instances
+                .iter()
+                .map(|instance| instance_bounds(&blas[instance.mesh], instance))
+                .collect::<Vec<_>>();
+
+            let tlas = bvh::BvhData::from_bounds(&bounds, self.eps, bvh::Strategy::BINS);
+
+            // A depth-first traversal that pushes both children
+            // never holds more than one node per level
+            let depth_tlas = tlas.metrics().depth_max + 1;
+            let depth_blas = blas
+                .iter()
+                .map(|blas| blas.metrics().depth_max + 1)
+                .max()
+                .unwrap_or(1);
+
+            TlasData { blas, bases, tlas, instances, depth_tlas, depth_blas, }
+        });
+
+        let (uniforms_blas, roots) = flatten(data);
+
+        let items = data.tlas.indices
+            .iter()
+            .map(|&instance| TlasItem {
+                instance,
+                root: roots[data.instances
+                    .get(instance as usize)
+                    .map(|instance| instance.mesh)
+                    .unwrap_or(0)],
+            }).collect::<Vec<_>>();
+
+        let tlas_uniforms = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&data.tlas.uniforms),
+                usage: wgpu::BufferUsages::STORAGE | Self::COPY_USAGES,
+            }
+        );
+
+        let tlas_items = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&items),
+                usage: wgpu::BufferUsages::STORAGE | Self::COPY_USAGES,
+            }
+        );
+
+        let blas_uniforms = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&uniforms_blas),
+                usage: wgpu::BufferUsages::STORAGE | Self::COPY_USAGES,
+            }
+        );
+
+        let entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            count: None,
+            ty: wgpu::BindingType::Buffer {
+                has_dynamic_offset: false,
+                min_binding_size: None,
+                ty: wgpu::BufferBindingType::Storage {
+                    read_only: true
+                },
+            },
+        };
+
+        let layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[entry(0), entry(1), entry(2)],
+            }
+        );
+
+        let group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: tlas_uniforms.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: tlas_items.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: blas_uniforms.as_entire_binding(),
+                    },
+                ],
+            }
+        );
+
+        let pack = super::IntrsPack {
+            vars: vec![
+                super::IntrsVar {
+                    var_name: "tlas_uniforms",
+                    var_ty: "array<Aabb>",
+                    buffer: tlas_uniforms,
+                    buffer_ty: wgpu::BufferBindingType::Storage {
+                        read_only: true,
+                    },
+                },
+                super::IntrsVar {
+                    var_name: "tlas_items",
+                    var_ty: "array<TlasItem>",
+                    buffer: tlas_items,
+                    buffer_ty: wgpu::BufferBindingType::Storage {
+                        read_only: true,
+                    },
+                },
+                super::IntrsVar {
+                    var_name: "blas_uniforms",
+                    var_ty: "array<Aabb>",
+                    buffer: blas_uniforms,
+                    buffer_ty: wgpu::BufferBindingType::Storage {
+                        read_only: true,
+                    },
+                },
+            ],
+            group,
+            layout,
+        };
+
+        let stats = super::IntrsStats {
+            name: "TLAS",
+            size: mem::size_of::<bvh::AabbUniform>() * data.tlas.uniforms.len() +
+                mem::size_of::<TlasItem>() * items.len() +
+                mem::size_of::<bvh::AabbUniform>() * uniforms_blas.len(),
+            // Only the top level is reported,
+            // each BLAS is a regular BVH
+            metrics: Some(data.tlas.metrics()),
+            compression: None,
+        };
+
+        Ok((pack, stats))
+    }
+
+    fn logic(&self) -> shaders::Module {
+        // IntrsHandler::logic is always called after IntrsHandler::vars,
+        // so the diverging case is truly unreachable
+        let Some(TlasData { depth_tlas, depth_blas, .. }) = self.data.get() else {
+            unreachable!();
+        };
+
+        shaders::Module::new("tlas", LOGIC)
+            .import(shaders::library::tri())
+            .import(shaders::library::slab())
+            .import(shaders::library::stack_named("tlas", "u32", *depth_tlas))
+            .import(shaders::library::stack_named("blas", "u32", *depth_blas))
+    }
+
+    fn instanced(&self) -> bool { true }
+
+    fn refit(
+        &mut self,
+        scene: &scene::Scene,
+        queue: &wgpu::Queue,
+        pack: &super::IntrsPack,
+    ) -> bool {
+        let (
+            Some(data),
+            scene::Scene::Active { prims, vertices, .. },
+        ) = (self.data.get_mut(), scene) else { return false; };
+
+        // `vars` reordered each mesh's primitives to match its BLAS
+        for (blas, &base) in data.blas.iter_mut().zip(data.bases.iter()) {
+            let count = blas.indices.len();
+
+            blas.refit_ordered(&prims[base..(base + count)], vertices);
+        }
+
+        // The instances haven't moved, but their meshes may have grown
+        refit_tlas(data);
+
+        let (uniforms_blas, _) = flatten(data);
+
+        for (name, contents) in [
+            ("tlas_uniforms", bytemuck::cast_slice(&data.tlas.uniforms)),
+            ("blas_uniforms", bytemuck::cast_slice(&uniforms_blas)),
+        ] {
+            let Some(super::IntrsVar { buffer, .. }) = pack.vars
+                .iter()
+                .find(|var| var.var_name == name) else { return false; };
+
+            queue.write_buffer(buffer, 0, contents);
+        }
+
+        true
+    }
+}
+
+// The intersection logic
+const LOGIC: &str = "\
+    struct Bounds {
+        min: vec3<f32>,
+        max: vec3<f32>,
+    }
+
+    struct Aabb {
+        fst: u32,
+        snd: u32,
+        item_idx: u32,
+        item_count: u32,
+        bounds: Bounds,
+    }
+
+    struct TlasItem {
+        instance: u32,
+        root: u32,
+    }
+
+    fn collides(bb: Aabb, ray: Ray) -> bool {
+        return box_collides(bb.bounds.min, bb.bounds.max, ray);
+    }
+
+    // Internal nodes always have two distinct children
+    fn is_leaf(bb: Aabb) -> bool {
+        return bb.fst == bb.snd;
+    }
+
+    // Traverses a single BLAS with an object-space ray
+    fn intrs_blas(root: u32, ray: Ray, excl: Excl) -> Intrs {
+        var stack_idx = 0u;
+        blas_push(&stack_idx, root);
+
+        var intrs = intrs_empty();
+
+        while(stack_idx > 0u) {
+            let bb = blas_uniforms[blas_pop(&stack_idx)];
+
+            if(collides(bb, ray)) {
+                if(is_leaf(bb)) {
+                    for(var i: u32 = bb.item_idx; i < (bb.item_idx + bb.item_count); i = i + 1u) {
+                        // Offset by one to skip the 'null' primitive
+                        let prim: Prim = primitives[i + 1u];
+
+                        if(eq(prim, excl.s)) { continue; }
+
+                        let temp: Intrs = intrs_tri(ray, prim);
+
+                        if(temp.t < intrs.t) {
+                            intrs = temp;
+                        }
+                    }
+                } else {
+                    blas_push(&stack_idx, bb.fst);
+                    blas_push(&stack_idx, bb.snd);
+                }
+            }
+        }
+
+        return intrs;
+    }
+
+    fn intrs(r: Ray, excl: Excl) -> Intrs {
+        var stack_idx = 0u;
+        tlas_push(&stack_idx, 0u);
+
+        var intrs = intrs_empty();
+
+        while(stack_idx > 0u) {
+            let bb = tlas_uniforms[tlas_pop(&stack_idx)];
+
+            if(collides(bb, r)) {
+                if(is_leaf(bb)) {
+                    for(var i: u32 = bb.item_idx; i < (bb.item_idx + bb.item_count); i = i + 1u) {
+                        let item: TlasItem = tlas_items[i];
+                        let inst: Instance = instances[item.instance];
+
+                        // Directions aren't renormalized,
+                        // so the distances match in both spaces
+                        let ray = Ray(
+                            (inst.inverse * vec4<f32>(r.origin, 1.0)).xyz,
+                            (inst.inverse * vec4<f32>(r.dir, 0.0)).xyz
+                        );
+
+                        // Other instances of the excluded primitive are still hit
+                        var skip = excl_none();
+                        if(item.instance == excl.inst) { skip = excl; }
+
+                        let temp: Intrs = intrs_blas(item.root, ray, skip);
+
+                        if(temp.t < intrs.t) {
+                            intrs = temp;
+                            intrs.inst = item.instance;
+                        }
+                    }
+                } else {
+                    tlas_push(&stack_idx, bb.fst);
+                    tlas_push(&stack_idx, bb.snd);
+                }
+            }
+        }
+
+        return intrs;
+    }\
+";
@@ -0,0 +1,399 @@
+use std::{collections, mem};
+
+use once_cell::unsync;
+use wgpu::util::DeviceExt as _;
+
+use crate::{bvh, shaders};
+
+// Each slot of a wide node is either an internal node,
+// a leaf (pointing into the reordered primitives) or empty
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy)]
+struct WideChild {
+    min: [f32; 3],
+    // Index of the child's wide node, or of its first item
+    link: u32,
+    max: [f32; 3],
+    // WideChild::INTERNAL if the child is not a leaf
+    count: u32,
+}
+
+impl WideChild {
+    const INTERNAL: u32 = u32::MAX;
+
+    // Marks an unused slot
+    const EMPTY: u32 = u32::MAX;
+
+    // Empty slots have no items, and are skipped by the traversal.
+    // Their bounds are never tested, since slab hits inverted ones
+    fn empty() -> Self {
+        Self {
+            min: [f32::MAX; 3],
+            link: 0,
+            max: [-f32::MAX; 3],
+            count: 0,
+        }
+    }
+}
+
+// The binary tree collapsed into wide nodes.
+// Slots hold indices into the binary tree,
+// so the bounds can be repacked after a refit
+struct WideData {
+    // The binary node each wide node was collapsed from
+    roots: Vec<u32>,
+    // `width` binary node indices per wide node
+    slots: Vec<u32>,
+    // The depth of the deepest wide node
+    depth: usize,
+}
+
+#[derive(serde::Deserialize)]
+#[derive(Default)]
+pub enum WideBvhConfig {
+    Runtime { eps: f32, strategy: bvh::Strategy, width: usize, },
+    #[default]
+    Default,
+}
+
+pub struct WideBvhIntrs {
+    pub eps: f32,
+    pub strategy: bvh::Strategy,
+    pub width: usize,
+    data: unsync::OnceCell<(bvh::BvhData, WideData)>,
+}
+
+impl Default for WideBvhIntrs {
+    fn default() -> Self {
+        Self {
+            eps: 0.02,
+            strategy: bvh::Strategy::Midpoint,
+            width: 4,
+            data: unsync::OnceCell::new(),
+        }
+    }
+}
+
+impl WideBvhIntrs {
+    // When reloading scenes, we may want to write into our previous buffers
+    const COPY_USAGES: wgpu::BufferUsages = {
+        wgpu::BufferUsages::COPY_SRC //
+            .union(wgpu::BufferUsages::COPY_DST) //
+    };
+
+    // The supported branching factors
+    pub const WIDTHS: [usize; 2] = [4, 8];
+}
+
+fn is_leaf(uniform: &bvh::AabbUniform) -> bool {
+    uniform.fst == 0 && uniform.snd == 0
+}
+
+// Collapses the binary tree by repeatedly opening
+// the internal child with the largest surface area
+// until each node holds `width` children
+fn collapse(data: &bvh::BvhData, width: usize) -> WideData {
+    let bvh::BvhData { uniforms, .. } = data;
+
+    let mut wide = WideData {
+        roots: Vec::new(),
+        slots: Vec::new(),
+        depth: 0,
+    };
+
+    // Wide nodes are emitted breadth-first
+    let mut queue = collections::VecDeque::from([(0, 1)]);
+
+    while let Some((root, depth)) = queue.pop_front() {
+        wide.roots.push(root);
+        wide.depth = wide.depth.max(depth);
+
+        let mut children = match uniforms[root as usize] {
+            // Only happens when the root itself is a leaf
+            ref uniform if is_leaf(uniform) => vec![root],
+            bvh::AabbUniform { fst, snd, .. } => vec![fst, snd],
+        };
+
+        while children.len() < width {
+            let largest = children
+                .iter()
+                .enumerate()
+                .filter(|(_, &child)| !is_leaf(&uniforms[child as usize]))
+                .max_by(|(_, &a), (_, &b)| {
+                    let a = uniforms[a as usize].bounds.area();
+                    let b = uniforms[b as usize].bounds.area();
+
+                    a.total_cmp(&b)
+                });
+
+            let Some((idx, &child)) = largest else { break; };
+
+            let bvh::AabbUniform { fst, snd, .. } = uniforms[child as usize];
+
+            children[idx] = fst;
+            children.push(snd);
+        }
+
+        for &child in children.iter() {
+            if !is_leaf(&uniforms[child as usize]) {
+                queue.push_back((child, depth + 1));
+            }
+        }
+
+        children.resize(width, WideChild::EMPTY);
+
+        wide.slots.extend(children);
+    }
+
+    wide
+}
+
+// Converts the collapsed tree into its GPU representation
+fn flatten(data: &bvh::BvhData, wide: &WideData) -> Vec<WideChild> {
+    let bvh::BvhData { uniforms, .. } = data;
+
+    // Maps each internal binary node to its wide node
+    let mut links = vec![0; uniforms.len()];
+    for (idx, &root) in wide.roots.iter().enumerate() {
+        links[root as usize] = idx as u32;
+    }
+
+    wide.slots
+        .iter()
+        .map(|&slot| {
+            if slot == WideChild::EMPTY {
+                return WideChild::empty();
+            }
+
+            let uniform = uniforms[slot as usize];
+
+            let bvh::Bounds { min, max, .. } = uniform.bounds;
+
+            if is_leaf(&uniform) {
+                WideChild { min, link: uniform.item_idx, max, count: uniform.item_count, }
+            } else {
+                WideChild { min, link: links[slot as usize], max, count: WideChild::INTERNAL, }
+            }
+        }).collect()
+}
+
+impl super::IntrsHandler for WideBvhIntrs {
+    type Config = WideBvhConfig;
+
+    fn new(config: Self::Config) -> anyhow::Result<Self> {
+        let intrs = match config {
+            WideBvhConfig::Runtime { eps, strategy, width } => Self {
+                eps,
+                strategy,
+                width,
+                ..Default::default()
+            },
+            WideBvhConfig::Default => Self::default(),
+        };
+
+        if !Self::WIDTHS.contains(&intrs.width) {
+            anyhow::bail!("Wide BVH nodes must have either 4 or 8 children");
+        }
+
+        Ok(intrs)
+    }
+
+    fn vars<'a>(
+        &self,
+        scene: &mut crate::scene::Scene,
+        device: &wgpu::Device,
+    ) -> anyhow::Result<(super::IntrsPack<'a>, super::IntrsStats)> {
+        // Build the BVH if we haven't already
+        let (data, wide) = self.data.get_or_init(|| {
+            let data = bvh::BvhData::from_scene(self.eps, scene, 2, self.strategy);
+            let wide = collapse(&data, self.width);
+
+            (data, wide)
+        });
+
+        let uniforms_wide = flatten(data, wide);
+
+        let wide_uniforms = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&uniforms_wide),
+                usage: wgpu::BufferUsages::STORAGE | Self::COPY_USAGES,
+            }
+        );
+
+        // Leaves refer to contiguous ranges of the reordered primitives
+        scene.reorder(&data.indices);
+
+        let layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        count: None,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                            ty: wgpu::BufferBindingType::Storage {
+                                read_only: true
+                            },
+                        },
+                    },
+                ]
+            }
+        );
+
+        let group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wide_uniforms.as_entire_binding(),
+                    },
+                ],
+            }
+        );
+
+        let pack = super::IntrsPack {
+            vars: vec![
+                super::IntrsVar {
+                    var_name: "wide_uniforms",
+                    var_ty: "array<WideNode>",
+                    buffer: wide_uniforms,
+                    buffer_ty: wgpu::BufferBindingType::Storage {
+                        read_only: true,
+                    },
+                },
+            ],
+            group,
+            layout,
+        };
+
+        let stats = super::IntrsStats {
+            name: if self.width == 8 { "BVH8" } else { "BVH4" },
+            size: mem::size_of::<WideChild>() * uniforms_wide.len(),
+            metrics: Some(data.metrics()),
+            compression: None,
+        };
+
+        Ok((pack, stats))
+    }
+
+    fn logic(&self) -> shaders::Module {
+        // IntrsHandler::logic is always called after IntrsHandler::vars,
+        // so the diverging case is truly unreachable
+        let Some((_, WideData { depth, .. })) = self.data.get() else {
+            unreachable!();
+        };
+
+        // Each level leaves at most `width - 1` siblings on the stack
+        let size = depth * (self.width - 1) + 1;
+
+        shaders::Module::new("bvh-wide", LOGIC)
+            .param("WIDTH", self.width)
+            .import(shaders::library::tri())
+            .import(shaders::library::slab())
+            .import(shaders::library::stack(size))
+    }
+
+    fn refit(
+        &mut self,
+        scene: &crate::scene::Scene,
+        queue: &wgpu::Queue,
+        pack: &super::IntrsPack,
+    ) -> bool {
+        let (
+            Some((data, wide)),
+            crate::scene::Scene::Active { prims, vertices, .. },
+        ) = (self.data.get_mut(), scene) else { return false; };
+
+        // `vars` reordered the scene's primitives to match `indices`
+        data.refit_ordered(prims, vertices);
+
+        // The collapsed topology is kept, only the bounds change
+        let Some(super::IntrsVar { buffer, .. }) = pack.vars
+            .iter()
+            .find(|var| var.var_name == "wide_uniforms") else { return false; };
+
+        queue.write_buffer(buffer, 0, bytemuck::cast_slice(&flatten(data, wide)));
+
+        true
+    }
+}
+
+// The intersection logic
+const LOGIC: &str = "\
+    const WIDTH: u32 = {{WIDTH}};
+
+    struct WideChild {
+        min: vec3<f32>,
+        link: u32,
+        max: vec3<f32>,
+        count: u32,
+    }
+
+    // All children are fetched together
+    struct WideNode {
+        children: array<WideChild, WIDTH>,
+    }
+
+    const INTERNAL: u32 = 0xFFFFFFFFu;
+
+    fn collides(child: WideChild, ray: Ray) -> bool {
+        return box_collides(child.min, child.max, ray);
+    }
+
+    fn intrs_leaf(child: WideChild, ray: Ray, excl: Excl) -> Intrs {
+        var intrs: Intrs = intrs_empty();
+
+        for(var i: u32 = child.link; i < (child.link + child.count); i = i + 1u) {
+            // Offset by one to skip the 'null' primitive
+            let prim: Prim = primitives[i + 1u];
+
+            if(eq(prim, excl.s)) { continue; }
+
+            let temp: Intrs = intrs_tri(ray, prim);
+
+            if(temp.t < intrs.t) {
+                intrs = temp;
+            }
+        }
+
+        return intrs;
+    }
+
+    fn intrs(r: Ray, excl: Excl) -> Intrs {
+        var stack_idx = 0u;
+        push(&stack_idx, 0u);
+
+        var intrs = intrs_empty();
+
+        while(stack_idx > 0u) {
+            // A single fetch for every child's bounds
+            var node: WideNode = wide_uniforms[pop(&stack_idx)];
+
+            for(var i: u32 = 0u; i < WIDTH; i = i + 1u) {
+                let child = node.children[i];
+
+                // Empty slots hold no items
+                if(child.count != 0u && collides(child, r)) {
+                    if(child.count == INTERNAL) {
+                        push(&stack_idx, child.link);
+                    } else {
+                        let temp = intrs_leaf(child, r, excl);
+
+                        if(temp.t < intrs.t) {
+                            intrs = temp;
+                        }
+                    }
+                }
+            }
+        }
+
+        return intrs;
+    }\
+";
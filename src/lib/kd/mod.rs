@@ -0,0 +1,272 @@
+use crate::{bvh, scene};
+
+// The compact node layout shared with the shader.
+// The low 2 bits of `tag` hold the split axis, or KdNode::LEAF.
+// The rest holds the index of the second child for internal nodes
+// (the first child always follows its parent), or the item count for leaves.
+// `data` holds the bits of the split position, or the offset of the first item
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+pub struct KdNode {
+    pub data: u32,
+    pub tag: u32,
+}
+
+impl KdNode {
+    pub const LEAF: u32 = 3;
+
+    fn leaf(item_idx: usize, item_count: usize) -> Self {
+        Self {
+            data: item_idx as u32,
+            tag: ((item_count as u32) << 2) | Self::LEAF,
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.tag & 3 == Self::LEAF
+    }
+
+    pub fn axis(&self) -> usize {
+        (self.tag & 3) as usize
+    }
+
+    pub fn split(&self) -> f32 {
+        f32::from_bits(self.data)
+    }
+
+    // Index of the child above the split plane
+    pub fn snd(&self) -> usize {
+        (self.tag >> 2) as usize
+    }
+
+    pub fn item_idx(&self) -> usize {
+        self.data as usize
+    }
+
+    pub fn item_count(&self) -> usize {
+        (self.tag >> 2) as usize
+    }
+}
+
+// Primitives straddling a split plane are referenced by both children,
+// so leaves index into `indices` rather than the scene's primitives
+#[derive(Clone)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct KdData {
+    pub bounds: bvh::Bounds,
+    pub nodes: Vec<KdNode>,
+    pub indices: Vec<u32>,
+}
+
+impl KdData {
+    // Relative costs of visiting a node and testing a primitive
+    pub const COST_TRAVERSAL: f32 = 1.;
+    pub const COST_INTERSECTION: f32 = 1.5;
+
+    // Splits that leave one side empty are favored by this factor
+    pub const EMPTY_BONUS: f32 = 0.8;
+
+    // The depth limit suggested by Havran (2000)
+    pub fn max_depth(prim_count: usize) -> usize {
+        (8. + 1.3 * (prim_count.max(1) as f32).log2()).round() as usize
+    }
+
+    // The depth of the deepest leaf
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+
+        let mut stack = vec![(0, 0)];
+        while let Some((idx, level)) = stack.pop() {
+            let node = self.nodes[idx];
+
+            if node.is_leaf() {
+                depth = usize::max(depth, level);
+            } else {
+                stack.push((idx + 1, level + 1));
+                stack.push((node.snd(), level + 1));
+            }
+        }
+
+        depth
+    }
+
+    // Builds a Kd-tree over the scene with the Surface Area Heuristic.
+    // Subdivision stops once no split is cheaper than a leaf,
+    // or the tree reaches `max_depth`
+    pub fn from_scene(scene: &scene::Scene, max_depth: Option<usize>) -> Self {
+        let mut data = Self {
+            bounds: bvh::Bounds::new([].into_iter(), &[]),
+            nodes: Vec::new(),
+            indices: Vec::new(),
+        };
+
+        let scene::Scene::Active { prims, vertices, .. } = scene else {
+            data.nodes.push(KdNode::leaf(0, 0));
+
+            return data;
+        };
+
+        let bounds = bvh::Bounds::new(prims.iter().copied(), vertices);
+
+        data.bounds = bounds;
+
+        let builder = Builder {
+            bounds: prims
+                .iter()
+                .map(|&prim| bvh::Bounds::new(std::iter::once(prim), vertices))
+                .collect(),
+            max_depth: max_depth.unwrap_or(Self::max_depth(prims.len())),
+        };
+
+        builder.build(&mut data, (0..prims.len() as u32).collect(), bounds, 0);
+
+        data
+    }
+}
+
+// The cheapest plane found by `Builder::plane`
+struct Plane {
+    axis: usize,
+    pos: f32,
+    cost: f32,
+}
+
+struct Builder {
+    // The bounds of each primitive
+    bounds: Vec<bvh::Bounds>,
+    max_depth: usize,
+}
+
+impl Builder {
+    // The extent of an item along `axis`, clipped to the node
+    fn extent(&self, item: u32, bounds: bvh::Bounds, axis: usize) -> (f32, f32) {
+        let item = self.bounds[item as usize];
+
+        (
+            item.min[axis].max(bounds.min[axis]),
+            item.max[axis].min(bounds.max[axis]),
+        )
+    }
+
+    // Sweeps every item boundary along each axis (Wald & Havran 2006)
+    fn plane(&self, items: &[u32], bounds: bvh::Bounds) -> Option<Plane> {
+        let area = bounds.area();
+
+        if area <= 0. { return None; }
+
+        let mut best: Option<Plane> = None;
+
+        for axis in 0..3 {
+            let lo = bounds.min[axis];
+            let hi = bounds.max[axis];
+
+            if hi <= lo { continue; }
+
+            let mut starts = Vec::with_capacity(items.len());
+            let mut ends = Vec::with_capacity(items.len());
+            let mut planar = Vec::new();
+
+            for &item in items.iter() {
+                let (min, max) = self.extent(item, bounds, axis);
+
+                starts.push(min);
+                ends.push(max);
+
+                if min == max { planar.push(min); }
+            }
+
+            starts.sort_unstable_by(f32::total_cmp);
+            ends.sort_unstable_by(f32::total_cmp);
+            planar.sort_unstable_by(f32::total_cmp);
+
+            let mut candidates = starts
+                .iter()
+                .chain(ends.iter())
+                .copied()
+                .filter(|&pos| pos > lo && pos < hi)
+                .collect::<Vec<_>>();
+
+            candidates.sort_unstable_by(f32::total_cmp);
+            candidates.dedup();
+
+            for pos in candidates {
+                // Items lying in the plane are placed on both sides
+                let on = planar.partition_point(|&p| p <= pos) -
+                    planar.partition_point(|&p| p < pos);
+
+                let count_fst = starts.partition_point(|&p| p < pos) + on;
+                let count_snd = items.len() - ends.partition_point(|&p| p <= pos) + on;
+
+                let mut fst = bounds; fst.max[axis] = pos;
+                let mut snd = bounds; snd.min[axis] = pos;
+
+                let bonus = if count_fst == 0 || count_snd == 0 {
+                    KdData::EMPTY_BONUS
+                } else {
+                    1.
+                };
+
+                let cost = KdData::COST_TRAVERSAL + KdData::COST_INTERSECTION * bonus * (
+                    fst.area() / area * count_fst as f32 +
+                    snd.area() / area * count_snd as f32
+                );
+
+                if best.as_ref().map(|best| cost < best.cost).unwrap_or(true) {
+                    best = Some(Plane { axis, pos, cost });
+                }
+            }
+        }
+
+        best
+    }
+
+    // Emits nodes depth-first, so each node's first child follows it
+    fn build(&self, data: &mut KdData, items: Vec<u32>, bounds: bvh::Bounds, depth: usize) {
+        let node = data.nodes.len();
+
+        let leaf = KdData::COST_INTERSECTION * items.len() as f32;
+
+        let plane = if depth < self.max_depth {
+            self.plane(&items, bounds)
+        } else {
+            None
+        };
+
+        let plane = match plane {
+            Some(plane) if plane.cost < leaf => plane,
+            _ => {
+                data.nodes.push(KdNode::leaf(data.indices.len(), items.len()));
+                data.indices.extend(items);
+
+                return;
+            },
+        };
+
+        let Plane { axis, pos, .. } = plane;
+
+        let mut fst = Vec::new();
+        let mut snd = Vec::new();
+
+        for &item in items.iter() {
+            let (min, max) = self.extent(item, bounds, axis);
+
+            if min < pos || min == max && min == pos { fst.push(item); }
+            if max > pos || min == max && max == pos { snd.push(item); }
+        }
+
+        let mut bounds_fst = bounds; bounds_fst.max[axis] = pos;
+        let mut bounds_snd = bounds; bounds_snd.min[axis] = pos;
+
+        // Placeholder until the second child's index is known
+        data.nodes.push(KdNode { data: pos.to_bits(), tag: axis as u32 });
+
+        self.build(data, fst, bounds_fst, depth + 1);
+
+        data.nodes[node].tag |= (data.nodes.len() as u32) << 2;
+
+        self.build(data, snd, bounds_snd, depth + 1);
+    }
+}
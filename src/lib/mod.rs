@@ -1,19 +1,20 @@
 mod pipelines;
 mod vertex;
 mod state;
-mod shaders;
 
 pub mod timing;
 pub mod scene;
 pub mod geom;
 pub mod handlers;
+pub mod shaders;
 pub mod bvh;
+pub mod kd;
 
 #[cfg(target_arch = "wasm32")]
 mod web;
 
 #[cfg(target_arch = "wasm32")]
-pub use web::{update_config, update_scene, update_viewport};
+pub use web::{update_config, update_handler, update_scene, update_viewport};
 
 use std::sync;
 
@@ -119,6 +120,10 @@ pub struct ComputeConfig {
     pub bounces: u32,
     pub eps: f32,
     pub ambience: f32,
+    // A SelfIntersection, stored as its discriminant
+    pub self_intersection: u32,
+    // A TriangleTest, stored as its discriminant
+    pub triangle_test: u32,
 }
 
 impl ComputeConfig {
@@ -130,6 +135,62 @@ impl ComputeConfig {
             bounces: 4,
             eps: 0.0000001,
             ambience: 0.1,
+            self_intersection: SelfIntersection::Exclusion as u32,
+            triangle_test: TriangleTest::MollerTrumbore as u32,
+        }
+    }
+}
+
+// How shadow and reflection rays avoid hitting the surface they leave
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(PartialEq, Eq)]
+#[repr(u32)]
+pub enum SelfIntersection {
+    // Skip the primitive that was hit,
+    // and nudge the ray's origin along the normal
+    #[default]
+    Exclusion = 0,
+    // Move the ray's origin off of the surface by a margin that scales
+    // with its magnitude (Wächter & Binder, Ray Tracing Gems, 2019).
+    // Unlike exclusion, this also avoids neighboring coplanar primitives,
+    // see tests/acne.rs
+    Offset = 1,
+}
+
+impl SelfIntersection {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "exclusion" => Some(Self::Exclusion),
+            "offset" => Some(Self::Offset),
+            _ => None,
+        }
+    }
+}
+
+// The ray-triangle intersection used by every handler
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(PartialEq, Eq)]
+#[repr(u32)]
+pub enum TriangleTest {
+    // Fast, but rays can leak through the edges that primitives share.
+    // Uses ComputeConfig::eps as the determinant threshold
+    #[default]
+    MollerTrumbore = 0,
+    // Never misses between primitives that share an edge
+    // (Woop et al., Watertight Ray/Triangle Intersection, 2013)
+    Watertight = 1,
+}
+
+impl TriangleTest {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "moller-trumbore" => Some(Self::MollerTrumbore),
+            "watertight" => Some(Self::Watertight),
+            _ => None,
         }
     }
 }
@@ -165,16 +226,18 @@ impl Config {
     }
 }
 
+// The handler is usually built with `handlers::registry`,
+// or directly from one of the handler types
 #[allow(unused_mut)]
-pub async fn run_native<H, S>(
+pub async fn run_native<S>(
     mut config: Config, 
-    mut config_handler: H::Config,
+    handler: Box<dyn handlers::DynIntrsHandler>,
     mut scene: scene::Scene
 ) -> Result<(), Failed> 
-    where H: handlers::IntrsHandler, S: timing::Scheduler {
+    where S: timing::Scheduler {
 
     unsafe {
-        run_internal::<H, S>(&mut config, config_handler, &mut scene).await
+        run_internal::<S>(&mut config, handler, &mut scene).await
     }
 }
 
@@ -185,27 +248,32 @@ pub async fn run_wasm() -> Result<(), Failed> {
         #[allow(static_mut_refs)]
         let web::WebState {
             config,
-            scene, ..
+            scene,
+            handler, ..
         } = &mut web::WEB_STATE;
 
         // We don't take benchmarks on WASM
         type WebScheduler = timing::DefaultScheduler;
 
-        // TODO: I'm going to keep web::WebHandler == BasicIntrs
-        // until optimizations are complete
-        run_internal::<web::WebHandler, WebScheduler>
-            (config, <web::WebHandler as handlers::IntrsHandler>::Config::default(), scene).await
+        let handler = match web::handler(handler.as_ref()) {
+            Ok(handler) => handler,
+            Err(e) => {
+                let _ = web::note("Failed to initialize intersection handler");
 
-            
+                return BAIL(Err(e));
+            },
+        };
+
+        run_internal::<WebScheduler>(config, handler, scene).await
     }
 }
 
-async unsafe fn run_internal<H, S>(
+async unsafe fn run_internal<S>(
     config: &mut Config,
-    config_handler: H::Config,
+    handler: Box<dyn handlers::DynIntrsHandler>,
     scene: &mut scene::Scene
 ) -> Result<(), Failed> 
-    where H: handlers::IntrsHandler, S: timing::Scheduler {
+    where S: timing::Scheduler {
 
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
@@ -237,8 +305,8 @@ async unsafe fn run_internal<H, S>(
     let mut state = {
         let window = window.clone();
 
-        BAIL(state::State::<S>::new::<H>(
-            *config, config_handler, scene, window).await)?
+        BAIL(state::State::<S>::new(
+            *config, handler, scene, window).await)?
     };
 
     // Keeps track of resize actions. 
@@ -257,6 +325,23 @@ async unsafe fn run_internal<H, S>(
     // Indicates whether the camera has changed
     let mut update_required_camera = false;
 
+    // Tab cycles through these handlers, for side-by-side comparisons.
+    // None until the first swap, since the initial handler may be any other
+    const SWAP_HANDLERS: [&str; 3] = ["naive", "bvh", "bvh-rf"];
+
+    let mut swap_idx: Option<usize> = None;
+
+    // Space toggles a wave through the scene's vertices, which are refit
+    // every frame. Holds the rest positions and the elapsed time (s)
+    let mut wave: Option<(Vec<geom::PrimVertex>, f32)> = None;
+
+    // Puts the vertices back where they started
+    let settle = |scene: &mut scene::Scene, rest: Vec<geom::PrimVertex>| {
+        if let scene::Scene::Active { vertices, .. } = scene {
+            if vertices.len() == rest.len() { *vertices = rest; }
+        }
+    };
+
     // Enter the event loop
     BAIL(event_loop.run(|event, target| {
         // We are only updating config options live on the web
@@ -272,6 +357,9 @@ async unsafe fn run_internal<H, S>(
             }
         }
 
+        // Indicates that the handler has been swapped
+        let mut update_required_handler = false;
+
         match event {
             event::Event::WindowEvent { event, window_id, .. }
                 if window_id == window.id() => {
@@ -299,6 +387,63 @@ async unsafe fn run_internal<H, S>(
                                 logical_key: keyboard::Key::Named(keyboard::NamedKey::Escape), ..
                             }, ..
                         } => target.exit(),
+                        event::WindowEvent::KeyboardInput {
+                            event: event::KeyEvent {
+                                state: event::ElementState::Pressed,
+                                logical_key: keyboard::Key::Named(keyboard::NamedKey::Tab),
+                                repeat: false, ..
+                            }, ..
+                        } => {
+                            // The new handler is built around the rest positions
+                            if let Some((rest, _)) = wave.take() {
+                                settle(scene, rest);
+                            }
+
+                            let idx = swap_idx
+                                .map(|idx| (idx + 1) % SWAP_HANDLERS.len())
+                                .unwrap_or(0);
+
+                            let name = SWAP_HANDLERS[idx];
+
+                            let swapped = handlers::registry::build(name, serde_json::Value::Null)
+                                .and_then(|handler| state.swap(*config, handler, scene));
+
+                            match swapped {
+                                Ok(_) => {
+                                    log::info!("Swapped to the '{name}' handler");
+
+                                    // Later scenes are loaded with it as well
+                                    #[cfg(target_arch = "wasm32")] unsafe {
+                                        web::WEB_STATE.handler = Some({
+                                            handlers::registry::HandlerDesc::Name(name.to_owned())
+                                        });
+                                    }
+
+                                    swap_idx = Some(idx);
+
+                                    update_required_handler = true;
+                                },
+                                Err(e) => log::warn!("Unable to swap to the '{name}' handler: {e:#}"),
+                            }
+                        },
+                        event::WindowEvent::KeyboardInput {
+                            event: event::KeyEvent {
+                                state: event::ElementState::Pressed,
+                                logical_key: keyboard::Key::Named(keyboard::NamedKey::Space),
+                                repeat: false, ..
+                            }, ..
+                        } => match wave.take() {
+                            Some((rest, _)) => {
+                                settle(scene, rest);
+
+                                if state.refit(scene) {
+                                    update_required_handler = true;
+                                }
+                            },
+                            None => if let scene::Scene::Active { vertices, .. } = scene {
+                                wave = Some((vertices.clone(), 0.));
+                            },
+                        },
                         event::WindowEvent::Resized(physical_size) //
                             if resize_dim != Some(physical_size) => {
                             // Update the size and the time the event occurred
@@ -352,6 +497,34 @@ async unsafe fn run_internal<H, S>(
             }
         }
 
+        // Move the vertices, then refit the handler around them.
+        // Only once a frame is due, since refitting isn't free
+        let mut update_required_wave = false;
+        if let Some((rest, t)) = wave.as_mut() {
+            *t += 0.001 * temp as f32;
+
+            let due = prev_frame_duration + temp > frame_duration;
+
+            if due && !scene.wave(rest, *t) {
+                // The vertices were left alone, there is nothing to refit
+                log::warn!("The scene no longer matches the wave, stopping it");
+
+                wave = None;
+            } else if due && state.refit(scene) {
+                update_required_wave = true;
+            } else if due {
+                log::warn!("The current handler can't be refit, stopping the wave");
+
+                if let Some((rest, _)) = wave.take() {
+                    settle(scene, rest);
+                }
+
+                // Only uploads the vertices, since the handler
+                // was built around their rest positions anyway
+                state.refit(scene);
+            }
+        }
+
         prev_frame_instant = frame_instant;
         prev_frame_duration += temp;
 
@@ -381,15 +554,16 @@ async unsafe fn run_internal<H, S>(
             }
         }
 
-        if !(update_required_camera || update_required_framerate) {
+        if !(update_required_camera || update_required_framerate || update_required_wave) {
             // If no update is required, discard the frame
             if prev_frame_duration > frame_duration {
                 prev_frame_duration -= frame_duration;
             }
         }
 
-        // Force an update if `web` requests it
-        if update_required_web && prev_frame_duration < frame_duration {
+        // Force an update if `web` requests it, or the handler has changed
+        let update_required_forced = update_required_web || update_required_handler;
+        if update_required_forced && prev_frame_duration < frame_duration {
             prev_frame_duration += frame_duration;
         }
 
@@ -195,7 +195,7 @@ impl CameraController {
         }
 
         if *right {
-            orbit(uniform, -1. * SPEED * dt);
+            orbit(uniform, -SPEED * dt);
 
             return true;
         }
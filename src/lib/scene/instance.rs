@@ -0,0 +1,54 @@
+use crate::geom::transform;
+
+// A contiguous block of the scene's primitives and vertices,
+// which is shared by every instance that references it
+#[derive(Clone, Copy)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug)]
+pub struct Mesh {
+    pub prim_idx: usize,
+    pub prim_count: usize,
+    pub vertex_idx: usize,
+    pub vertex_count: usize,
+}
+
+// Places a mesh in the scene
+#[derive(Clone, Copy)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(Debug)]
+pub struct Instance {
+    pub mesh: usize,
+    // Object space to world space (column-major)
+    pub transform: transform::M4,
+}
+
+// The GPU-side instance, with its inverse precomputed
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+pub struct InstanceUniform {
+    pub transform: transform::M4,
+    pub inverse: transform::M4,
+}
+
+impl InstanceUniform {
+    pub const IDENTITY: Self = Self {
+        transform: transform::IDENTITY,
+        inverse: transform::IDENTITY,
+    };
+}
+
+impl TryFrom<Instance> for InstanceUniform {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Instance) -> Result<Self, Self::Error> {
+        let Instance { transform, .. } = value;
+
+        let inverse = transform::inverse(&transform).ok_or({
+            anyhow::anyhow!("Instance transforms must be invertible")
+        })?;
+
+        Ok(Self { transform, inverse })
+    }
+}
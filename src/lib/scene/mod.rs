@@ -1,7 +1,9 @@
 mod camera;
+mod instance;
 
 // Export for use in `builder` binary
 pub use camera::{CameraUniform, CameraController};
+pub use instance::{Mesh, Instance, InstanceUniform};
 
 use crate::geom;
 use crate::geom::light as light;
@@ -13,6 +15,9 @@ pub struct ScenePack {
     pub bg_layout: wgpu::BindGroupLayout,
 }
 
+// There's only one scene, so the unloaded variant's size doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
 #[derive(Debug)]
 pub enum Scene {
     Unloaded,
@@ -23,6 +28,15 @@ pub enum Scene {
         vertices: Vec<geom::PrimVertex>,
         lights: Vec<light::Light>,
         materials: Vec<geom::PrimMat>,
+        // Each call to `Scene::add_mesh` registers a mesh.
+        // If there are no instances, every primitive is in world space.
+        // Otherwise only instanced meshes are visible
+        meshes: Vec<Mesh>,
+        instances: Vec<Instance>,
+        // The original index of each primitive, after a handler
+        // has reordered them with `Scene::reorder`.
+        // Empty while they're in their original order
+        order: Vec<u32>,
     },
 }
 
@@ -38,6 +52,11 @@ impl<'de> serde::Deserialize<'de> for Scene {
             vertices: Vec<geom::PrimVertex>,
             lights: Vec<light::Light>,
             materials: Vec<geom::PrimMat>,
+            // Scenes without instancing omit these
+            #[serde(default)]
+            meshes: Vec<Mesh>,
+            #[serde(default)]
+            instances: Vec<Instance>,
         }
 
         impl From<Intermediate> for Scene {
@@ -49,6 +68,8 @@ impl<'de> serde::Deserialize<'de> for Scene {
                     vertices,
                     lights,
                     materials,
+                    meshes,
+                    instances,
                 } = value;
 
                 Self::Active {
@@ -58,11 +79,27 @@ impl<'de> serde::Deserialize<'de> for Scene {
                     vertices,
                     lights,
                     materials,
+                    meshes,
+                    instances,
+                    order: Vec::new(),
                 }
             }
         }
 
-        Ok(Intermediate::deserialize(deserializer)?.into())
+        let intermediate = Intermediate::deserialize(deserializer)?;
+
+        // Validate the instances up front, so packing can't fail
+        for instance in intermediate.instances.iter() {
+            use serde::de;
+
+            if instance.mesh >= intermediate.meshes.len() {
+                return Err(de::Error::custom("instance refers to a missing mesh"));
+            }
+
+            InstanceUniform::try_from(*instance).map_err(de::Error::custom)?;
+        }
+
+        Ok(intermediate.into())
     }
 }
 
@@ -78,6 +115,10 @@ impl serde::Serialize for Scene {
             vertices: &'a [geom::PrimVertex],
             lights: &'a [light::Light],
             materials: &'a [geom::PrimMat],
+            #[serde(skip_serializing_if = "<[_]>::is_empty")]
+            meshes: &'a [Mesh],
+            #[serde(skip_serializing_if = "<[_]>::is_empty")]
+            instances: &'a [Instance],
         }
 
         #[allow(clippy::from_over_into)]
@@ -92,6 +133,8 @@ impl serde::Serialize for Scene {
                         vertices,
                         lights,
                         materials,
+                        meshes,
+                        instances, ..
                     } => Intermediate {
                         camera,
                         camera_controller,
@@ -99,6 +142,8 @@ impl serde::Serialize for Scene {
                         vertices,
                         lights,
                         materials,
+                        meshes,
+                        instances,
                     },
                 }
             }
@@ -125,11 +170,42 @@ impl Scene {
             vertices: vec![geom::PrimVertex::new(N3, N3)],
             lights: vec![light::Light { pos: N3, strength: 0., }],
             materials: vec![geom::PrimMat::new(N3, N3, 0.)],
+            meshes: Vec::new(),
+            instances: Vec::new(),
+            order: Vec::new(),
         };
 
         scene.pack(device)
     }
 
+    // The primitives and instances, as they are laid out on the GPU
+    fn uniforms(
+        prims: &[geom::Prim],
+        instances: &[Instance],
+    ) -> (Vec<geom::Prim>, Vec<InstanceUniform>) {
+        // The first primitive acts as a 'null'
+        let mut primitives = vec![
+            geom::Prim { indices: [0; 3], material: -1 }
+        ];
+        
+        // Then we add all the others
+        primitives.extend(prims.iter().copied());
+
+        // Scenes without instances are drawn through a single identity
+        let instances = match instances.len() {
+            0 => vec![InstanceUniform::IDENTITY],
+            _ => instances
+                .iter()
+                .map(|&instance| {
+                    // Transforms were validated when they were added
+                    InstanceUniform::try_from(instance)
+                        .unwrap_or(InstanceUniform::IDENTITY)
+                }).collect(),
+        };
+
+        (primitives, instances)
+    }
+
     pub fn pack(&self, device: &wgpu::Device) -> ScenePack {
         use wgpu::util::DeviceExt as _;
 
@@ -138,7 +214,8 @@ impl Scene {
             prims, 
             vertices,
             lights, 
-            materials, .. 
+            materials,
+            instances, .. 
         } = self else {
             return Self::pack_unloaded(device);
         };
@@ -157,13 +234,7 @@ impl Scene {
             &camera_buffer_descriptor
         });
 
-        // The first primitive acts as a 'null'
-        let mut primitives = vec![
-            geom::Prim { indices: [0; 3], material: -1 }
-        ];
-        
-        // Then we add all the others
-        primitives.extend(prims.iter().copied());
+        let (primitives, instances) = Self::uniforms(prims, instances);
 
         //
         // group(2) Scene Buffer and Groups
@@ -173,6 +244,7 @@ impl Scene {
         // 2: 'vertices'
         // 3: 'lights'
         // 4: 'materials'
+        // 5: 'instances'
 
         // NOTE: Gotta keep camera distinct,
         // because later we need the actual buffer.
@@ -199,6 +271,11 @@ impl Scene {
                 label: None,
                 usage: wgpu::BufferUsages::STORAGE | Self::COPY_USAGES,
                 contents: bytemuck::cast_slice(materials),
+            },
+            &wgpu::util::BufferInitDescriptor {
+                label: None,
+                usage: wgpu::BufferUsages::STORAGE | Self::COPY_USAGES,
+                contents: bytemuck::cast_slice(instances.as_slice()),
             }
         ];
 
@@ -271,20 +348,70 @@ impl Scene {
         }
     }
 
+    // Writes the scene into the buffers of an earlier `pack`,
+    // e.g. after a new handler has reordered its primitives.
+    // Returns false if any of them would have to be resized
+    pub fn write(&self, queue: &wgpu::Queue, buffers: &[wgpu::Buffer]) -> bool {
+        let Scene::Active { 
+            prims, 
+            vertices,
+            lights, 
+            materials,
+            instances, .. 
+        } = self else { return false; };
+
+        let (primitives, instances) = Self::uniforms(prims, instances);
+
+        // In the same order as `pack`
+        let contents: [&[u8]; 5] = [
+            bytemuck::cast_slice(primitives.as_slice()),
+            bytemuck::cast_slice(vertices.as_slice()),
+            bytemuck::cast_slice(lights),
+            bytemuck::cast_slice(materials),
+            bytemuck::cast_slice(instances.as_slice()),
+        ];
+
+        let fits = buffers.len() == contents.len() && buffers
+            .iter()
+            .zip(contents)
+            .all(|(buffer, contents)| buffer.size() == contents.len() as u64);
+
+        if fits {
+            for (buffer, contents) in buffers.iter().zip(contents) {
+                queue.write_buffer(buffer, 0, contents);
+            }
+        }
+
+        fits
+    }
+
+    // Appends the mesh's geometry to the scene.
+    // Returns the index of the new mesh, so it can be instanced
     pub fn add_mesh(
         &mut self, 
         obj: wavefront::Obj,
         material: i32,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<usize> {
         use crate::geom::V3Ops as _;
 
+        // Meshes refer to the primitives in their original order
+        self.restore_order();
+
         let Self::Active {
             vertices,
-            prims, .. 
+            prims, 
+            meshes, .. 
         } = self else { 
             anyhow::bail!("Unable to add mesh to unloaded scene"); 
         };
 
+        let mesh = Mesh {
+            prim_idx: prims.len(),
+            prim_count: 0,
+            vertex_idx: vertices.len(),
+            vertex_count: obj.positions().len(),
+        };
+
         let mut obj_normals = vec![vec![]; obj.positions().len()];
         let mut obj_prims = vec![];
 
@@ -337,8 +464,226 @@ impl Scene {
             })
         });
 
+        meshes.push(Mesh { prim_count: obj_prims.len(), ..mesh });
+
         prims.append(&mut obj_prims);
 
+        Ok(meshes.len() - 1)
+    }
+
+    // Deletes a mesh along with its primitives, vertices and instances.
+    // Returns the removed mesh, so acceleration structures can drop its range
+    pub fn remove_mesh(&mut self, mesh: usize) -> anyhow::Result<Mesh> {
+        self.restore_order();
+
+        let Self::Active {
+            prims,
+            vertices,
+            meshes,
+            instances, ..
+        } = self else {
+            anyhow::bail!("Unable to remove mesh from unloaded scene");
+        };
+
+        if mesh >= meshes.len() {
+            anyhow::bail!("Unable to remove missing mesh [{mesh}]");
+        }
+
+        let removed @ Mesh {
+            prim_idx,
+            prim_count,
+            vertex_idx,
+            vertex_count,
+        } = meshes.remove(mesh);
+
+        prims.drain(prim_idx..(prim_idx + prim_count));
+        vertices.drain(vertex_idx..(vertex_idx + vertex_count));
+
+        // Later primitives refer to vertices that have shifted down
+        for geom::Prim { indices, .. } in prims.iter_mut() {
+            for idx in indices.iter_mut() {
+                if *idx as usize >= vertex_idx + vertex_count {
+                    *idx -= vertex_count as u32;
+                }
+            }
+        }
+
+        for other in meshes.iter_mut() {
+            if other.prim_idx > prim_idx { other.prim_idx -= prim_count; }
+            if other.vertex_idx > vertex_idx { other.vertex_idx -= vertex_count; }
+        }
+
+        instances.retain(|instance| instance.mesh != mesh);
+
+        for instance in instances.iter_mut() {
+            if instance.mesh > mesh { instance.mesh -= 1; }
+        }
+
+        Ok(removed)
+    }
+
+    // Places another copy of a mesh in the scene.
+    // Once a scene has instances, meshes are only drawn through them
+    pub fn add_instance(
+        &mut self,
+        mesh: usize,
+        transform: geom::transform::M4,
+    ) -> anyhow::Result<()> {
+        let Self::Active { 
+            meshes, 
+            instances, .. 
+        } = self else {
+            anyhow::bail!("Unable to add instance to unloaded scene");
+        };
+
+        if mesh >= meshes.len() {
+            anyhow::bail!("Unable to instance missing mesh [{mesh}]");
+        }
+
+        let instance = Instance { mesh, transform };
+
+        // Fail early on singular transforms
+        InstanceUniform::try_from(instance)?;
+
+        instances.push(instance);
+
         Ok(())
     }
-}
\ No newline at end of file
+
+    // Moves every vertex away from its rest position along a travelling wave.
+    // The primitives are left alone, so handlers can refit rather than rebuild.
+    // Returns false if the rest positions don't match the scene
+    pub fn wave(&mut self, rest: &[geom::PrimVertex], t: f32) -> bool {
+        let Self::Active { vertices, .. } = self else { return false; };
+
+        if rest.len() != vertices.len() { return false; }
+
+        let mut min = [f32::MAX; 3];
+        let mut max = [-f32::MAX; 3];
+
+        for geom::PrimVertex { pos, .. } in rest {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(pos[axis]);
+                max[axis] = max[axis].max(pos[axis]);
+            }
+        }
+
+        // The wave is scaled to the scene, and spans it twice
+        let extent = (0..3).map(|axis| max[axis] - min[axis]).fold(0., f32::max);
+        if extent <= 0. { return true; }
+
+        let amplitude = extent * 0.01;
+        let frequency = std::f32::consts::TAU * 2. / extent;
+
+        for (vertex, rest) in vertices.iter_mut().zip(rest) {
+            let [x, y, z] = rest.pos;
+
+            vertex.pos = [x, y + amplitude * (frequency * x + t).sin(), z];
+        }
+
+        true
+    }
+
+    // True if only the instanced meshes are visible, see `flatten`
+    pub fn has_instances(&self) -> bool {
+        match self {
+            Self::Active { instances, .. } => !instances.is_empty(),
+            Self::Unloaded => false,
+        }
+    }
+
+    // Bakes every instance into world space,
+    // so handlers without instancing can draw the scene.
+    // Does nothing if the scene has no instances
+    pub fn flatten(&mut self) {
+        use geom::transform;
+
+        self.restore_order();
+
+        let Self::Active {
+            prims,
+            vertices,
+            meshes,
+            instances, ..
+        } = self else { return; };
+
+        if instances.is_empty() { return; }
+
+        let mut prims_flat = Vec::new();
+        let mut vertices_flat = Vec::new();
+
+        for &instance in instances.iter() {
+            let Mesh {
+                prim_idx,
+                prim_count,
+                vertex_idx,
+                vertex_count,
+            } = meshes[instance.mesh];
+
+            let InstanceUniform { 
+                transform: m, 
+                inverse, 
+            } = InstanceUniform::try_from(instance)
+                .unwrap_or(InstanceUniform::IDENTITY);
+
+            // Each copy of the mesh gets its own vertices
+            let base = vertices_flat.len() as u32;
+
+            vertices_flat.extend({
+                vertices[vertex_idx..(vertex_idx + vertex_count)]
+                    .iter()
+                    .map(|vertex| geom::PrimVertex::new(
+                        transform::point(&m, vertex.pos),
+                        transform::normal(&inverse, vertex.normal),
+                    ))
+            });
+
+            prims_flat.extend({
+                prims[prim_idx..(prim_idx + prim_count)]
+                    .iter()
+                    .map(|&geom::Prim { indices, material }| geom::Prim {
+                        indices: indices.map(|idx| idx - vertex_idx as u32 + base),
+                        material,
+                    })
+            });
+        }
+
+        *prims = prims_flat;
+        *vertices = vertices_flat;
+
+        meshes.clear();
+        instances.clear();
+    }
+
+    // Rearranges the primitives to match a handler's `indices`,
+    // which may reference a primitive more than once.
+    // The original order is kept, so meshes can still be edited
+    pub fn reorder(&mut self, indices: &[u32]) {
+        let Self::Active { prims, order, .. } = self else { return; };
+
+        if order.is_empty() {
+            order.extend(0..(prims.len() as u32));
+        }
+
+        *prims = indices.iter().map(|&idx| prims[idx as usize]).collect();
+        *order = indices.iter().map(|&idx| order[idx as usize]).collect();
+    }
+
+    // Undoes every `Scene::reorder`, dropping duplicated primitives
+    pub fn restore_order(&mut self) {
+        let Self::Active { prims, order, .. } = self else { return; };
+
+        if order.is_empty() { return; }
+
+        let count = order.iter().max().map_or(0, |&idx| idx as usize + 1);
+
+        let mut original = vec![bytemuck::Zeroable::zeroed(); count];
+        for (&idx, &prim) in order.iter().zip(prims.iter()) {
+            original[idx as usize] = prim;
+        }
+
+        *prims = original;
+
+        order.clear();
+    }
+}
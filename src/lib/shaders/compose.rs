@@ -0,0 +1,271 @@
+use std::{borrow, fmt};
+
+use crate::handlers;
+
+// A value substituted for `{{NAME}}` in a Module's source
+#[derive(Clone)]
+#[derive(Debug)]
+pub enum Param {
+    U32(u32),
+    Bool(bool),
+    // Substituted as is, such as a type or part of a name
+    Ident(borrow::Cow<'static, str>),
+}
+
+impl From<u32> for Param {
+    fn from(value: u32) -> Self { Self::U32(value) }
+}
+
+impl From<usize> for Param {
+    fn from(value: usize) -> Self { Self::U32(value as u32) }
+}
+
+impl From<bool> for Param {
+    fn from(value: bool) -> Self { Self::Bool(value) }
+}
+
+impl From<&'static str> for Param {
+    fn from(value: &'static str) -> Self { Self::Ident(value.into()) }
+}
+
+impl From<String> for Param {
+    fn from(value: String) -> Self { Self::Ident(value.into()) }
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::U32(value) => write!(f, "{value}u"),
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::Ident(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+// A named piece of WGSL.
+// Module-scope declarations can appear in any order,
+// so Composer only has to place one module after the other
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Module {
+    pub name: borrow::Cow<'static, str>,
+    pub source: borrow::Cow<'static, str>,
+    pub params: Vec<(&'static str, Param)>,
+    pub imports: Vec<Module>,
+}
+
+impl Module {
+    pub fn new(
+        name: impl Into<borrow::Cow<'static, str>>,
+        source: impl Into<borrow::Cow<'static, str>>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            source: source.into(),
+            params: Vec::new(),
+            imports: Vec::new(),
+        }
+    }
+
+    // Fills every `{{NAME}}` in the source
+    pub fn param(mut self, name: &'static str, value: impl Into<Param>) -> Self {
+        self.params.push((name, value.into())); self
+    }
+
+    // Declares a module this one depends on.
+    // Composer places it ahead of this one, once per shader
+    pub fn import(mut self, module: Module) -> Self {
+        self.imports.push(module); self
+    }
+
+    // Substitutes the params.
+    // Fails on a param the source never uses, or a placeholder left unfilled
+    fn resolve(&self) -> anyhow::Result<borrow::Cow<'static, str>> {
+        let Self { name, source, params, .. } = self;
+
+        let mut source = source.clone();
+        for (param, value) in params {
+            let placeholder = format!("{{{{{param}}}}}");
+
+            if !source.contains(&placeholder) {
+                anyhow::bail!("Module '{name}' has no template parameter {param}");
+            }
+
+            source = source.replace(&placeholder, &value.to_string()).into();
+        }
+
+        if let Some(idx) = source.find("{{") {
+            let placeholder = source[idx..]
+                .split_once("}}")
+                .map(|(placeholder, _)| &placeholder[2..])
+                .unwrap_or_default();
+
+            anyhow::bail!("Module '{name}' needs a value for template parameter {placeholder}");
+        }
+
+        Ok(source)
+    }
+}
+
+// A resource binding, declared ahead of the modules that use it
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+pub struct Binding<'a> {
+    pub group: u32,
+    pub binding: u32,
+    pub name: &'a str,
+    pub ty: &'a str,
+    pub buffer_ty: wgpu::BufferBindingType,
+}
+
+impl<'a> Binding<'a> {
+    // Binds each of a handler's variables in order
+    pub fn pack(
+        group: u32,
+        pack: &'a handlers::IntrsPack<'a>,
+    ) -> impl Iterator<Item = Self> + 'a {
+        pack.vars
+            .iter()
+            .enumerate()
+            .map(move |(binding, var)| Self {
+                group,
+                binding: binding as u32,
+                name: var.var_name,
+                ty: var.var_ty,
+                buffer_ty: var.buffer_ty,
+            })
+    }
+
+    fn decl(&self) -> String {
+        let Self { group, binding, name, ty, buffer_ty } = self;
+
+        let space = match buffer_ty {
+            wgpu::BufferBindingType::Uniform => //
+                "uniform",
+            wgpu::BufferBindingType::Storage { read_only: true } => //
+                "storage, read",
+            wgpu::BufferBindingType::Storage { read_only: false } => //
+                "storage, read_write",
+        };
+
+        format!("@group({group}) @binding({binding}) var<{space}> {name}: {ty};\n")
+    }
+}
+
+// Assembles modules and bindings into a single shader,
+// which is validated before it reaches the device
+#[derive(Default)]
+pub struct Composer<'a> {
+    bindings: Vec<Binding<'a>>,
+    modules: Vec<Module>,
+}
+
+impl<'a> Composer<'a> {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn module(mut self, module: Module) -> Self {
+        self.modules.push(module); self
+    }
+
+    pub fn bindings(mut self, bindings: impl IntoIterator<Item = Binding<'a>>) -> Self {
+        self.bindings.extend(bindings); self
+    }
+
+    pub fn compose(&self) -> anyhow::Result<String> {
+        let Self { bindings, modules } = self;
+
+        let mut source = String::new();
+
+        // The first line of each module, to attribute errors
+        let mut starts: Vec<(&str, usize)> = Vec::with_capacity(modules.len() + 1);
+
+        if !bindings.is_empty() {
+            starts.push(("bindings", source.lines().count() + 1));
+
+            for binding in bindings {
+                source.push_str(&binding.decl());
+            }
+        }
+
+        let mut included = Vec::new();
+        for module in modules {
+            include(module, &mut included)?;
+        }
+
+        for (name, resolved) in &included {
+            starts.push((name, source.lines().count() + 1));
+
+            source.push_str(resolved);
+            source.push('\n');
+        }
+
+        validate(&source, &starts)?;
+
+        Ok(source)
+    }
+}
+
+// Resolves a module after everything it imports.
+// A module imported twice is only included once,
+// provided both imports agree on its params
+fn include(
+    module: &Module,
+    included: &mut Vec<(borrow::Cow<'static, str>, borrow::Cow<'static, str>)>,
+) -> anyhow::Result<()> {
+    for import in &module.imports {
+        include(import, included)?;
+    }
+
+    let resolved = module.resolve()?;
+
+    match included.iter().find(|(name, _)| *name == module.name) {
+        Some((_, source)) if *source == resolved => Ok(()),
+        Some((name, _)) => {
+            anyhow::bail!("Module '{name}' is included twice with different template parameters")
+        },
+        None => {
+            included.push((module.name.clone(), resolved)); Ok(())
+        },
+    }
+}
+
+fn validate(source: &str, starts: &[(&str, usize)]) -> anyhow::Result<()> {
+    // Points at the module that contains the error
+    let context = |location: Option<naga::SourceLocation>| {
+        let Some(naga::SourceLocation { line_number, .. }) = location else {
+            return String::from("Invalid WGSL");
+        };
+
+        let line = line_number as usize;
+
+        match starts.iter().rev().find(|(_, start)| *start <= line) {
+            Some((name, start)) => format!(
+                "Invalid WGSL in module '{name}' at line {}",
+                line - start + 1,
+            ),
+            None => String::from("Invalid WGSL"),
+        }
+    };
+
+    let module = match naga::front::wgsl::parse_str(source) {
+        Ok(module) => module,
+        Err(e) => {
+            let report = e.emit_to_string_with_path(source, "composed.wgsl");
+
+            return Err(anyhow::anyhow!(report).context(context(e.location(source))));
+        },
+    };
+
+    let mut validator = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::default(),
+    );
+
+    if let Err(e) = validator.validate(&module) {
+        let report = e.emit_to_string_with_path(source, "composed.wgsl");
+
+        return Err(anyhow::anyhow!(report).context(context(e.location(source))));
+    }
+
+    Ok(())
+}
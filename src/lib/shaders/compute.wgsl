@@ -1,8 +1,10 @@
 // NOTE: This won't compile on its own.
-// The project automatically inserts intersection logic and 
-// additional bind groups based on the `IntrsHandler` that is passed to State.
-// This additional code is inserted before `main_cs` and MUST contain
-// a function with name and signature: `fn intrs(r: Ray) -> Intrs`
+// It is composed with the `IntrsHandler`'s bindings and logic (see `shaders::source`),
+// which MUST contain a function with name and signature: 
+// `fn intrs(r: Ray, excl: Excl) -> Intrs`.
+// It may also contain `fn occluded(r: Ray, t_max: f32, excl: Excl) -> bool`,
+// see `IntrsHandler::any_hit`.
+// Config, Prim, Vertex, Ray and Intrs are declared in types.wgsl
 
 //
 // Output Texture
@@ -21,15 +23,6 @@ var<uniform> size: Size;
 //
 // Config Declaration & Binding
 
-struct Config { 
-    t_min: f32, 
-    t_max: f32,
-    camera_light_source: f32,
-    bounces: u32,
-    eps: f32,
-    ambience: f32,
-}
-
 @group(1) @binding(0)
 var<uniform> config: Config;
 
@@ -44,23 +37,10 @@ var<uniform> camera: Camera;
 //
 // Geometry
 
-struct Prim {
-    a: u32, 
-    b: u32, 
-    c: u32,
-    material: i32,
-}
-
 // Array of raw primitives
 @group(2) @binding(1)
 var<storage, read> primitives: array<Prim>;
 
-// Vertex definition
-struct Vertex {
-    pos: vec3<f32>,
-    normal: vec3<f32>,
-}
-
 // Array of vertices
 @group(2) @binding(2)
 var<storage, read> vertices: array<Vertex>;
@@ -84,17 +64,23 @@ struct Material {
 @group(2) @binding(4)
 var<storage, read> materials: array<Material>;
 
-// Ray declaration
-struct Ray { origin: vec3<f32>, dir: vec3<f32>, }
+// Instance declaration
+// Scenes without instancing have a single identity instance
+struct Instance {
+    transform: mat4x4<f32>,
+    inverse: mat4x4<f32>,
+}
 
-// Intersection type declaration
-struct Intrs { s: Prim, t: f32, }
+// Array of instances
+@group(2) @binding(5)
+var<storage, read> instances: array<Instance>;
 
 struct Hit { 
     at: vec3<f32>, 
     normal: vec3<f32>, 
     s: Prim,
     t: f32,
+    inst: u32,
 }
 
 //
@@ -118,6 +104,14 @@ fn camera_ray(coord: vec2<i32>) -> Ray {
 }
 
 fn hit(intrs: Intrs, r: Ray) -> Hit {
+    let inst: Instance = instances[intrs.inst];
+
+    // The barycentric coordinates are found in object space.
+    // Directions aren't renormalized, so `t` is shared between spaces
+    let origin_obj = (inst.inverse * vec4<f32>(r.origin, 1.0)).xyz;
+    let dir_obj = (inst.inverse * vec4<f32>(r.dir, 0.0)).xyz;
+
+    let at_obj: vec3<f32> = origin_obj + (dir_obj * intrs.t);
     let at: vec3<f32> = r.origin + (r.dir * intrs.t);
     // NOTE: As of now, 
     // I have no explanation for why these need to be flipped...
@@ -127,7 +121,7 @@ fn hit(intrs: Intrs, r: Ray) -> Hit {
 
     let v0: vec3<f32> = b - a;
     let v1: vec3<f32> = c - a;
-    let v2: vec3<f32> = at - a;
+    let v2: vec3<f32> = at_obj - a;
 
     let d00: f32 = dot(v0, v0);
     let d01: f32 = dot(v0, v1);
@@ -145,9 +139,11 @@ fn hit(intrs: Intrs, r: Ray) -> Hit {
     let nb: vec3<f32> = vertices[intrs.s.b].normal * w;
     let nc: vec3<f32> = vertices[intrs.s.c].normal * u;
 
-    let normal = normalize(na + nb + nc);
+    // Normals are transformed by the inverse transpose
+    let normal_obj = vec4<f32>(na + nb + nc, 0.0);
+    let normal = normalize((normal_obj * inst.inverse).xyz);
 
-    return Hit(at, normal, intrs.s, intrs.t);
+    return Hit(at, normal, intrs.s, intrs.t, intrs.inst);
 }
 
 struct LightingPack {
@@ -183,32 +179,86 @@ fn intrs_valid(intrs: Intrs) -> bool {
 }
 
 fn intrs_empty() -> Intrs {
-    return Intrs(primitives[0], config.t_max + 1.0);
+    return Intrs(primitives[0], config.t_max + 1.0, 0u);
 }
 
-fn shadowed(pack: LightingPack) -> bool {
-    let light_dir = normalize(pack.light.pos - pack.hit.at);
-    let light_dist = length(pack.light.pos - pack.hit.at);
+// Matches SelfIntersection
+const SELF_INTERSECTION_EXCLUSION: u32 = 0u;
+const SELF_INTERSECTION_OFFSET: u32 = 1u;
 
-    var shadow_origin: vec3<f32>;
-    if(dot(light_dir, pack.hit.normal) < 0.0) {
-        shadow_origin = pack.hit.at - pack.hit.normal * 0.001;
-    } else {
-        shadow_origin = pack.hit.at + pack.hit.normal * 0.001;
+// A primitive that a ray should skip, and the instance it belongs to.
+// Instances share primitives, so handlers that traverse them
+// only skip the primitive within this instance
+struct Excl {
+    s: Prim,
+    inst: u32,
+}
+
+// Skips nothing, as the 'null' primitive is never hit
+fn excl_none() -> Excl {
+    return Excl(primitives[0], 0u);
+}
+
+// What rays leaving the hit should skip
+fn excluded(hit: Hit) -> Excl {
+    if(config.self_intersection != SELF_INTERSECTION_EXCLUSION) {
+        return excl_none();
     }
 
-    let shadow_ray: Ray = Ray(shadow_origin, light_dir);
+    return Excl(hit.s, hit.inst);
+}
 
-    let shadow_intrs = intrs(shadow_ray, pack.hit.s);
-    if(intrs_valid(shadow_intrs)) {
-        let shadow_hit = hit(shadow_intrs, shadow_ray);
+// Constants from the paper. Components closer to the origin
+// than 1/32 are moved by a fixed 1/65536 instead
+const OFFSET_ORIGIN: f32 = 0.03125;
+const OFFSET_FLOAT_SCALE: f32 = 0.0000152587890625;
+const OFFSET_INT_SCALE: f32 = 256.0;
+
+// Nudges a point along the normal by a few ULPs of each component,
+// so the margin scales with the distance from the origin.
+// From "A Fast and Robust Method for Avoiding Self-Intersection"
+// (Wächter & Binder, Ray Tracing Gems, 2019)
+fn offset_ray(p: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    let of_i = vec3<i32>(OFFSET_INT_SCALE * n);
+
+    let p_i = vec3<f32>(
+        bitcast<f32>(bitcast<i32>(p.x) + select(of_i.x, -of_i.x, p.x < 0.0)),
+        bitcast<f32>(bitcast<i32>(p.y) + select(of_i.y, -of_i.y, p.y < 0.0)),
+        bitcast<f32>(bitcast<i32>(p.z) + select(of_i.z, -of_i.z, p.z < 0.0)),
+    );
+
+    return vec3<f32>(
+        select(p_i.x, p.x + OFFSET_FLOAT_SCALE * n.x, abs(p.x) < OFFSET_ORIGIN),
+        select(p_i.y, p.y + OFFSET_FLOAT_SCALE * n.y, abs(p.y) < OFFSET_ORIGIN),
+        select(p_i.z, p.z + OFFSET_FLOAT_SCALE * n.z, abs(p.z) < OFFSET_ORIGIN),
+    );
+}
 
-        if(length(shadow_hit.at - shadow_origin) < light_dist) {
-            return true;
-        }
+// The origin of a ray leaving the hit in the given direction,
+// moved to the side of the surface that the ray travels into
+fn leave(hit: Hit, dir: vec3<f32>) -> vec3<f32> {
+    var normal = hit.normal;
+    if(dot(dir, normal) < 0.0) {
+        normal = -1.0 * normal;
+    }
+
+    if(config.self_intersection == SELF_INTERSECTION_OFFSET) {
+        return offset_ray(hit.at, normal);
     }
 
-    return false;
+    return hit.at + normal * 0.001;
+}
+
+fn shadowed(pack: LightingPack) -> bool {
+    let light_dir = normalize(pack.light.pos - pack.hit.at);
+
+    let shadow_origin = leave(pack.hit, light_dir);
+    let shadow_ray: Ray = Ray(shadow_origin, light_dir);
+
+    // The ray's direction is normalized, so `t` is the distance to the light
+    let light_dist = length(pack.light.pos - shadow_origin);
+
+    return occluded(shadow_ray, light_dist, excluded(pack.hit));
 }
 
 fn eq(a: Prim, b: Prim) -> bool {
@@ -219,10 +269,13 @@ fn eq(a: Prim, b: Prim) -> bool {
 fn lighting(camera_ray: Ray) -> vec3<f32> {
     var ray: Ray = camera_ray;
 
+    // Camera rays don't leave a surface
+    var excl: Excl = excl_none();
+
     var color: vec3<f32> = vec3<f32>(0.0);
 
     for(var i: u32 = 0u; i < config.bounces; i = i + 1u) {
-        let intrs: Intrs = intrs(ray, primitives[0]);
+        let intrs: Intrs = intrs(ray, excl);
         if(!intrs_valid(intrs)) { break; }
 
         let material: Material = materials[intrs.s.material];
@@ -266,22 +319,15 @@ fn lighting(camera_ray: Ray) -> vec3<f32> {
 
         let refl_dir = normalize(reflect(ray.dir, hit.normal));
 
-        var refl_origin: vec3<f32>;
-        if(dot(refl_dir, hit.normal) < 0.0) {
-            refl_origin = hit.at - hit.normal * 0.001;
-        } else {
-            refl_origin = hit.at + hit.normal * 0.001;
-        }
-
-        ray = Ray(refl_origin, refl_dir);
+        ray = Ray(leave(hit, refl_dir), refl_dir);
+        excl = excluded(hit);
     }
 
     return color;
 }
 
-// NOTE: The workgroup size is effected by config options, 
-// the x & y values are replaced at runtime
-@compute @workgroup_size(16, 16, 1)
+// NOTE: The workgroup size is effected by config options
+@compute @workgroup_size({{WORKGROUP}}, {{WORKGROUP}}, 1)
 fn main_cs(@builtin(global_invocation_id) id: vec3<u32>) {
     if(id.x < size.width && id.y < size.height) {
         let coord: vec2<i32> = vec2<i32>(i32(id.x), i32(id.y));
@@ -291,5 +337,3 @@ fn main_cs(@builtin(global_invocation_id) id: vec3<u32>) {
         textureStore(out, coord, vec4<f32>(color, 1.0));
     }
 }
-
-fn intrs(ray: Ray, excl: Prim) -> Intrs { return intrs_empty(); }
\ No newline at end of file
@@ -0,0 +1,425 @@
+// Builds a Linear BVH (LBVH) on the GPU, see `bvh::GpuBuilder`.
+// The hierarchy follows Karras (2012): given N primitives sorted by Morton code,
+// internal nodes are [0, N - 1) and leaves are [N - 1, 2N - 1)
+
+const WORKGROUP: u32 = {{WORKGROUP}};
+
+// Each pass of the radix sort handles a 4-bit digit
+const RADIX: u32 = 16u;
+
+const NONE: u32 = 0xFFFFFFFFu;
+
+struct Params {
+    count: u32,
+    // The offset of the current digit
+    shift: u32,
+    // The number of workgroups that cover `count` items
+    blocks: u32,
+}
+
+struct Prim {
+    a: u32,
+    b: u32,
+    c: u32,
+    material: i32,
+}
+
+struct Vertex {
+    pos: vec3<f32>,
+    normal: vec3<f32>,
+}
+
+// Matches bvh::AabbUniform
+struct Bounds {
+    min: vec3<f32>,
+    max: vec3<f32>,
+}
+
+struct Aabb {
+    fst: u32,
+    snd: u32,
+    item_idx: u32,
+    item_count: u32,
+    bounds: Bounds,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+
+@group(0) @binding(1)
+var<storage, read> primitives: array<Prim>;
+
+@group(0) @binding(2)
+var<storage, read> vertices: array<Vertex>;
+
+// The bounds of all centroids, encoded by `ordered`
+@group(0) @binding(3)
+var<storage, read_write> extent: array<atomic<u32>, 6>;
+
+@group(0) @binding(4)
+var<storage, read_write> keys_in: array<u32>;
+
+@group(0) @binding(5)
+var<storage, read_write> vals_in: array<u32>;
+
+@group(0) @binding(6)
+var<storage, read_write> keys_out: array<u32>;
+
+@group(0) @binding(7)
+var<storage, read_write> vals_out: array<u32>;
+
+// Digit-major, so its exclusive scan gives each block's offsets
+@group(0) @binding(8)
+var<storage, read_write> histogram: array<u32>;
+
+@group(0) @binding(9)
+var<storage, read_write> parents: array<u32>;
+
+// Six components per node, encoded by `ordered`
+@group(0) @binding(10)
+var<storage, read_write> fitted: array<atomic<u32>>;
+
+@group(0) @binding(11)
+var<storage, read_write> nodes: array<Aabb>;
+
+// Maps floats to integers that sort in the same order,
+// so the bounds can be reduced with atomicMin and atomicMax
+fn ordered(value: f32) -> u32 {
+    let bits = bitcast<u32>(value);
+
+    if((bits & 0x80000000u) != 0u) {
+        return ~bits;
+    }
+
+    return bits | 0x80000000u;
+}
+
+fn unordered(bits: u32) -> f32 {
+    if((bits & 0x80000000u) != 0u) {
+        return bitcast<f32>(bits & 0x7FFFFFFFu);
+    }
+
+    return bitcast<f32>(~bits);
+}
+
+fn centroid(s: Prim) -> vec3<f32> {
+    return (vertices[s.a].pos + vertices[s.b].pos + vertices[s.c].pos) / 3.0;
+}
+
+// Spreads the lower 10 bits so there are two zeroes between each
+fn expand(value: u32) -> u32 {
+    var v = value & 0x3FFu;
+
+    v = (v * 0x00010001u) & 0xFF0000FFu;
+    v = (v * 0x00000101u) & 0x0F00F00Fu;
+    v = (v * 0x00000011u) & 0xC30C30C3u;
+    v = (v * 0x00000005u) & 0x49249249u;
+
+    return v;
+}
+
+@compute @workgroup_size(WORKGROUP, 1, 1)
+fn extent_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if(id.x >= params.count) { return; }
+
+    let c = centroid(primitives[id.x]);
+
+    atomicMin(&extent[0], ordered(c.x));
+    atomicMin(&extent[1], ordered(c.y));
+    atomicMin(&extent[2], ordered(c.z));
+    atomicMax(&extent[3], ordered(c.x));
+    atomicMax(&extent[4], ordered(c.y));
+    atomicMax(&extent[5], ordered(c.z));
+}
+
+@compute @workgroup_size(WORKGROUP, 1, 1)
+fn morton_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if(id.x >= params.count) { return; }
+
+    let lo = vec3<f32>(
+        unordered(atomicLoad(&extent[0])),
+        unordered(atomicLoad(&extent[1])),
+        unordered(atomicLoad(&extent[2])),
+    );
+
+    let hi = vec3<f32>(
+        unordered(atomicLoad(&extent[3])),
+        unordered(atomicLoad(&extent[4])),
+        unordered(atomicLoad(&extent[5])),
+    );
+
+    // Flat scenes have no extent along some axis
+    let size = max(hi - lo, vec3<f32>(1e-20));
+
+    let p = clamp((centroid(primitives[id.x]) - lo) / size, vec3<f32>(0.0), vec3<f32>(1.0));
+    let q = vec3<u32>(p * 1023.0);
+
+    keys_in[id.x] = (expand(q.x) << 2u) | (expand(q.y) << 1u) | expand(q.z);
+    vals_in[id.x] = id.x;
+}
+
+var<workgroup> block_histogram: array<atomic<u32>, RADIX>;
+
+@compute @workgroup_size(WORKGROUP, 1, 1)
+fn histogram_main(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) block: vec3<u32>,
+) {
+    if(local < RADIX) {
+        atomicStore(&block_histogram[local], 0u);
+    }
+
+    workgroupBarrier();
+
+    if(id.x < params.count) {
+        let digit = (keys_in[id.x] >> params.shift) & (RADIX - 1u);
+
+        atomicAdd(&block_histogram[digit], 1u);
+    }
+
+    workgroupBarrier();
+
+    if(local < RADIX) {
+        histogram[local * params.blocks + block.x] = atomicLoad(&block_histogram[local]);
+    }
+}
+
+var<workgroup> scan_chunk: array<u32, WORKGROUP>;
+
+// An exclusive scan over the whole histogram, by a single workgroup
+@compute @workgroup_size(WORKGROUP, 1, 1)
+fn scan_main(@builtin(local_invocation_index) local: u32) {
+    let len = RADIX * params.blocks;
+
+    // The sum of every previous chunk
+    var carry = 0u;
+
+    for(var base = 0u; base < len; base = base + WORKGROUP) {
+        let idx = base + local;
+
+        var value = 0u;
+        if(idx < len) { value = histogram[idx]; }
+
+        scan_chunk[local] = value;
+
+        workgroupBarrier();
+
+        // Hillis-Steele inclusive scan
+        for(var offset = 1u; offset < WORKGROUP; offset = offset * 2u) {
+            var sum = scan_chunk[local];
+            if(local >= offset) { sum = sum + scan_chunk[local - offset]; }
+
+            workgroupBarrier();
+
+            scan_chunk[local] = sum;
+
+            workgroupBarrier();
+        }
+
+        if(idx < len) {
+            histogram[idx] = carry + scan_chunk[local] - value;
+        }
+
+        carry = carry + scan_chunk[WORKGROUP - 1u];
+
+        workgroupBarrier();
+    }
+}
+
+var<workgroup> block_digits: array<u32, WORKGROUP>;
+
+@compute @workgroup_size(WORKGROUP, 1, 1)
+fn scatter_main(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) block: vec3<u32>,
+) {
+    // Out-of-range invocations hold a digit that matches nothing
+    var digit = RADIX;
+    if(id.x < params.count) {
+        digit = (keys_in[id.x] >> params.shift) & (RADIX - 1u);
+    }
+
+    block_digits[local] = digit;
+
+    workgroupBarrier();
+
+    if(id.x >= params.count) { return; }
+
+    // Earlier items with the same digit keep their place, so the sort is stable
+    var rank = 0u;
+    for(var idx = 0u; idx < local; idx = idx + 1u) {
+        if(block_digits[idx] == digit) { rank = rank + 1u; }
+    }
+
+    let dst = histogram[digit * params.blocks + block.x] + rank;
+
+    keys_out[dst] = keys_in[id.x];
+    vals_out[dst] = vals_in[id.x];
+}
+
+// The length of the common prefix of two sorted keys.
+// Duplicate keys are told apart by their indices
+fn delta(i: i32, j: i32) -> i32 {
+    if(j < 0 || j >= i32(params.count)) { return -1; }
+
+    let ki = keys_in[i];
+    let kj = keys_in[j];
+
+    if(ki == kj) {
+        return 32 + i32(countLeadingZeros(u32(i) ^ u32(j)));
+    }
+
+    return i32(countLeadingZeros(ki ^ kj));
+}
+
+@compute @workgroup_size(WORKGROUP, 1, 1)
+fn hierarchy_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let count = params.count;
+
+    if(id.x >= 2u * count - 1u) { return; }
+
+    // Fitting starts from the empty bounds
+    for(var k = 0u; k < 3u; k = k + 1u) {
+        atomicStore(&fitted[id.x * 6u + k], NONE);
+        atomicStore(&fitted[id.x * 6u + k + 3u], 0u);
+    }
+
+    if(id.x == 0u) {
+        parents[0] = NONE;
+    }
+
+    // Leaves hold a single primitive, in its original position
+    if(id.x >= count - 1u) {
+        let leaf = id.x - (count - 1u);
+
+        nodes[id.x].fst = 0u;
+        nodes[id.x].snd = 0u;
+        nodes[id.x].item_idx = vals_in[leaf];
+        nodes[id.x].item_count = 1u;
+
+        return;
+    }
+
+    let i = i32(id.x);
+
+    // The direction of the node's range
+    let d = select(-1, 1, delta(i, i + 1) > delta(i, i - 1));
+
+    // Find the other end of the range
+    let delta_min = delta(i, i - d);
+
+    var l_max = 2;
+    while(delta(i, i + l_max * d) > delta_min) {
+        l_max = l_max * 2;
+    }
+
+    var l = 0;
+    for(var t = l_max / 2; t >= 1; t = t / 2) {
+        if(delta(i, i + (l + t) * d) > delta_min) {
+            l = l + t;
+        }
+    }
+
+    let j = i + l * d;
+
+    // Find where the range splits
+    let delta_node = delta(i, j);
+
+    var s = 0;
+    var div = 2;
+    var t = (l + div - 1) / div;
+    loop {
+        if(delta(i, i + (s + t) * d) > delta_node) {
+            s = s + t;
+        }
+
+        if(t <= 1) { break; }
+
+        div = div * 2;
+        t = (l + div - 1) / div;
+    }
+
+    let split = i + s * d + min(d, 0);
+
+    var fst = u32(split);
+    if(min(i, j) == split) { fst = fst + count - 1u; }
+
+    var snd = u32(split + 1);
+    if(max(i, j) == split + 1) { snd = snd + count - 1u; }
+
+    nodes[id.x].fst = fst;
+    nodes[id.x].snd = snd;
+    nodes[id.x].item_idx = 0u;
+    nodes[id.x].item_count = 0u;
+
+    parents[fst] = id.x;
+    parents[snd] = id.x;
+}
+
+@compute @workgroup_size(WORKGROUP, 1, 1)
+fn fit_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let count = params.count;
+
+    if(id.x >= count) { return; }
+
+    let leaf = id.x + count - 1u;
+
+    let s = primitives[nodes[leaf].item_idx];
+
+    let a = vertices[s.a].pos;
+    let b = vertices[s.b].pos;
+    let c = vertices[s.c].pos;
+
+    let lo = min(a, min(b, c));
+    let hi = max(a, max(b, c));
+
+    var components = array<u32, 6>(
+        ordered(lo.x), ordered(lo.y), ordered(lo.z),
+        ordered(hi.x), ordered(hi.y), ordered(hi.z),
+    );
+
+    // Walk towards the root, growing each ancestor.
+    // Once an ancestor already reaches a component, the invocation
+    // that put it there carries it the rest of the way
+    var pending = 0x3Fu;
+    var node = leaf;
+    while(node != NONE && pending != 0u) {
+        for(var k = 0u; k < 6u; k = k + 1u) {
+            if((pending & (1u << k)) == 0u) { continue; }
+
+            var old = 0u;
+            if(k < 3u) {
+                old = atomicMin(&fitted[node * 6u + k], components[k]);
+
+                if(old <= components[k]) { pending = pending & ~(1u << k); }
+            } else {
+                old = atomicMax(&fitted[node * 6u + k], components[k]);
+
+                if(old >= components[k]) { pending = pending & ~(1u << k); }
+            }
+        }
+
+        node = parents[node];
+    }
+}
+
+@compute @workgroup_size(WORKGROUP, 1, 1)
+fn finalize_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if(id.x >= 2u * params.count - 1u) { return; }
+
+    let base = id.x * 6u;
+
+    nodes[id.x].bounds.min = vec3<f32>(
+        unordered(atomicLoad(&fitted[base])),
+        unordered(atomicLoad(&fitted[base + 1u])),
+        unordered(atomicLoad(&fitted[base + 2u])),
+    );
+
+    nodes[id.x].bounds.max = vec3<f32>(
+        unordered(atomicLoad(&fitted[base + 3u])),
+        unordered(atomicLoad(&fitted[base + 4u])),
+        unordered(atomicLoad(&fitted[base + 5u])),
+    );
+}
@@ -0,0 +1,42 @@
+// Building blocks for handler logic, added with `Module::import`.
+// Each may be imported by several modules of the same shader
+
+use super::{Module, Param};
+
+// Config, Prim, Vertex, Ray and Intrs, as the compute shader declares them
+pub fn types() -> Module {
+    Module::new("types", include_str!("types.wgsl"))
+}
+
+// `intrs_tri`, which tests a single primitive
+// with the test selected by `config.triangle_test`
+pub fn tri() -> Module {
+    Module::new("tri", include_str!("tri.wgsl"))
+}
+
+// `box_collides` and `box_entry`, which test a ray against a box.
+// Also provides INF_POS, INF_NEG and the EPS wobble
+pub fn slab() -> Module {
+    Module::new("slab", include_str!("slab.wgsl"))
+}
+
+// A private stack of node indices, with `push` and `pop`
+pub fn stack(size: impl Into<Param>) -> Module {
+    Module::new("stack", include_str!("stack.wgsl"))
+        .param("PREFIX", "")
+        .param("ITEM", "u32")
+        .param("STACK_SIZE", size)
+}
+
+// A private stack of `item`, a type the importing module declares.
+// Declares `{name}_push` and `{name}_pop`, so a handler can keep several
+pub fn stack_named(
+    name: &'static str,
+    item: &'static str,
+    size: impl Into<Param>,
+) -> Module {
+    Module::new(format!("stack_{name}"), include_str!("stack.wgsl"))
+        .param("PREFIX", format!("{name}_"))
+        .param("ITEM", item)
+        .param("STACK_SIZE", size)
+}
@@ -1,12 +1,15 @@
-use std::{borrow, io};
+mod compose;
+pub mod library;
+pub use compose::{Binding, Composer, Module, Param};
 
 use crate::handlers;
 
 pub enum ShaderStage<'a, 'b: 'a> {
-    Compute { 
-        wg: u32, 
+    Compute {
+        wg: u32,
         pack: &'a handlers::IntrsPack<'b>,
-        logic: &'a str,
+        logic: Module,
+        any_hit: bool,
     },
     Render,
 }
@@ -14,77 +17,40 @@ pub enum ShaderStage<'a, 'b: 'a> {
 pub fn source<'a, 'b: 'a>(
     stage: ShaderStage<'a, 'b>,
 ) -> anyhow::Result<wgpu::ShaderSource<'static>> {
-    
-    // Helper function to find index of @compute declarative
-    fn main_cs_idx(source: &str) -> anyhow::Result<usize> {
-        let result = source.find("@compute").ok_or({
-            #[allow(unused_parens)]
-            io::Error::new(io::ErrorKind::InvalidData, ("\
-                Compute shader had no entry point [@compute], \
-                so the IntrsHandler's logic could not be inserted.\
-            "))
-        })?;
-
-        Ok(result)
-    }
-
     let source = match stage {
-        ShaderStage::Render => { //
-            include_str!("render.wgsl").into()
-        },
-        ShaderStage::Compute { wg, pack, logic, .. } => {
-            let source: &'static str = include_str!("compute.wgsl");
-
-            let source = source.replace(
-                "@workgroup_size(16, 16, 1)", 
-                &format!("@workgroup_size({}, {}, 1)", wg, wg)
-            );
-
-            // No more replacements from here on out
-            let mut source = source;
-
-            // Each group contains its own bindings
-            let handlers::IntrsPack { vars, .. } = pack;
-
-            // Construct and insert all binding statements
-            for (binding, var) in vars.iter().enumerate() {
-                let handlers::IntrsVar { 
-                    var_name, 
-                    var_ty, 
-                    buffer_ty, .. 
-                } = var;
-
-                let var_decl = match buffer_ty {
-                    wgpu::BufferBindingType::Uniform => //
-                        "var<uniform>",
-                    wgpu::BufferBindingType::Storage { read_only: true } => //
-                        "var<storage, read>",
-                    wgpu::BufferBindingType::Storage { read_only: false } => //
-                        "var<storage, read_write>",
-                };
-                
+        ShaderStage::Render => Composer::new()
+            .module(Module::new("render", include_str!("render.wgsl")))
+            .compose()?,
+        ShaderStage::Compute { wg, pack, logic, any_hit } => {
+            let compute = Module::new("compute", include_str!("compute.wgsl"))
+                .param("WORKGROUP", wg)
+                .import(library::types());
+
+            let composer = Composer::new()
                 // NOTE: group(3) is hard-coded
                 // See the same behavior in `State::update`
-                let binding = format!("
-                    @group(3) @binding({binding})
-                    {var_decl} {var_name}: {var_ty};
-                ");
-
-                // The insertion index changes each time
-                source.insert_str(main_cs_idx(&source)?, &binding);
+                .bindings(Binding::pack(3, pack))
+                .module(compute)
+                .module(logic);
+
+            if any_hit {
+                composer.compose()?
+            } else {
+                composer
+                    .module(Module::new("occluded", LOGIC_OCCLUDED))
+                    .compose()?
             }
-
-            // Add the intersection logic
-            let source = source.replace(LOGIC_DEFAULT, logic);
-
-            borrow::Cow::Borrowed({
-                Box::leak(source.into_boxed_str())
-            })
         },
     };
 
-    Ok(wgpu::ShaderSource::Wgsl(source))
+    Ok(wgpu::ShaderSource::Wgsl(source.into()))
 }
 
-const LOGIC_DEFAULT: &str = //
-    "fn intrs(ray: Ray, excl: Prim) -> Intrs { return intrs_empty(); }";
\ No newline at end of file
+// Used when the IntrsHandler has no any-hit query of its own
+const LOGIC_OCCLUDED: &str = "
+    fn occluded(r: Ray, t_max: f32, excl: Excl) -> bool {
+        let intrs = intrs(r, excl);
+
+        return intrs_valid(intrs) && intrs.t < t_max;
+    }
+";
@@ -0,0 +1,47 @@
+const INF_POS: f32 = 0x1.p+38f;
+const INF_NEG: f32 = -1.0 * INF_POS;
+
+// Wobble for the box tests below
+const EPS: f32 = 0.000002;
+
+// The distances at which the ray enters and leaves the box.
+// The box is missed if the ray leaves before it enters
+fn slab(lo: vec3<f32>, hi: vec3<f32>, ray: Ray) -> vec2<f32> {
+    var t0 = (lo.x - EPS - ray.origin.x) / ray.dir.x;
+    var t1 = (hi.x + EPS - ray.origin.x) / ray.dir.x;
+
+    var t_min = min(t0, t1);
+    var t_max = max(t0, t1);
+
+    t0 = (lo.y - EPS - ray.origin.y) / ray.dir.y;
+    t1 = (hi.y + EPS - ray.origin.y) / ray.dir.y;
+
+    t_min = max(t_min, min(min(t0, t1), INF_NEG));
+    t_max = min(t_max, max(max(t0, t1), INF_POS));
+
+    t0 = (lo.z - EPS - ray.origin.z) / ray.dir.z;
+    t1 = (hi.z + EPS - ray.origin.z) / ray.dir.z;
+
+    t_min = max(t_min, min(min(t0, t1), INF_NEG));
+    t_max = min(t_max, max(max(t0, t1), INF_POS));
+
+    return vec2<f32>(t_min, t_max);
+}
+
+fn box_collides(lo: vec3<f32>, hi: vec3<f32>, ray: Ray) -> bool {
+    let t = slab(lo, hi, ray);
+
+    return (t.x < t.y);
+}
+
+// The distance at which the ray enters the box,
+// or INF_POS if the box is missed entirely
+fn box_entry(lo: vec3<f32>, hi: vec3<f32>, ray: Ray) -> f32 {
+    let t = slab(lo, hi, ray);
+
+    if(t.x < t.y && t.y > 0.0) {
+        return max(t.x, 0.0);
+    }
+
+    return INF_POS;
+}
@@ -0,0 +1,15 @@
+// Items still to be visited, usually node indices.
+// NOTE: The size and item type are specified by the importing handler
+var<private> {{PREFIX}}stack: array<{{ITEM}}, {{STACK_SIZE}}>;
+
+fn {{PREFIX}}push(idx: ptr<function, u32>, item: {{ITEM}}) {
+    {{PREFIX}}stack[*idx] = item;
+
+    *idx = *idx + 1u;
+}
+
+fn {{PREFIX}}pop(idx: ptr<function, u32>) -> {{ITEM}} {
+    *idx = *idx - 1u;
+
+    return {{PREFIX}}stack[*idx];
+}
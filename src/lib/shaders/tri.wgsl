@@ -0,0 +1,111 @@
+// Intersects a ray with a single primitive,
+// using the test selected by `config.triangle_test`
+fn intrs_tri(r: Ray, s: Prim) -> Intrs {
+    if(config.triangle_test == TRIANGLE_TEST_WATERTIGHT) {
+        return intrs_watertight(r, s);
+    }
+
+    return intrs_moller_trumbore(r, s);
+}
+
+// From "Fast, Minimum Storage Ray/Triangle Intersection" (Möller & Trumbore, 1997)
+fn intrs_moller_trumbore(r: Ray, s: Prim) -> Intrs {
+    let e1: vec3<f32> = vertices[s.b].pos - vertices[s.a].pos;
+    let e2: vec3<f32> = vertices[s.c].pos - vertices[s.a].pos;
+
+    let p: vec3<f32> = cross(r.dir, e2);
+    let t: vec3<f32> = r.origin - vertices[s.a].pos;
+    let q: vec3<f32> = cross(t, e1);
+
+    let det = dot(e1, p);
+
+    var u: f32 = 0.0;
+    var v: f32 = 0.0;
+    if(det > config.eps) {
+        u = dot(t, p);
+        if(u < 0.0 || u > det) { return intrs_empty(); }
+
+        v = dot(r.dir, q);
+        if(v < 0.0 || u + v > det) { return intrs_empty(); }
+    } else if(det < -1.0 * config.eps) {
+        u = dot(t, p);
+        if(u > 0.0 || u < det) { return intrs_empty(); }
+
+        v = dot(r.dir, q);
+        if(v > 0.0 || u + v < det) { return intrs_empty(); }
+    } else {
+        return intrs_empty();
+    }
+
+    let w: f32 = dot(e2, q) / det;
+
+    if(w > config.t_max || w < config.t_min) {
+        return intrs_empty();
+    } else {
+        return Intrs(s, w, 0u);
+    }
+}
+
+// Matches TriangleTest
+const TRIANGLE_TEST_MOLLER_TRUMBORE: u32 = 0u;
+const TRIANGLE_TEST_WATERTIGHT: u32 = 1u;
+
+// Used by `intrs_tri` when the watertight test is selected.
+// The triangle is moved into a space where the ray runs along the z-axis,
+// so primitives that share an edge compute the same edge function
+// (with opposite signs), and rays can't slip between them.
+// From "Watertight Ray/Triangle Intersection" (Woop et al., JCGT 2013),
+// without its double precision fallback for edge functions that are
+// exactly zero (those rays hit both primitives instead). Drivers may
+// also contract the edge functions into FMAs, which can reopen the gaps;
+// see `geom::tri::watertight`
+fn intrs_watertight(r: Ray, s: Prim) -> Intrs {
+    // The largest component of the direction becomes z.
+    // Both windings are accepted, so the other axes are never swapped
+    let d = abs(r.dir);
+
+    var kz = 2u;
+    if(d.x > d.y && d.x > d.z) {
+        kz = 0u;
+    } else if(d.y > d.z) {
+        kz = 1u;
+    }
+
+    let kx = (kz + 1u) % 3u;
+    let ky = (kx + 1u) % 3u;
+
+    // Shears the direction onto the z-axis
+    let shear = vec3<f32>(r.dir[kx], r.dir[ky], 1.0) / r.dir[kz];
+
+    let a = vertices[s.a].pos - r.origin;
+    let b = vertices[s.b].pos - r.origin;
+    let c = vertices[s.c].pos - r.origin;
+
+    let ax = a[kx] - shear.x * a[kz];
+    let ay = a[ky] - shear.y * a[kz];
+    let bx = b[kx] - shear.x * b[kz];
+    let by = b[ky] - shear.y * b[kz];
+    let cx = c[kx] - shear.x * c[kz];
+    let cy = c[ky] - shear.y * c[kz];
+
+    // Scaled barycentric coordinates. Rays through an edge give zero,
+    // which counts as a hit for both of the primitives that share it
+    let u = cx * by - cy * bx;
+    let v = ax * cy - ay * cx;
+    let w = bx * ay - by * ax;
+
+    if((u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0)) {
+        return intrs_empty();
+    }
+
+    let det = u + v + w;
+    if(det == 0.0) { return intrs_empty(); }
+
+    let t = shear.z * (u * a[kz] + v * b[kz] + w * c[kz]) / det;
+
+    if(t > config.t_max || t < config.t_min) {
+        return intrs_empty();
+    } else {
+        return Intrs(s, t, 0u);
+    }
+}
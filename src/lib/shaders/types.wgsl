@@ -0,0 +1,33 @@
+// Types shared by the compute shader and the modules it imports.
+// Config, Prim and Vertex match ComputeConfig, geom::Prim and geom::PrimVertex
+
+struct Config { 
+    t_min: f32, 
+    t_max: f32,
+    camera_light_source: f32,
+    bounces: u32,
+    eps: f32,
+    ambience: f32,
+    self_intersection: u32,
+    triangle_test: u32,
+}
+
+struct Prim {
+    a: u32, 
+    b: u32, 
+    c: u32,
+    material: i32,
+}
+
+// Vertex definition
+struct Vertex {
+    pos: vec3<f32>,
+    normal: vec3<f32>,
+}
+
+// Ray declaration
+struct Ray { origin: vec3<f32>, dir: vec3<f32>, }
+
+// Intersection type declaration
+// `inst` is the instance that was hit, `s` is in its object space
+struct Intrs { s: Prim, t: f32, inst: u32, }
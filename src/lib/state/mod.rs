@@ -151,6 +151,7 @@ pub struct State<S: timing::Scheduler> {
     scheduler: S,
 
     // CPU-side of the intersection logic
+    handler: Box<dyn handlers::DynIntrsHandler>,
     pack_vars: handlers::IntrsPack<'static>,
     #[allow(dead_code)]
     pack_stats: handlers::IntrsStats,
@@ -170,6 +171,10 @@ pub struct State<S: timing::Scheduler> {
     #[allow(dead_code)]
     scene_buffers: Vec<wgpu::Buffer>,
 
+    // The scene as it was before the handler flattened its instances,
+    // so an instanced handler swapped in later can use them again
+    unflattened: Option<scene::Scene>,
+
     // Config buffers & group
     #[allow(dead_code)]
     config_buffer: wgpu::Buffer,
@@ -188,9 +193,9 @@ pub struct State<S: timing::Scheduler> {
 }
 
 impl<S: timing::Scheduler> State<S> {
-    pub async fn new<H: handlers::IntrsHandler>(
+    pub async fn new(
         config: crate::Config, 
-        config_handler: H::Config,
+        handler: Box<dyn handlers::DynIntrsHandler>,
         scene: &mut scene::Scene,
         window: sync::Arc<window::Window>,
     ) -> anyhow::Result<Self> {
@@ -198,39 +203,29 @@ impl<S: timing::Scheduler> State<S> {
         // All other state loads pass it back and forth
         let internals = StateInternals::new(window).await?;
 
-        match H::new(config_handler) {
-            Ok(handler) => {
-                match State::init(internals, config, scene, handler) {
-                    Ok(state) => Ok(state),
-                    Err((_, e)) => {
-                        // NOTE: When we load additional scenes after this,
-                        // we can always revert to the previous one on failure.
-                        // However, if a blank scene fails to load, 
-                        // there is almost certainly something VERY wrong.
-                        #[cfg(target_arch = "wasm32")]
-                        crate::web::note("Unable to configure empty scene on load")?;
-
-                        Err(e)
-                    },
-                }
-            },
-            Err(e) => {
+        match State::init(internals, config, scene, handler) {
+            Ok(state) => Ok(state),
+            Err((_, e)) => {
+                // NOTE: When we load additional scenes after this,
+                // we can always revert to the previous one on failure.
+                // However, if a blank scene fails to load, 
+                // there is almost certainly something VERY wrong.
                 #[cfg(target_arch = "wasm32")]
-                crate::web::note("Failed to initialize intersection handler")?;
+                crate::web::note("Unable to configure empty scene on load")?;
 
-                anyhow::bail!(e);
-            }
+                Err(e)
+            },
         }
     }
 
     // This function replaces self with a new state object
     // (that has initialized a new scene's data)
     #[cfg(target_arch = "wasm32")]
-    pub fn load<H: handlers::IntrsHandler>(
+    pub fn load(
         &mut self, 
         config: crate::Config, 
-        config_handler: H::Config,
-        scene: &scene::Scene,
+        handler: Box<dyn handlers::DynIntrsHandler>,
+        scene: &mut scene::Scene,
     ) -> anyhow::Result<()> {
         let internals = self.internals
             .take()
@@ -260,27 +255,15 @@ impl<S: timing::Scheduler> State<S> {
             config_buffer.destroy();
         }
 
-        match H::new(config_handler) {
-            Ok(handler) => {
-                match Self::init::<H>(internals, config, scene, handler) {
-                    Ok(state) => {
-                        destroy(self); 
-                        
-                        let _ = mem::replace(self, state);
-                    },
-                    Err((internals, e)) => {
-                        #[cfg(target_arch = "wasm32")]
-                        crate::web::note("Unable to load new scene")?;
-
-                        let _ = self.internals.insert(internals); Err(e)?;
-                    },
-                }
+        match Self::init(internals, config, scene, handler) {
+            Ok(state) => {
+                destroy(self); 
+                
+                let _ = mem::replace(self, state);
             },
-            Err(e) => {
-                // If handler construction fails, 
-                // try it again with a blank handler
+            Err((internals, e)) => {
                 #[cfg(target_arch = "wasm32")]
-                crate::web::note("Failed to initialize intersection handler")?;
+                crate::web::note("Unable to load new scene")?;
 
                 let _ = self.internals.insert(internals); Err(e)?;
             },
@@ -293,11 +276,12 @@ impl<S: timing::Scheduler> State<S> {
     // because we need to recover the StateInternals if
     // initialization fails.
     // By doing this, we can keep the program going on the current scene
-    fn init<H: handlers::IntrsHandler>(
+    #[allow(clippy::result_large_err)]
+    fn init(
         internals: StateInternals,
         config: crate::Config,
         scene: &mut scene::Scene,
-        handler: H,
+        handler: Box<dyn handlers::DynIntrsHandler>,
     ) -> Result<Self, (StateInternals, anyhow::Error)> {
         use wgpu::util::DeviceExt as _;
 
@@ -318,6 +302,31 @@ impl<S: timing::Scheduler> State<S> {
             }
         );
 
+        // The new handler's data refers to the primitives in their
+        // original order, not the order the last handler left them in
+        scene.restore_order();
+
+        // Most handlers can't traverse instances,
+        // so they are given a world-space copy of each one
+        let mut unflattened = None;
+        if !handler.instanced() && scene.has_instances() {
+            unflattened = Some(scene.clone());
+
+            scene.flatten();
+        }
+
+        // Collection of IntrsHandler-specific bindings
+        // NOTE: Handlers may reorder the scene's primitives,
+        // so this must happen before the scene is packed
+        let (pack_vars, pack_stats) = match handler.vars(scene, &internals.device) {
+            Ok(vars) => vars,
+            Err(e) => {
+                return Err((internals, e));
+            },
+        };
+
+        handler.build(&internals.device, &internals.queue);
+
         // Get all the buffers, groups associated with the scene
         // These fill group(3)
         let scene::ScenePack {
@@ -336,69 +345,13 @@ impl<S: timing::Scheduler> State<S> {
             }
         );
 
-        // A list of all entry layouts in the config group (2)
-        let mut config_group_layout_entries = vec![
-            wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                count: None,
-                ty: wgpu::BindingType::Buffer {
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                    ty: wgpu::BufferBindingType::Uniform,
-                }
-            },
-        ];
-
-        // A list of all entries in the config group (2)
-        let mut config_group_entries = vec![
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: config_buffer.as_entire_binding(),
-            },
-        ];
-
-        // Collection of IntrsHandler-specific bindings
-        let (pack_vars, pack_stats) = handler.vars(scene, &internals.device);
-
         // Frame scheduler + benchmark handler
         let scheduler = S::init(&internals.queue, &internals.device, pack_stats);
 
-        // The scheduler's buffers (if its using them)
-        // need to piggyback off group 2
-        // Otherwise, they will always be available to map
-        if let Some(scheduler_entry) = scheduler.entry() {
-            let timing::SchedulerEntry {
-                ty,
-                resource,
-            } = scheduler_entry;
-
-            config_group_layout_entries.push(wgpu::BindGroupLayoutEntry {
-                binding: config_group_layout_entries.len() as u32,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty,
-                count: None,
-            });
-
-            config_group_entries.push(wgpu::BindGroupEntry {
-                binding: config_group_entries.len() as u32,
-                resource,
-            });
-        }
-
-        let config_group_layout = internals.device.create_bind_group_layout(
-            &wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: &config_group_layout_entries,
-            }
-        );
-
-        let config_group = internals.device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                label: None,
-                layout: &config_group_layout,
-                entries: &config_group_entries,
-            }
+        let (config_group_layout, config_group) = Self::config_group(
+            &internals.device, 
+            &config_buffer, 
+            &scheduler,
         );
 
         // Build the render shader module
@@ -423,6 +376,7 @@ impl<S: timing::Scheduler> State<S> {
                     wg: config.resolution.wg(),
                     pack: &pack_vars,
                     logic: handler.logic(),
+                    any_hit: handler.any_hit(),
                 }) {
                     Ok(source) => source,
                     Err(e) => {
@@ -481,6 +435,7 @@ impl<S: timing::Scheduler> State<S> {
 
             scheduler,
 
+            handler,
             pack_vars,
             pack_stats,
 
@@ -493,6 +448,7 @@ impl<S: timing::Scheduler> State<S> {
             scene_group,
             scene_camera_buffer,
             scene_buffers,
+            unflattened,
 
             config_buffer,
             config_group_layout,
@@ -508,6 +464,75 @@ impl<S: timing::Scheduler> State<S> {
         })
     }
 
+    // The config group also carries the scheduler's buffers,
+    // so it is rebuilt along with the scheduler
+    fn config_group(
+        device: &wgpu::Device,
+        config_buffer: &wgpu::Buffer,
+        scheduler: &S,
+    ) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
+        // A list of all entry layouts in the config group (2)
+        let mut config_group_layout_entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                    ty: wgpu::BufferBindingType::Uniform,
+                }
+            },
+        ];
+
+        // A list of all entries in the config group (2)
+        let mut config_group_entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: config_buffer.as_entire_binding(),
+            },
+        ];
+
+        // The scheduler's buffers (if its using them)
+        // need to piggyback off group 2
+        // Otherwise, they will always be available to map
+        if let Some(scheduler_entry) = scheduler.entry() {
+            let timing::SchedulerEntry {
+                ty,
+                resource,
+            } = scheduler_entry;
+
+            config_group_layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding: config_group_layout_entries.len() as u32,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty,
+                count: None,
+            });
+
+            config_group_entries.push(wgpu::BindGroupEntry {
+                binding: config_group_entries.len() as u32,
+                resource,
+            });
+        }
+
+        let config_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &config_group_layout_entries,
+            }
+        );
+
+        let config_group = device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &config_group_layout,
+                entries: &config_group_entries,
+            }
+        );
+
+        (config_group_layout, config_group)
+    }
+
     pub fn resize_hard(&mut self, size: dpi::PhysicalSize<u32>) {
         let Self {
             internals: Some(StateInternals { device, queue, .. }),
@@ -728,6 +753,166 @@ impl<S: timing::Scheduler> State<S> {
         );
     }
 
+    // Writes the scene's updated vertices to the GPU and refits
+    // the handler's acceleration structure around them.
+    // Returns false if the State has to be rebuilt instead.
+    // NOTE: The scene's topology (its primitives) must not have changed
+    pub fn refit(&mut self, scene: &scene::Scene) -> bool {
+        let Self {
+            internals: Some(StateInternals { queue, .. }),
+            handler,
+            pack_vars,
+            scene_buffers, ..
+        } = self else { unreachable!(); };
+
+        let scene::Scene::Active { vertices, .. } = scene else {
+            return false;
+        };
+
+        let contents: &[u8] = bytemuck::cast_slice(vertices);
+
+        // Buffers can't grow, so vertices can only be moved
+        match scene_buffers.get(1) {
+            Some(buffer) if buffer.size() == contents.len() as u64 => {
+                queue.write_buffer(buffer, 0, contents);
+            },
+            _ => return false,
+        }
+
+        handler.refit(scene, queue, pack_vars)
+    }
+
+    // Replaces the intersection handler on the running State.
+    // The StateInternals are kept, as are the scene's buffers 
+    // unless the new handler changes their sizes (by flattening instances).
+    // If the new handler fails, the current one and the scene are left in place
+    pub fn swap(
+        &mut self,
+        config: crate::Config,
+        handler: Box<dyn handlers::DynIntrsHandler>,
+        scene: &mut scene::Scene,
+    ) -> anyhow::Result<()> {
+        let Some(StateInternals { 
+            device, 
+            queue, 
+            window_size, .. 
+        }) = &self.internals else { unreachable!(); };
+
+        let window_size = *window_size;
+
+        // The new handler works on a copy, which replaces the scene
+        // once it succeeds. An instanced handler starts from the scene
+        // as it was before flattening, seen from the current camera
+        let mut next = match &self.unflattened {
+            Some(unflattened) if handler.instanced() => {
+                let mut next = unflattened.clone();
+
+                if let (
+                    scene::Scene::Active { camera, camera_controller, .. },
+                    scene::Scene::Active { 
+                        camera: current, 
+                        camera_controller: current_controller, .. 
+                    },
+                ) = (&mut next, &*scene) {
+                    *camera = *current;
+                    *camera_controller = *current_controller;
+                }
+
+                next
+            },
+            _ => scene.clone(),
+        };
+
+        // The new handler's data refers to the primitives in their
+        // original order, not the order the last handler left them in
+        next.restore_order();
+
+        // Most handlers can't traverse instances,
+        // so they are given a world-space copy of each one
+        let mut unflattened = None;
+        if !handler.instanced() && next.has_instances() {
+            unflattened = Some(next.clone());
+
+            next.flatten();
+        }
+
+        let (pack_vars, pack_stats) = handler.vars(&mut next, device)?;
+
+        handler.build(device, queue);
+
+        let source = match shaders::source(shaders::ShaderStage::Compute {
+            wg: config.resolution.wg(),
+            pack: &pack_vars,
+            logic: handler.logic(),
+            any_hit: handler.any_hit(),
+        }) {
+            Ok(source) => source,
+            Err(e) => {
+                pack_vars.destroy(); 
+                
+                return Err(e);
+            },
+        };
+
+        let shader_compute = device.create_shader_module(
+            wgpu::ShaderModuleDescriptor { label: None, source, }
+        );
+
+        // The new handler may have reordered the scene's primitives
+        if !next.write(queue, &self.scene_buffers) {
+            let scene::ScenePack {
+                camera_buffer, 
+                buffers,
+                bg, 
+                bg_layout, ..
+            } = next.pack(device);
+
+            self.scene_camera_buffer.destroy();
+
+            for buffer in self.scene_buffers.iter() {
+                buffer.destroy();
+            }
+
+            self.scene_camera_buffer = camera_buffer;
+            self.scene_buffers = buffers;
+            self.scene_group = bg;
+            self.scene_group_layout = bg_layout;
+        }
+
+        // Benchmarks are kept separate for each handler
+        self.scheduler = S::init(queue, device, pack_stats);
+
+        (self.config_group_layout, self.config_group) = Self::config_group(
+            device, 
+            &self.config_buffer, 
+            &self.scheduler,
+        );
+
+        self.pack_vars.destroy();
+
+        // An already flattened scene keeps its original,
+        // until an instanced handler takes over
+        if handler.instanced() || unflattened.is_some() {
+            self.unflattened = unflattened;
+        }
+
+        *scene = next;
+
+        self.handler = handler;
+        self.pack_vars = pack_vars;
+        self.pack_stats = pack_stats;
+        self.shader_compute = shader_compute;
+
+        // Rebuild the pipelines around the new bind group layouts
+        self.resize_hard(match config.resolution {
+            crate::Resolution::Dynamic(_) => window_size,
+            crate::Resolution::Sized(size) => size,
+            crate::Resolution::Fixed { size, .. } => size,
+        });
+
+        Ok(())
+    }
+
     #[cfg(target_arch = "wasm32")]
     pub fn update_config(&mut self, config: crate::ComputeConfig) {
         let Self {
@@ -16,8 +16,8 @@ pub trait Scheduler {
         stats: handlers::IntrsStats
     ) -> Self;
 
-    fn entry(&self) -> Option<SchedulerEntry>;
-    fn desc(&self) -> wgpu::ComputePassDescriptor;
+    fn entry(&self) -> Option<SchedulerEntry<'_>>;
+    fn desc(&self) -> wgpu::ComputePassDescriptor<'_>;
     fn pre(&self, encoder: &mut wgpu::CommandEncoder);
     fn post(&self, queue: &wgpu::Queue, device: &wgpu::Device);
     fn ready(&mut self) -> bool;
@@ -66,7 +66,7 @@ impl Scheduler for DefaultScheduler {
         Some(entry)
     }
 
-    fn desc(&self) -> wgpu::ComputePassDescriptor {
+    fn desc(&self) -> wgpu::ComputePassDescriptor<'_> {
         wgpu::ComputePassDescriptor::default()
     }
 
@@ -152,7 +152,7 @@ impl Scheduler for BenchScheduler {
 
             loop {
                 match times_reciever.recv() {
-                    Ok(value) if value == 0. => continue,
+                    Ok(0.) => continue,
                     Ok(value) => {
                         // Begin computing running average
                         avg *= data.len() as f32;
@@ -218,7 +218,7 @@ impl Scheduler for BenchScheduler {
 
     fn entry(&self) -> Option<SchedulerEntry<'_>> { None }
 
-    fn desc(&self) -> wgpu::ComputePassDescriptor {
+    fn desc(&self) -> wgpu::ComputePassDescriptor<'_> {
         let Self { set: query_set, .. } = self;
 
         wgpu::ComputePassDescriptor {
@@ -350,6 +350,27 @@ fn graph(
                 .legend(format!("Size: {size} bytes"))
         };
 
+        // Compressed handlers trade precision for memory
+        let chart_compression = {
+            let handlers::IntrsStats { size, compression, .. } = stats;
+
+            compression
+                .map(|handlers::Compression { uncompressed, slack }| {
+                    let ratio = size as f32 / uncompressed.max(1) as f32 * 100.;
+
+                    vec![
+                        format!("Compression: {ratio:.1}% of {uncompressed} bytes"),
+                        format!("Bounds slack: {:.2}%", slack * 100.),
+                    ]
+                })
+                .unwrap_or_default()
+                .into_iter()
+                .map(|line| {
+                    repr::Plot::new(Vec::with_capacity(0))
+                        .legend(line)
+                }).collect::<Vec<_>>()
+        };
+
         let chart_avg = {
             let chart_avg = avg
                 .map(|avg| format!("Average: {avg}ms"))
@@ -359,9 +380,28 @@ fn graph(
                 .legend(chart_avg)
         };
 
-        view::ContinuousView::new()
+        // Each line of the BVH metrics gets its own legend entry
+        let chart_metrics = {
+            let handlers::IntrsStats { metrics, .. } = stats;
+
+            metrics
+                .map(|metrics| metrics.to_string())
+                .unwrap_or_default()
+                .lines()
+                .map(|line| {
+                    repr::Plot::new(Vec::with_capacity(0))
+                        .legend(String::from(line))
+                }).collect::<Vec<_>>()
+        };
+
+        let chart_view = view::ContinuousView::new()
             .add(chart_title)
-            .add(chart_size)
+            .add(chart_size);
+
+        chart_compression
+            .into_iter()
+            .chain(chart_metrics)
+            .fold(chart_view, |chart_view, chart| chart_view.add(chart))
             .add(chart_avg)
             .add(chart)
             .x_range(0., data.len() as f64)
@@ -33,8 +33,6 @@ mod err {
     }
 }
 
-pub type WebHandler = handlers::BvhIntrs;
-
 pub struct WebState {
     // These members are used for run_internal dispatch
     pub config: crate::Config,
@@ -43,6 +41,11 @@ pub struct WebState {
     pub scene: scene::Scene,
     pub scene_temp: Option<scene::Scene>,
 
+    // The intersection handler, chosen from JS.
+    // None selects the default, see `handler`
+    pub handler: Option<handlers::registry::HandlerDesc>,
+    pub handler_temp: Option<handlers::registry::HandlerDesc>,
+
     // These flags tell us when there is an update pending
     update_config: bool,
 
@@ -55,6 +58,8 @@ pub static mut WEB_STATE: WebState = WebState {
     update_config: true,
     scene: scene::Scene::Unloaded,
     scene_temp: None,
+    handler: None,
+    handler_temp: None,
     viewport: None,
 };
 
@@ -125,14 +130,33 @@ pub unsafe fn update<S>(state: &mut state::State<S>) -> bool
         update = true;
     }
 
-    if let Some(scene) = WEB_STATE.scene_temp.take() {
-        update = match state.load::<WebHandler>(
-            WEB_STATE.config, 
-            <WebHandler as handlers::IntrsHandler>::Config::default(),
-            &scene
-        ) {
+    if let Some(mut scene) = WEB_STATE.scene_temp.take() {
+        // A pending handler is loaded along with the scene
+        let desc = WEB_STATE.handler_temp
+            .take()
+            .or_else(|| WEB_STATE.handler.clone());
+
+        update = match load(state, desc.as_ref(), &mut scene) {
+            Ok(_) => {
+                WEB_STATE.scene = scene;
+                WEB_STATE.handler = desc; true
+            },
+            Err(_) => false,
+        };
+    } else if let Some(desc) = WEB_STATE.handler_temp.take() {
+        // Otherwise, the new handler is swapped in over the current scene
+        #[allow(static_mut_refs)]
+        let scene = &mut WEB_STATE.scene;
+
+        let swapped = handler(Some(&desc))
+            .map_err(|e| {
+                let _ = note("Failed to initialize intersection handler"); e
+            })
+            .and_then(|handler| state.swap(WEB_STATE.config, handler, scene));
+
+        update = match swapped {
             Ok(_) => {
-                WEB_STATE.scene = scene; true
+                WEB_STATE.handler = Some(desc); true
             },
             Err(_) => false,
         };
@@ -147,6 +171,35 @@ pub unsafe fn update<S>(state: &mut state::State<S>) -> bool
     update
 }
 
+// Used until JS selects a handler.
+// Must match the option preselected in static/index.html
+const DEFAULT_HANDLER: &str = "bvh";
+
+// Builds the handler that `desc` selects
+pub fn handler(
+    desc: Option<&handlers::registry::HandlerDesc>,
+) -> anyhow::Result<Box<dyn handlers::DynIntrsHandler>> {
+    match desc {
+        Some(desc) => desc.build(),
+        // The GPU builder is opt-in, through a configured descriptor
+        None => handlers::registry::build(DEFAULT_HANDLER, serde_json::Value::Null),
+    }
+}
+
+unsafe fn load<S>(
+    state: &mut state::State<S>,
+    desc: Option<&handlers::registry::HandlerDesc>,
+    scene: &mut scene::Scene,
+) -> anyhow::Result<()> 
+    where S: timing::Scheduler {
+
+    let handler = handler(desc).map_err(|e| {
+        let _ = note("Failed to initialize intersection handler"); e
+    })?;
+
+    state.load(WEB_STATE.config, handler, scene)
+}
+
 unsafe fn parse<'de, 'a: 'de, D>(
     serialized: wasm_bindgen::JsValue
 ) -> Result<D, wasm_bindgen::JsValue>
@@ -189,6 +242,21 @@ pub unsafe fn update_scene(
     Ok(())
 }
 
+// Accepts either a handler's name, such as `"bvh"`,
+// or `{ "name": "bvh", "config": { ... } }`
+#[no_mangle]
+#[cfg(target_arch = "wasm32")]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen::prelude::wasm_bindgen)]
+pub unsafe fn update_handler(
+    serialized: wasm_bindgen::JsValue
+) -> Result<(), crate::Failed> {
+    let _ = WEB_STATE.handler_temp.insert({
+        parse::<handlers::registry::HandlerDesc>(serialized)?
+    });
+
+    Ok(())
+}
+
 #[no_mangle]
 #[cfg(target_arch = "wasm32")]
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen::prelude::wasm_bindgen)]
@@ -26,6 +26,12 @@ fn main() -> anyhow::Result<()> {
                 .required(true)
                 .min_values(1)
                 .action(clap::ArgAction::Append))
+        .arg(
+            clap::Arg::new("instance")
+                .long("instance")
+                .number_of_values(5)
+                .value_parser(clap::value_parser!(f32))
+                .action(clap::ArgAction::Append))
         .arg(
             clap::Arg::new("camera-pos")
                 .long("camera-pos")
@@ -169,6 +175,9 @@ fn main() -> anyhow::Result<()> {
         vertices: Vec::new(),
         lights,
         materials,
+        meshes: Vec::new(),
+        instances: Vec::new(),
+        order: Vec::new(),
     };
 
     for (path, idx) in models {
@@ -182,6 +191,22 @@ fn main() -> anyhow::Result<()> {
         scene.add_mesh(obj, idx)?;
     }
 
+    // Each instance places a copy of one of the models above.
+    // If any are given, the models are only drawn through them
+    for values in parsed
+        .get_many::<f32>("instance")
+        .unwrap_or_default()
+        .copied()
+        .collect::<Vec<_>>()
+        .chunks_exact(5) {
+
+        let [mesh, x, y, z, scale] = values else { unreachable!(); };
+
+        let transform = geom::transform::from_translation_scale([*x, *y, *z], *scale);
+
+        scene.add_instance(*mesh as usize, transform)?;
+    }
+
     let out = parsed
         .get_one::<String>("out")
         .map(path::PathBuf::from)
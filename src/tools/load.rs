@@ -2,7 +2,8 @@ use std::{fs, io};
 
 use winit::dpi;
 
-use rt::{handlers, timing, scene};
+use rt::{bvh, handlers, timing, scene};
+use rt::handlers::IntrsHandler as _;
 
 #[derive(clap::Parser)]
 #[derive(Debug)]
@@ -20,8 +21,8 @@ use rt::{handlers, timing, scene};
         .multiple(true)
 ))]
 #[clap(group(
-    clap::ArgGroup::new("handler")
-        .args(&["handler-bvh", "handler-bvh-rf", "handler-naive"])
+    clap::ArgGroup::new("handlers")
+        .args(&["handler", "handler-bvh", "handler-bvh-rf", "handler-bvh-wide", "handler-bvh-quantized", "handler-bvh-threaded", "handler-grid", "handler-kd", "handler-octree", "handler-tlas", "handler-naive"])
         .multiple(false)
 ))]
 struct Args {
@@ -29,6 +30,11 @@ struct Args {
     #[clap(long, value_parser, default_value_t = String::from("scenes/default.json"))]
     path: String,
 
+    // Any registered handler, by name or as JSON:
+    // `bvh` or `{ "name": "bvh", "config": { ... } }`
+    #[clap(long = "handler", value_parser)]
+    handler: Option<String>,
+
     #[clap(long = "handler-naive", action)]
     handler_naive: bool,
 
@@ -39,7 +45,75 @@ struct Args {
     handler_bvh: Option<Vec<String>>,
 
     #[clap(long = "handler-bvh-rf", value_parser, min_values = 0, max_values = 1)]
-    handler_bvh_rf: Option<Vec<f32>>,
+    handler_bvh_rf: Option<Vec<String>>,
+
+    // Optionally takes the branching factor (4 or 8)
+    #[clap(long = "handler-bvh-wide", value_parser, min_values = 0, max_values = 1)]
+    handler_bvh_wide: Option<Vec<usize>>,
+
+    // Optionally takes the epsilon used to construct the BVH
+    #[clap(long = "handler-bvh-quantized", value_parser, min_values = 0, max_values = 1)]
+    handler_bvh_quantized: Option<Vec<f32>>,
+
+    // Optionally takes the epsilon used to construct the BVH
+    #[clap(long = "handler-bvh-threaded", value_parser, min_values = 0, max_values = 1)]
+    handler_bvh_threaded: Option<Vec<f32>>,
+
+    // Optionally takes the number of cells per primitive
+    #[clap(long = "handler-grid", value_parser, min_values = 0, max_values = 1)]
+    handler_grid: Option<Vec<f32>>,
+
+    // Overrides the grid's density with a fixed resolution
+    #[clap(long = "grid-resolution", value_parser, number_of_values = 3, requires = "handler-grid")]
+    grid_resolution: Option<Vec<u32>>,
+
+    // This argument takes a single value
+    // Either the maximum depth of the tree
+    // Or a path to a precomputed Kd-tree
+    #[clap(long = "handler-kd", value_parser, min_values = 0, max_values = 1)]
+    handler_kd: Option<Vec<String>>,
+
+    // Optionally takes the maximum depth of the octree
+    #[clap(long = "handler-octree", value_parser, min_values = 0, max_values = 1)]
+    handler_octree: Option<Vec<usize>>,
+
+    // The number of primitives below which octree nodes aren't subdivided
+    #[clap(long = "octree-leaf-size", value_parser, requires = "handler-octree")]
+    octree_leaf_size: Option<usize>,
+
+    // Builds a BVH per mesh and one over the scene's instances
+    // Optionally takes the epsilon used to construct the BLASes
+    #[clap(long = "handler-tlas", value_parser, min_values = 0, max_values = 1)]
+    handler_tlas: Option<Vec<f32>>,
+
+    // Construct BVHs with the SAH instead of midpoint splits
+    // Optionally takes the number of bins
+    #[clap(long = "sah", value_parser, min_values = 0, max_values = 1)]
+    sah: Option<Vec<usize>>,
+
+    // Construct BVHs with spatial splits (SBVH)
+    // Optionally takes the duplication budget
+    #[clap(long = "sbvh", value_parser, min_values = 0, max_values = 1, conflicts_with = "sah")]
+    sbvh: Option<Vec<f32>>,
+
+    // Construct BVHs from Morton codes (LBVH)
+    // Optionally takes the code precision (30 or 63 bits)
+    #[clap(long = "lbvh", value_parser, min_values = 0, max_values = 1, conflicts_with_all = &["sah", "sbvh"])]
+    lbvh: Option<Vec<u32>>,
+
+    // Visit the nearer child first and cull nodes beyond the closest hit
+    // Only affects --handler-bvh and --handler-bvh-rf
+    #[clap(long = "ordered", action)]
+    ordered: bool,
+
+    // Build the BVH of --handler-bvh with compute shaders
+    // Ignored when --handler-bvh is given an epsilon or a path
+    #[clap(long = "gpu-build", action, requires = "handler-bvh", conflicts_with_all = &["sah", "sbvh", "lbvh"])]
+    gpu_build: bool,
+
+    // Node encoding of --handler-bvh-rf (auto, compact or wide)
+    #[clap(long = "rf-encoding", value_parser)]
+    rf_encoding: Option<String>,
 
     #[clap(long = "benchmark", action)]
     benchmark: bool,
@@ -64,14 +138,22 @@ struct Args {
 
     #[clap(long = "ambience", value_parser)]
     compute_ambience: Option<f32>,
+
+    // How secondary rays avoid their own surface (exclusion or offset)
+    #[clap(long = "self-intersection", value_parser)]
+    compute_self_intersection: Option<String>,
+
+    // The ray-triangle test (moller-trumbore or watertight)
+    #[clap(long = "triangle-test", value_parser)]
+    compute_triangle_test: Option<String>,
 }
 
-fn start<H: handlers::IntrsHandler>(
+fn start(
     benchmark: bool,
     resolution: rt::Resolution, 
     fps: Option<u32>,
     config_compute: rt::ComputeConfig, 
-    config_handler: H::Config,
+    handler: Box<dyn handlers::DynIntrsHandler>,
     scene: scene::Scene,
 ) -> anyhow::Result<()> {
     let config_default = rt::Config::default();
@@ -83,13 +165,13 @@ fn start<H: handlers::IntrsHandler>(
     
     if benchmark {
         pollster::block_on({
-            rt::run_native::<H, timing::BenchScheduler>
-                (config, config_handler, scene)
+            rt::run_native::<timing::BenchScheduler>
+                (config, handler, scene)
         })
     } else {
         pollster::block_on({
-            rt::run_native::<H, timing::DefaultScheduler>
-                (config, config_handler, scene)
+            rt::run_native::<timing::DefaultScheduler>
+                (config, handler, scene)
         })
     }
 }
@@ -101,9 +183,25 @@ fn main() -> anyhow::Result<()> {
 
     let Args {
         path,
+        handler,
         handler_naive,
         handler_bvh,
         handler_bvh_rf,
+        handler_bvh_wide,
+        handler_bvh_quantized,
+        handler_bvh_threaded,
+        handler_grid,
+        grid_resolution,
+        handler_kd,
+        handler_octree,
+        octree_leaf_size,
+        handler_tlas,
+        sah,
+        sbvh,
+        lbvh,
+        ordered,
+        gpu_build,
+        rf_encoding,
         benchmark,
         width,
         height,
@@ -111,7 +209,9 @@ fn main() -> anyhow::Result<()> {
         fps,
         compute_bounces,
         compute_camera_light_source,
-        compute_ambience, ..
+        compute_ambience,
+        compute_self_intersection,
+        compute_triangle_test, ..
     } = args;
 
     let resolution =  match (width, height, workgroup_size) {
@@ -135,9 +235,60 @@ fn main() -> anyhow::Result<()> {
             .unwrap_or(config_compute_default.camera_light_source),
         ambience: compute_ambience
             .unwrap_or(config_compute_default.ambience),
+        self_intersection: match compute_self_intersection.as_deref() {
+            None => config_compute_default.self_intersection,
+            Some(name) => rt::SelfIntersection::from_name(name).ok_or({
+                anyhow::anyhow!("Flag --self-intersection expects one of: exclusion, offset")
+            })? as u32,
+        },
+        triangle_test: match compute_triangle_test.as_deref() {
+            None => config_compute_default.triangle_test,
+            Some(name) => rt::TriangleTest::from_name(name).ok_or({
+                anyhow::anyhow!("Flag --triangle-test expects one of: moller-trumbore, watertight")
+            })? as u32,
+        },
         ..Default::default()
     };
 
+    let strategy = match (sah.as_deref(), sbvh.as_deref(), lbvh.as_deref()) {
+        (None, None, None) => bvh::Strategy::Midpoint,
+        (Some([]), ..) => bvh::Strategy::Sah { bins: bvh::Strategy::BINS },
+        (Some([bins]), ..) => bvh::Strategy::Sah { bins: *bins },
+        (_, Some([]), _) => bvh::Strategy::Spatial { 
+            bins: bvh::Strategy::BINS, 
+            budget: bvh::Strategy::BUDGET,
+        },
+        (_, Some([budget]), _) => bvh::Strategy::Spatial { 
+            bins: bvh::Strategy::BINS, 
+            budget: *budget,
+        },
+        (.., Some([])) => bvh::Strategy::Linear { 
+            bits: bvh::Morton::default(),
+        },
+        (.., Some([bits])) => bvh::Strategy::Linear { 
+            bits: bvh::Morton::from_bits(*bits).ok_or({
+                anyhow::anyhow!("Flag --lbvh expects either 30 or 63 bits")
+            })?,
+        },
+        _ => unreachable!(),
+    };
+
+    // Only applies to trees built at startup
+    let precomputed_conflict = !matches!(strategy, bvh::Strategy::Midpoint);
+
+    let traversal = if ordered {
+        handlers::Traversal::Ordered
+    } else {
+        handlers::Traversal::Unordered
+    };
+
+    let encoding = match rf_encoding.as_deref() {
+        None => handlers::RfEncoding::default(),
+        Some(name) => handlers::RfEncoding::from_name(name).ok_or({
+            anyhow::anyhow!("Flag --rf-encoding expects one of: auto, compact, wide")
+        })?,
+    };
+
     let scene_reader = io::BufReader::new({
         fs::File::open(path)?
     });
@@ -145,25 +296,28 @@ fn main() -> anyhow::Result<()> {
     let scene: scene::Scene = //
         serde_json::from_reader(scene_reader)?;
 
-    if handler_naive {
-        start::<handlers::BasicIntrs>
-            (benchmark, resolution, fps, config_compute, (), scene)
+    let handler: Box<dyn handlers::DynIntrsHandler> = if let Some(desc) = handler {
+        handlers::registry::HandlerDesc::parse(&desc)?.build()?
+    } else if handler_naive {
+        Box::new(handlers::BasicIntrs::new(())?)
     } else if let Some(args) = handler_bvh {
-        use io::Read as _;
-
         let config_handler: handlers::BvhConfig = match args.len() {
-            0 => handlers::BvhConfig::Default,
+            0 if gpu_build => handlers::BvhConfig::Gpu(traversal),
+            0 => handlers::BvhConfig::Runtime { 
+                eps: handlers::BvhIntrs::default().eps, 
+                strategy, 
+                traversal,
+            },
             1 => {
                 match args[0].parse::<f32>() {
-                    Ok(eps) => handlers::BvhConfig::Runtime { eps, },
-                    Err(_) => match fs::File::open(&args[0]) {
-                        Ok(file) => {
-                            let bytes = file
-                                .bytes()
-                                .collect::<Result<Vec<_>, io::Error>>()?;
-
-                            handlers::BvhConfig::Bytes(bytes)
-                        },
+                    Ok(eps) => handlers::BvhConfig::Runtime { eps, strategy, traversal, },
+                    Err(_) => match fs::read(&args[0]) {
+                        // The tree is already built, so there's nothing to apply a strategy to
+                        Ok(_) if precomputed_conflict => anyhow::bail!("\
+                            Flags --sah, --sbvh and --lbvh can't be combined \
+                            with a precomputed BVH file given to --handler-bvh\
+                        "),
+                        Ok(bytes) => handlers::BvhConfig::Bytes(bytes, traversal),
                         Err(_) => anyhow::bail!("\
                             Flag --handler-bvh requires either:
                               - The path to a precomputed BVH file
@@ -175,19 +329,128 @@ fn main() -> anyhow::Result<()> {
             _ => unreachable!(),
         };
 
-        start::<handlers::BvhIntrs>
-            (benchmark, resolution, fps, config_compute, config_handler, scene)
+        Box::new(handlers::BvhIntrs::new(config_handler)?)
     } else if let Some(args) = handler_bvh_rf {
+        let config_handler: handlers::RfBvhConfig = match args.len() {
+            0 => handlers::RfBvhConfig::Runtime { 
+                eps: handlers::RfBvhIntrs::default().eps, 
+                strategy, 
+                traversal,
+                encoding,
+            },
+            1 => {
+                match args[0].parse::<f32>() {
+                    Ok(eps) => handlers::RfBvhConfig::Runtime { eps, strategy, traversal, encoding, },
+                    Err(_) => match fs::read(&args[0]) {
+                        // The tree is already built, so there's nothing to apply a strategy to
+                        Ok(_) if precomputed_conflict => anyhow::bail!("\
+                            Flags --sah, --sbvh and --lbvh can't be combined \
+                            with a precomputed BVH file given to --handler-bvh-rf\
+                        "),
+                        Ok(bytes) => handlers::RfBvhConfig::Bytes(bytes, traversal, encoding),
+                        Err(_) => anyhow::bail!("\
+                            Flag --handler-bvh-rf requires either:
+                              - The path to a precomputed BVH file
+                              - An epsilon value (f32)\
+                        "),
+                    },
+                }
+            },
+            _ => unreachable!(),
+        };
+
+        Box::new(handlers::RfBvhIntrs::new(config_handler)?)
+    } else if let Some(args) = handler_bvh_wide {
+        let config_handler = handlers::WideBvhConfig::Runtime {
+            eps: handlers::WideBvhIntrs::default().eps,
+            strategy,
+            width: match args.len() {
+                0 => handlers::WideBvhIntrs::default().width,
+                1 => args[0],
+                _ => unreachable!(),
+            },
+        };
+
+        Box::new(handlers::WideBvhIntrs::new(config_handler)?)
+    } else if let Some(args) = handler_bvh_quantized {
+        let config_handler = handlers::QuantizedBvhConfig::Runtime {
+            eps: match args.len() {
+                0 => handlers::QuantizedBvhIntrs::default().eps,
+                1 => args[0],
+                _ => unreachable!(),
+            },
+            strategy,
+        };
+
+        Box::new(handlers::QuantizedBvhIntrs::new(config_handler)?)
+    } else if let Some(args) = handler_bvh_threaded {
+        let config_handler = match args.len() {
+            0 => handlers::ThreadedBvhConfig::Runtime { 
+                eps: handlers::ThreadedBvhIntrs::default().eps, 
+                strategy, 
+            },
+            1 => handlers::ThreadedBvhConfig::Runtime { eps: args[0], strategy, },
+            _ => unreachable!(),
+        };
+
+        Box::new(handlers::ThreadedBvhIntrs::new(config_handler)?)
+    } else if let Some(args) = handler_grid {
+        let config_handler = match (args.as_slice(), grid_resolution.as_deref()) {
+            (_, Some(&[x, y, z])) => handlers::GridConfig::Resolution([x, y, z]),
+            ([], None) => handlers::GridConfig::Default,
+            ([density], None) => handlers::GridConfig::Density(*density),
+            _ => unreachable!(),
+        };
+
+        Box::new(handlers::GridIntrs::new(config_handler)?)
+    } else if let Some(args) = handler_kd {
         let config_handler = match args.len() {
-            0 => handlers::RfBvhConfig::default(),
-            1 => handlers::RfBvhConfig::Eps(args[0]),
+            0 => handlers::KdTreeConfig::Default,
+            1 => {
+                match args[0].parse::<usize>() {
+                    Ok(max_depth) => handlers::KdTreeConfig::Runtime { 
+                        max_depth: Some(max_depth), 
+                    },
+                    Err(_) => match fs::read(&args[0]) {
+                        Ok(bytes) => handlers::KdTreeConfig::Bytes(bytes),
+                        Err(_) => anyhow::bail!("\
+                            Flag --handler-kd requires either:
+                              - The path to a precomputed Kd-tree file
+                              - A maximum depth (usize)\
+                        "),
+                    },
+                }
+            },
             _ => unreachable!(),
         };
 
-        start::<handlers::RfBvhIntrs>
-            (benchmark, resolution, fps, config_compute, config_handler, scene)
+        Box::new(handlers::KdTreeIntrs::new(config_handler)?)
+    } else if let Some(args) = handler_octree {
+        let config_handler = handlers::OctreeConfig::Runtime {
+            max_depth: match args.len() {
+                0 => handlers::OctreeIntrs::default().max_depth,
+                1 => args[0],
+                _ => unreachable!(),
+            },
+            leaf_size: octree_leaf_size
+                .unwrap_or(handlers::OctreeIntrs::default().leaf_size),
+        };
+
+        Box::new(handlers::OctreeIntrs::new(config_handler)?)
+    } else if let Some(args) = handler_tlas {
+        let config_handler = match args.len() {
+            0 => handlers::TlasConfig::Runtime { 
+                eps: handlers::TlasIntrs::default().eps, 
+                strategy, 
+            },
+            1 => handlers::TlasConfig::Runtime { eps: args[0], strategy, },
+            _ => unreachable!(),
+        };
+
+        Box::new(handlers::TlasIntrs::new(config_handler)?)
     } else {
-        start::<handlers::BlankIntrs>
-            (benchmark, resolution, fps, config_compute, (), scene)
-    }
+        Box::new(handlers::BlankIntrs::new(())?)
+    };
+
+    start(benchmark, resolution, fps, config_compute, handler, scene)
 }
\ No newline at end of file
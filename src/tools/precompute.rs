@@ -1,6 +1,6 @@
-use std::{io, fs, path};
+use std::{io, fs, path, time};
 
-use rt::{bvh, handlers};
+use rt::{bvh, handlers, kd};
 
 fn main() -> anyhow::Result<()> {
     use std::io::Write as _;
@@ -26,7 +26,50 @@ fn main() -> anyhow::Result<()> {
                 .long("item-count")
                 .number_of_values(1)
                 .value_parser(clap::value_parser!(usize))
-                .required(true))
+                .required_unless_present("kd-tree"))
+        .arg(
+            clap::Arg::new("sah")
+                .long("sah")
+                .min_values(0)
+                .max_values(1)
+                .value_parser(clap::value_parser!(usize)))
+        .arg(
+            clap::Arg::new("sbvh")
+                .long("sbvh")
+                .min_values(0)
+                .max_values(1)
+                .conflicts_with("sah")
+                .value_parser(clap::value_parser!(f32)))
+        .arg(
+            clap::Arg::new("lbvh")
+                .long("lbvh")
+                .min_values(0)
+                .max_values(1)
+                .conflicts_with_all(&["sah", "sbvh"])
+                .value_parser(clap::value_parser!(u32)))
+        .arg(
+            clap::Arg::new("optimize")
+                .long("optimize")
+                .min_values(0)
+                .max_values(1)
+                .value_parser(clap::value_parser!(usize)))
+        .arg(
+            clap::Arg::new("optimize-secs")
+                .long("optimize-secs")
+                .number_of_values(1)
+                .conflicts_with("optimize")
+                .value_parser(clap::value_parser!(f32)))
+        .arg(
+            clap::Arg::new("kd-tree")
+                .long("kd-tree")
+                .conflicts_with_all(&["eps", "item-count", "sah", "sbvh", "lbvh", "optimize", "optimize-secs"])
+                .action(clap::ArgAction::SetTrue))
+        .arg(
+            clap::Arg::new("max-depth")
+                .long("max-depth")
+                .number_of_values(1)
+                .requires("kd-tree")
+                .value_parser(clap::value_parser!(usize)))
         .get_matches();
 
     let out = parsed
@@ -44,6 +87,18 @@ fn main() -> anyhow::Result<()> {
 
     let scene = serde_json::from_reader(scene_reader)?;
 
+    // Kd-trees share none of the BVH options
+    if parsed.get_flag("kd-tree") {
+        let max_depth = parsed.get_one::<usize>("max-depth").copied();
+
+        let kd = kd::KdData::from_scene(&scene, max_depth);
+
+        fs::File::create(out)?
+            .write_all(serde_json::to_string(&kd)?.as_bytes())?;
+
+        return Ok(());
+    }
+
     let eps = match parsed.get_one::<f32>("eps") {
         Some(eps) => *eps,
         None => handlers::BvhIntrs::default().eps,
@@ -54,9 +109,67 @@ fn main() -> anyhow::Result<()> {
         .get_one::<>("item-count")
         .unwrap();
 
-    let bvh = rt::bvh::BvhData::new({
-        &bvh::Aabb::from_scene(eps, &scene, *item_count)
-    });
+    // The SAH bin count is optional
+    let strategy = if parsed.contains_id("sah") {
+        let bins = parsed
+            .get_one::<usize>("sah")
+            .copied()
+            .unwrap_or(bvh::Strategy::BINS);
+
+        bvh::Strategy::Sah { bins }
+    } else if parsed.contains_id("sbvh") {
+        // As is the SBVH duplication budget
+        let budget = parsed
+            .get_one::<f32>("sbvh")
+            .copied()
+            .unwrap_or(bvh::Strategy::BUDGET);
+
+        bvh::Strategy::Spatial { bins: bvh::Strategy::BINS, budget }
+    } else if parsed.contains_id("lbvh") {
+        // Morton code precision defaults to 30 bits
+        let bits = match parsed.get_one::<u32>("lbvh").copied() {
+            Some(bits) => bvh::Morton::from_bits(bits).ok_or({
+                anyhow::anyhow!("Flag --lbvh expects either 30 or 63 bits")
+            })?,
+            None => bvh::Morton::default(),
+        };
+
+        bvh::Strategy::Linear { bits }
+    } else {
+        bvh::Strategy::Midpoint
+    };
+
+    // Optimization is bounded by either a pass count or a duration
+    let budget = if let Some(secs) = parsed.get_one::<f32>("optimize-secs") {
+        Some(bvh::Budget::Time(time::Duration::from_secs_f32(*secs)))
+    } else if parsed.contains_id("optimize") {
+        let passes = parsed
+            .get_one::<usize>("optimize")
+            .copied()
+            .unwrap_or(bvh::Budget::ITERATIONS);
+
+        Some(bvh::Budget::Iterations(passes))
+    } else {
+        None
+    };
+
+    let bvh = match budget {
+        Some(budget) => {
+            let mut aabb = bvh::Aabb::from_scene(eps, &scene, *item_count, strategy);
+
+            // Report the tree before optimization for comparison
+            println!("{}\n", bvh::BvhData::new(&aabb).metrics());
+
+            let improved = aabb.optimize(budget);
+
+            println!("Improved {improved} treelets\n");
+
+            bvh::BvhData::new(&aabb)
+        },
+        None => bvh::BvhData::from_scene(eps, &scene, *item_count, strategy),
+    };
+
+    println!("{}", bvh.metrics());
     
     fs::File::create(out)?
         .write_all(serde_json::to_string(&bvh)?.as_bytes())?;
@@ -0,0 +1,66 @@
+// Rays leaving a surface mustn't hit it again. Without blocks,
+// nothing on the acne scene's plane is in shadow, so any unlit pixel is acne
+
+mod common;
+
+use rt::{handlers, scene};
+
+// The plane fills the whole frame from here
+const CAMERA: ([f32; 3], [f32; 3]) = (
+    [65526., 65561., 65536.],
+    [65536., 65536., 65536.],
+);
+
+// The BVH handler's stack holds every node, which is too slow
+// on software adapters. TLAS also covers instanced exclusion
+const HANDLERS: [&str; 3] = ["naive", "bvh-threaded", "tlas"];
+
+// Returns None if there's no adapter
+fn unlit(name: &str, self_intersection: rt::SelfIntersection) -> Option<usize> {
+    let Some((device, queue)) = common::device() else {
+        eprintln!("No adapter, skipping the acne test");
+
+        return None;
+    };
+
+    let mut scene = common::acne::acne(false);
+
+    if let scene::Scene::Active { camera, .. } = &mut scene {
+        *camera = scene::CameraUniform::new(CAMERA.0, CAMERA.1);
+    }
+
+    let handler = handlers::registry::build(name, serde_json::Value::Null).unwrap();
+
+    let config = rt::ComputeConfig {
+        self_intersection: self_intersection as u32,
+        ..Default::default()
+    };
+
+    // Ambience isn't applied, so shadows are black
+    let unlit = common::render(&device, &queue, handler, &mut scene, config, 64)
+        .into_iter()
+        .filter(|&[r, g, b, _]| r == 0 && g == 0 && b == 0)
+        .count();
+
+    Some(unlit)
+}
+
+#[test]
+fn offset() {
+    for name in HANDLERS {
+        if let Some(unlit) = unlit(name, rt::SelfIntersection::Offset) {
+            assert_eq!(unlit, 0, "{name}");
+        }
+    }
+}
+
+// Exclusion skips the primitive that was hit, but not its neighbors.
+// This shows that the scene has acne for the offset to remove
+#[test]
+fn exclusion() {
+    for name in HANDLERS {
+        if let Some(unlit) = unlit(name, rt::SelfIntersection::Exclusion) {
+            assert!(unlit > 0, "{name}");
+        }
+    }
+}
@@ -0,0 +1,118 @@
+// Generates a scene prone to shadow acne: a finely tessellated ground plane
+// far from the origin, lit at a grazing angle, with a few boxes
+// casting long shadows. The whole scene is tilted slightly,
+// so hit points don't land exactly on the plane
+
+use rt::{geom, scene};
+use rt::geom::light;
+
+// Far enough from the origin that a float's ULP is about 1/128
+const ORIGIN: f64 = 65536.;
+
+const TILT: f64 = 3. * std::f64::consts::PI / 180.;
+
+// The plane is split into a grid of this many cells along each side
+const CELLS: usize = 48;
+const EXTENT: f64 = 96.;
+
+#[derive(Default)]
+struct Builder {
+    prims: Vec<geom::Prim>,
+    vertices: Vec<geom::PrimVertex>,
+}
+
+impl Builder {
+    // Adds a vertex relative to the origin of the scene.
+    // Returns its index
+    fn vertex(&mut self, pos: [f64; 3], normal: [f64; 3]) -> u32 {
+        let pos = tilt(pos).map(|c| (c + ORIGIN) as f32);
+        let normal = tilt(normal).map(|c| c as f32);
+
+        self.vertices.push(geom::PrimVertex::new(pos, normal));
+
+        (self.vertices.len() - 1) as u32
+    }
+
+    fn tri(&mut self, indices: [u32; 3], material: i32) {
+        self.prims.push(geom::Prim { indices, material });
+    }
+
+    // A box without a bottom, standing on the plane
+    fn block(&mut self, [x, z]: [f64; 2], width: f64, height: f64) {
+        let faces = [
+            ([0., 1., 0.], [[-1., 1., -1.], [-1., 1., 1.], [1., 1., 1.], [1., 1., -1.]]),
+            ([1., 0., 0.], [[1., 0., -1.], [1., 1., -1.], [1., 1., 1.], [1., 0., 1.]]),
+            ([-1., 0., 0.], [[-1., 0., -1.], [-1., 0., 1.], [-1., 1., 1.], [-1., 1., -1.]]),
+            ([0., 0., 1.], [[-1., 0., 1.], [1., 0., 1.], [1., 1., 1.], [-1., 1., 1.]]),
+            ([0., 0., -1.], [[-1., 0., -1.], [-1., 1., -1.], [1., 1., -1.], [1., 0., -1.]]),
+        ];
+
+        for (normal, corners) in faces {
+            let [a, b, c, d] = corners.map(|[cx, cy, cz]| {
+                self.vertex([x + cx * width, cy * height, z + cz * width], normal)
+            });
+
+            self.tri([a, b, c], 1);
+            self.tri([a, c, d], 1);
+        }
+    }
+}
+
+fn tilt([x, y, z]: [f64; 3]) -> [f64; 3] {
+    let (sin, cos) = TILT.sin_cos();
+
+    [x * cos - y * sin, x * sin + y * cos, z]
+}
+
+// Without `blocks`, nothing on the plane should be in shadow
+pub fn acne(blocks: bool) -> scene::Scene {
+    let mut builder = Builder::default();
+
+    let step = EXTENT / CELLS as f64;
+
+    for j in 0..=CELLS {
+        for i in 0..=CELLS {
+            let x = -EXTENT / 2. + i as f64 * step;
+            let z = -EXTENT / 2. + j as f64 * step;
+
+            builder.vertex([x, 0., z], [0., 1., 0.]);
+        }
+    }
+
+    for j in 0..CELLS {
+        for i in 0..CELLS {
+            let a = (j * (CELLS + 1) + i) as u32;
+            let b = a + 1;
+            let c = a + CELLS as u32 + 1;
+            let d = c + 1;
+
+            builder.tri([a, c, b], 0);
+            builder.tri([b, c, d], 0);
+        }
+    }
+
+    if blocks {
+        builder.block([-12., 6.], 3., 6.);
+        builder.block([8., -10.], 2., 9.);
+        builder.block([14., 14.], 4., 3.);
+    }
+
+    let Builder { prims, vertices } = builder;
+
+    let at = |[x, y, z]: [f64; 3]| [x, y, z].map(|c| (c + ORIGIN) as f32);
+
+    scene::Scene::Active {
+        camera: scene::CameraUniform::new(at([-40., 25., -40.]), at([0.; 3])),
+        camera_controller: scene::CameraController::Orbit { left: false, right: false },
+        prims,
+        vertices,
+        lights: vec![light::Light { pos: at([-120., 10., 30.]), strength: 1.6 }],
+        materials: vec![
+            geom::PrimMat::new([0.8, 0.8, 0.75], [0.9, 0.1, 0.], 10.),
+            geom::PrimMat::new([0.6, 0.2, 0.1], [0.8, 0.2, 0.], 50.),
+        ],
+        meshes: Vec::new(),
+        instances: Vec::new(),
+        order: Vec::new(),
+    }
+}
@@ -0,0 +1,240 @@
+// Helpers shared by the integration tests
+#![allow(dead_code)]
+
+pub mod acne;
+
+use std::{fs, io, path};
+
+use rt::{bvh, handlers, scene, shaders};
+
+pub fn load(name: &str) -> scene::Scene {
+    let path = path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("scenes")
+        .join(name);
+
+    let reader = io::BufReader::new(fs::File::open(path).unwrap());
+
+    serde_json::from_reader(reader).unwrap()
+}
+
+// Whether `outer` encloses `inner`
+pub fn contains(outer: bvh::Bounds, inner: bvh::Bounds) -> bool {
+    (0..3).all(|axis| {
+        outer.min[axis] <= inner.min[axis] && inner.max[axis] <= outer.max[axis]
+    })
+}
+
+// Machines without an adapter skip the tests that need one
+pub fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+
+    let adapter = pollster::block_on({
+        instance.request_adapter(&wgpu::RequestAdapterOptions::default())
+    })?;
+
+    pollster::block_on({
+        adapter.request_device(&wgpu::DeviceDescriptor::default(), None)
+    }).ok()
+}
+
+// Copies a buffer back to the CPU
+pub fn read<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    mut encoder: wgpu::CommandEncoder,
+    buffer: &wgpu::Buffer,
+) -> Vec<T> {
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: buffer.size(),
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, buffer.size());
+
+    queue.submit(Some(encoder.finish()));
+
+    staging.slice(..).map_async(wgpu::MapMode::Read, |result| result.unwrap());
+
+    device.poll(wgpu::Maintain::Wait);
+
+    let values = bytemuck::cast_slice(&staging.slice(..).get_mapped_range()).to_vec();
+
+    staging.unmap();
+
+    values
+}
+
+// The workgroup size of the compute shader along each axis
+const WORKGROUP: u32 = 16;
+
+// Renders a single frame of the scene with the compute shader,
+// as `State` would. Returns each pixel's RGBA color, row by row.
+// `size` must be a multiple of the workgroup size
+pub fn render(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    handler: Box<dyn handlers::DynIntrsHandler>,
+    scene: &mut scene::Scene,
+    config: rt::ComputeConfig,
+    size: u32,
+) -> Vec<[u8; 4]> {
+    use wgpu::util::DeviceExt as _;
+
+    if !handler.instanced() {
+        scene.flatten();
+    }
+
+    let (pack, _) = handler.vars(scene, device).unwrap();
+
+    handler.build(device, queue);
+
+    let scene_pack = scene.pack(device);
+
+    let source = shaders::source(shaders::ShaderStage::Compute {
+        wg: WORKGROUP,
+        pack: &pack,
+        logic: handler.logic(),
+        any_hit: handler.any_hit(),
+    }).unwrap();
+
+    let module = device.create_shader_module(
+        wgpu::ShaderModuleDescriptor { label: None, source }
+    );
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d { width: size, height: size, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    let size_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: None,
+        contents: bytemuck::cast_slice(&[size, size]),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+
+    let config_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: None,
+        contents: bytemuck::cast_slice(&[config]),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+
+    let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty,
+        count: None,
+    };
+
+    let uniform = wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    };
+
+    let output_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            entry(0, wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            }),
+            entry(1, uniform),
+        ],
+    });
+
+    let config_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[entry(0, uniform)],
+    });
+
+    let output_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout: &output_layout,
+        entries: &[
+            wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&view) },
+            wgpu::BindGroupEntry { binding: 1, resource: size_buffer.as_entire_binding() },
+        ],
+    });
+
+    let config_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout: &config_layout,
+        entries: &[
+            wgpu::BindGroupEntry { binding: 0, resource: config_buffer.as_entire_binding() },
+        ],
+    });
+
+    // Handlers without variables have no group(3)
+    let mut layouts = vec![&output_layout, &config_layout, &scene_pack.bg_layout];
+    if !pack.vars.is_empty() {
+        layouts.push(&pack.layout);
+    }
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &layouts,
+        push_constant_ranges: &[],
+    });
+
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: None,
+        layout: Some(&pipeline_layout),
+        module: &module,
+        entry_point: "main_cs",
+    });
+
+    let pixels = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: (size * size * 4) as u64,
+        usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(
+        &wgpu::CommandEncoderDescriptor { label: None }
+    );
+
+    {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &output_group, &[]);
+        pass.set_bind_group(1, &config_group, &[]);
+        pass.set_bind_group(2, &scene_pack.bg, &[]);
+
+        if !pack.vars.is_empty() {
+            pass.set_bind_group(3, &pack.group, &[]);
+        }
+
+        pass.dispatch_workgroups(size / WORKGROUP, size / WORKGROUP, 1);
+    }
+
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &pixels,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(size * 4),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d { width: size, height: size, depth_or_array_layers: 1 },
+    );
+
+    read(device, queue, encoder, &pixels)
+}
@@ -0,0 +1,112 @@
+// Editing a scene's meshes through bvh::DynamicBvh must give a tree
+// over the same primitives as rebuilding it with BvhData::from_scene
+
+mod common;
+
+use std::path;
+
+use rt::{bvh, scene};
+
+const EPS: f32 = 0.02;
+
+// Midpoint splits may shrink a node to half its items' bounds,
+// so its children aren't always enclosed
+const STRATEGY: bvh::Strategy = bvh::Strategy::Sah { bins: bvh::Strategy::BINS };
+
+fn add(scene: &mut scene::Scene, name: &str) -> usize {
+    let path = path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("meshes")
+        .join(name);
+
+    scene.add_mesh(wavefront::Obj::from_file(path).unwrap(), 0).unwrap()
+}
+
+fn mesh(scene: &scene::Scene, mesh: usize) -> scene::Mesh {
+    match scene {
+        scene::Scene::Active { meshes, .. } => meshes[mesh],
+        scene::Scene::Unloaded => unreachable!(),
+    }
+}
+
+// Checks that every node encloses its children and items,
+// then compares the trees' items and root bounds
+fn compare(data: &bvh::BvhData, scene: &scene::Scene) {
+    let scene::Scene::Active { prims, vertices, .. } = scene else { unreachable!(); };
+
+    let rebuilt = bvh::BvhData::from_scene(EPS, scene, 2, STRATEGY);
+
+    for bvh::AabbUniform { fst, snd, item_idx, item_count, bounds } in data.uniforms.iter().copied() {
+        if item_count == 0 {
+            assert!(common::contains(bounds, data.uniforms[fst as usize].bounds));
+            assert!(common::contains(bounds, data.uniforms[snd as usize].bounds));
+        } else {
+            let items = &data.indices[(item_idx as usize)..((item_idx + item_count) as usize)];
+
+            let items = bvh::Bounds::new(items.iter().map(|&idx| prims[idx as usize]), vertices);
+
+            assert!(common::contains(bounds, items));
+        }
+    }
+
+    let sorted = |data: &bvh::BvhData| {
+        let mut indices = data.indices.clone();
+        indices.sort_unstable();
+        indices
+    };
+
+    assert_eq!(sorted(data), sorted(&rebuilt));
+
+    let (root, root_rebuilt) = (data.uniforms[0].bounds, rebuilt.uniforms[0].bounds);
+
+    assert_eq!((root.min, root.max), (root_rebuilt.min, root_rebuilt.max));
+}
+
+#[test]
+fn insert_remove() {
+    let mut scene = common::load("default.json");
+
+    let dodecahedron = add(&mut scene, "dodecahedron.obj");
+
+    let data = bvh::BvhData::from_scene(EPS, &scene, 2, STRATEGY);
+
+    let mut dynamic = bvh::DynamicBvh::from_data(&data);
+
+    // As the BVH handler does when it's given the tree
+    scene.reorder(&data.indices);
+
+    let tetrahedron = add(&mut scene, "tetrahedron.obj");
+
+    let scene::Mesh { prim_idx, prim_count, .. } = mesh(&scene, tetrahedron);
+
+    let scene::Scene::Active { prims, vertices, .. } = &scene else { unreachable!(); };
+
+    dynamic.insert(EPS, prims, vertices, prim_idx..(prim_idx + prim_count), 2, STRATEGY);
+
+    compare(&dynamic.data(), &scene);
+
+    scene.reorder(&dynamic.data().indices);
+
+    // Removing the first mesh shifts the second one down
+    let scene::Mesh { prim_idx, prim_count, .. } = scene.remove_mesh(dodecahedron).unwrap();
+
+    let scene::Scene::Active { prims, vertices, .. } = &scene else { unreachable!(); };
+
+    dynamic.remove(prims, vertices, prim_idx..(prim_idx + prim_count));
+
+    compare(&dynamic.data(), &scene);
+
+    // The scene is left as if the dodecahedron had never been added
+    let mut expected = common::load("default.json");
+
+    add(&mut expected, "tetrahedron.obj");
+
+    let (
+        scene::Scene::Active { prims, .. },
+        scene::Scene::Active { prims: prims_expected, .. },
+    ) = (&scene, &expected) else { unreachable!(); };
+
+    assert_eq!(
+        bytemuck::cast_slice::<_, u8>(prims),
+        bytemuck::cast_slice::<_, u8>(prims_expected),
+    );
+}
@@ -0,0 +1,47 @@
+// Building large subtrees on separate threads mustn't change the tree
+
+mod common;
+
+use rt::{bvh, scene};
+
+// Enough threads that every node above Fork::THRESHOLD forks
+const THREADS: usize = 8;
+
+// Builds teatime on one thread and on many, and compares the shader data
+fn compare(strategy: bvh::Strategy) {
+    let scene::Scene::Active { prims, vertices, .. } = common::load("teatime.json") else {
+        unreachable!();
+    };
+
+    assert!(prims.len() >= bvh::Fork::THRESHOLD);
+
+    let build = |fork| {
+        let aabb = bvh::Aabb::from_prims_with(0.02, &prims, &vertices, 2, strategy, fork);
+        bvh::BvhData::new(&aabb)
+    };
+
+    let sequential = build(bvh::Fork::with_threads(1));
+    let forked = build(bvh::Fork::with_threads(THREADS));
+
+    assert_eq!(sequential.indices, forked.indices);
+    assert_eq!(
+        bytemuck::cast_slice::<_, u8>(&sequential.uniforms),
+        bytemuck::cast_slice::<_, u8>(&forked.uniforms),
+    );
+}
+
+#[test]
+fn midpoint() {
+    compare(bvh::Strategy::Midpoint);
+}
+
+#[test]
+fn sah() {
+    compare(bvh::Strategy::Sah { bins: bvh::Strategy::BINS });
+}
+
+#[test]
+fn spatial() {
+    // A small budget runs out, so how it is divided shows up in the tree
+    compare(bvh::Strategy::Spatial { bins: bvh::Strategy::BINS, budget: 0.01 });
+}
@@ -0,0 +1,112 @@
+// The GPU builder must emit a well-formed tree over every primitive,
+// about as good as the CPU's linear BVH
+
+mod common;
+
+use std::mem;
+
+use rt::{bvh, geom, scene};
+
+fn build_gpu(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    prims: &[geom::Prim],
+    vertices: &[geom::PrimVertex],
+) -> Vec<bvh::AabbUniform> {
+    let size = (mem::size_of::<bvh::AabbUniform>() * bvh::GpuBuilder::nodes(prims.len())) as u64;
+
+    let nodes = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+
+    bvh::GpuBuilder::new(device, prims, vertices, &nodes)
+        .unwrap()
+        .build(device, queue);
+
+    let encoder = device.create_command_encoder(
+        &wgpu::CommandEncoderDescriptor { label: None }
+    );
+
+    common::read(device, queue, encoder, &nodes)
+}
+
+fn compare(name: &str) {
+    let Some((device, queue)) = common::device() else {
+        eprintln!("No adapter, skipping the GPU builder test");
+
+        return;
+    };
+
+    let scene::Scene::Active { prims, vertices, .. } = common::load(name) else {
+        unreachable!();
+    };
+
+    let nodes = build_gpu(&device, &queue, &prims, &vertices);
+
+    // Walks the tree from the root, collecting leaves from left to right
+    let mut leaves = Vec::new();
+    let mut visited = vec![false; nodes.len()];
+    let mut stack = vec![0];
+    while let Some(idx) = stack.pop() {
+        assert!(!visited[idx], "node [{idx}] is reachable twice");
+
+        visited[idx] = true;
+
+        let bvh::AabbUniform { fst, snd, item_idx, item_count, bounds } = nodes[idx];
+
+        if item_count == 0 {
+            assert!(common::contains(bounds, nodes[fst as usize].bounds));
+            assert!(common::contains(bounds, nodes[snd as usize].bounds));
+
+            stack.extend([snd as usize, fst as usize]);
+        } else {
+            assert_eq!(item_count, 1);
+
+            let prim = bvh::Bounds::new(std::iter::once(prims[item_idx as usize]), &vertices);
+
+            assert!(common::contains(bounds, prim));
+
+            leaves.push(item_idx);
+        }
+    }
+
+    assert!(visited.iter().all(|&visited| visited), "some nodes are unreachable");
+
+    // Every primitive is in exactly one leaf
+    leaves.sort_unstable();
+
+    assert!(leaves.iter().copied().eq(0..(prims.len() as u32)));
+
+    let cpu = bvh::BvhData::from_prims(0., &prims, &vertices, 1, bvh::Strategy::Linear {
+        bits: bvh::Morton::Bits30,
+    });
+
+    let (root, root_cpu) = (nodes[0].bounds, cpu.uniforms[0].bounds);
+
+    assert_eq!((root.min, root.max), (root_cpu.min, root_cpu.max));
+
+    // Both builders sort the same Morton codes, but rounding differs
+    // on the GPU, so a few neighbouring primitives may swap places
+    let gpu = bvh::BvhData {
+        uniforms: nodes,
+        indices: (0..(prims.len() as u32)).collect(),
+    };
+
+    let (sah, sah_cpu) = (gpu.metrics().sah, cpu.metrics().sah);
+
+    assert!((sah - sah_cpu).abs() <= sah_cpu * 0.05, "SAH cost {sah}, {sah_cpu} on the CPU");
+}
+
+#[test]
+fn default() {
+    compare("default.json");
+}
+
+// Large enough that the histogram spans several chunks of the scan
+#[test]
+fn teatime() {
+    compare("teatime.json");
+}
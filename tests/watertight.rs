@@ -0,0 +1,302 @@
+// Rays aimed at an edge that two primitives share must hit one of them
+
+mod common;
+
+use std::{array, collections};
+
+use rt::{geom, scene, shaders};
+use rt::geom::V3Ops as _;
+
+// Rays come from each of these directions
+const DIRECTIONS: [[f32; 3]; 8] = [
+    [1., 2., 3.],
+    [-3., 1., 2.],
+    [2., -3., 1.],
+    [-1., -2., 3.],
+    [3., 2., -1.],
+    [-2., 3., -3.],
+    [1., -1., -2.],
+    [-3., -1., -1.],
+];
+
+// And are aimed at these points along each edge
+const SPLITS: [f32; 5] = [0.1, 0.3, 0.5, 0.7, 0.9];
+
+fn prims(scene: scene::Scene) -> (Vec<geom::Prim>, Vec<geom::PrimVertex>) {
+    match scene {
+        scene::Scene::Active { prims, vertices, .. } => (prims, vertices),
+        scene::Scene::Unloaded => unreachable!(),
+    }
+}
+
+// A ray aimed at an edge, and the two primitives that share it
+struct Probe {
+    origin: [f32; 3],
+    dir: [f32; 3],
+    prims: [usize; 2],
+}
+
+fn probes(prims: &[geom::Prim], vertices: &[geom::PrimVertex]) -> Vec<Probe> {
+    let pos = |idx: u32| vertices[idx as usize].pos;
+
+    // Find the primitives on either side of each edge
+    let mut edges = collections::HashMap::<[u32; 2], Vec<usize>>::new();
+    for (idx, prim) in prims.iter().enumerate() {
+        let [a, b, c] = prim.indices;
+
+        for [p, q] in [[a, b], [b, c], [c, a]] {
+            edges.entry([p.min(q), p.max(q)]).or_default().push(idx);
+        }
+    }
+
+    let mut probes = Vec::new();
+    for ([a, b], owners) in edges {
+        let [fst, snd] = owners[..] else { continue; };
+
+        // The vertices opposite the edge
+        let [far_fst, far_snd] = [fst, snd].map(|idx| {
+            let far = prims[idx].indices
+                .into_iter()
+                .find(|&idx| idx != a && idx != b)
+                .unwrap();
+
+            pos(far).sub(pos(a))
+        });
+
+        let edge = pos(b).sub(pos(a));
+
+        for dir in DIRECTIONS.map(|dir| dir.normalize()) {
+            // Skip edges on the silhouette, where the primitives
+            // lie on the same side of the ray and it can pass by both
+            let side = edge.cross(dir).normalize();
+
+            let dist_fst = side.dot(far_fst) / far_fst.mag();
+            let dist_snd = side.dot(far_snd) / far_snd.mag();
+
+            if dist_fst * dist_snd > 0. { continue; }
+
+            // Nor can grazing rays be expected to hit
+            if dist_fst.abs() < 0.01 || dist_snd.abs() < 0.01 { continue; }
+
+            for split in SPLITS {
+                // Far from the origin of the scene, the nearest f32 to a point
+                // on the edge can be well off it. So the point is kept in f64,
+                // and the ray is aimed at it from an f32 origin at a distance
+                let target: [f64; 3] = array::from_fn(|axis| {
+                    let [a, b] = [a, b].map(|idx| pos(idx)[axis] as f64);
+                    a + (b - a) * split as f64
+                });
+
+                let origin = array::from_fn(|axis| {
+                    (target[axis] - dir[axis] as f64 * 10.) as f32
+                });
+
+                let to = array::from_fn::<_, 3, _>(|axis| target[axis] - origin[axis] as f64);
+                let mag = to.iter().map(|c| c * c).sum::<f64>().sqrt();
+
+                let dir = to.map(|c| (c / mag) as f32);
+
+                probes.push(Probe { origin, dir, prims: [fst, snd] });
+            }
+        }
+    }
+
+    probes
+}
+
+// Counts the rays that pass between two primitives without hitting either
+fn leaks<F>(scene: scene::Scene, test: F) -> usize
+    where F: Fn([f32; 3], [f32; 3], [[f32; 3]; 3]) -> Option<f32> {
+
+    let (prims, vertices) = prims(scene);
+
+    let tri = |idx: usize| prims[idx].indices.map(|idx| vertices[idx as usize].pos);
+
+    probes(&prims, &vertices)
+        .into_iter()
+        .filter(|Probe { origin, dir, prims }| {
+            prims.iter().all(|&idx| test(*origin, *dir, tri(idx)).is_none())
+        })
+        .count()
+}
+
+fn moller_trumbore(origin: [f32; 3], dir: [f32; 3], tri: [[f32; 3]; 3]) -> Option<f32> {
+    geom::tri::moller_trumbore(origin, dir, tri, rt::ComputeConfig::default().eps)
+}
+
+// Runs `intrs_tri` from tri.wgsl on each ray and primitive.
+// The types come from types.wgsl, as in the compute shader
+const SHADER: &str = "
+struct Probe { origin: vec3<f32>, prim: u32, dir: vec3<f32>, }
+
+@group(0) @binding(0) var<uniform> config: Config;
+@group(0) @binding(1) var<storage, read> vertices: array<Vertex>;
+@group(0) @binding(2) var<storage, read> primitives: array<Prim>;
+@group(0) @binding(3) var<storage, read> probes: array<Probe>;
+@group(0) @binding(4) var<storage, read_write> hits: array<u32>;
+
+fn intrs_empty() -> Intrs {
+    return Intrs(Prim(0u, 0u, 0u, -1), config.t_max + 1.0, 0u);
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if(id.x >= arrayLength(&probes)) { return; }
+
+    let probe = probes[id.x];
+    let intrs = intrs_tri(Ray(probe.origin, probe.dir), primitives[probe.prim]);
+
+    hits[id.x] = u32(intrs.t <= config.t_max);
+}
+";
+
+// Matches Probe in SHADER
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy)]
+struct GpuProbe {
+    origin: [f32; 3],
+    prim: u32,
+    dir: [f32; 3],
+    _p0: u32,
+}
+
+// As `leaks`, but with the shader's test, on the GPU.
+// None if there's no adapter
+fn leaks_gpu(scene: scene::Scene, test: rt::TriangleTest) -> Option<usize> {
+    use wgpu::util::DeviceExt as _;
+
+    let (device, queue) = common::device()?;
+
+    let (prims, vertices) = prims(scene);
+
+    let probes = probes(&prims, &vertices);
+
+    let gpu_probes = probes
+        .iter()
+        .flat_map(|&Probe { origin, dir, prims }| prims.map(|prim| GpuProbe {
+            origin,
+            prim: prim as u32,
+            dir,
+            _p0: 0,
+        }))
+        .collect::<Vec<_>>();
+
+    let source = shaders::Composer::new()
+        .module(shaders::Module::new("watertight", SHADER)
+            .import(shaders::library::types())
+            .import(shaders::library::tri()))
+        .compose()
+        .unwrap();
+
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+
+    let config = rt::ComputeConfig {
+        triangle_test: test as u32,
+        ..Default::default()
+    };
+
+    let init = |contents: &[u8], usage| {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents,
+            usage,
+        })
+    };
+
+    let config_buffer = init(bytemuck::cast_slice(&[config]), wgpu::BufferUsages::UNIFORM);
+    let vertex_buffer = init(bytemuck::cast_slice(&vertices), wgpu::BufferUsages::STORAGE);
+    let prim_buffer = init(bytemuck::cast_slice(&prims), wgpu::BufferUsages::STORAGE);
+    let probe_buffer = init(bytemuck::cast_slice(&gpu_probes), wgpu::BufferUsages::STORAGE);
+
+    let hits = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: (gpu_probes.len() * 4) as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: None,
+        layout: None,
+        module: &module,
+        entry_point: "main",
+    });
+
+    let group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[
+            wgpu::BindGroupEntry { binding: 0, resource: config_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 1, resource: vertex_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 2, resource: prim_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 3, resource: probe_buffer.as_entire_binding() },
+            wgpu::BindGroupEntry { binding: 4, resource: hits.as_entire_binding() },
+        ],
+    });
+
+    let mut encoder = device.create_command_encoder(
+        &wgpu::CommandEncoderDescriptor { label: None }
+    );
+
+    {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &group, &[]);
+        pass.dispatch_workgroups((gpu_probes.len() as u32).div_ceil(64), 1, 1);
+    }
+
+    let hits = common::read::<u32>(&device, &queue, encoder, &hits);
+
+    // Each probe's primitives are adjacent
+    Some(hits.chunks(2).filter(|hits| hits.iter().all(|&hit| hit == 0)).count())
+}
+
+#[test]
+fn teatime() {
+    assert_eq!(leaks(common::load("teatime.json"), geom::tri::watertight), 0);
+}
+
+#[test]
+fn acne() {
+    assert_eq!(leaks(common::acne::acne(true), geom::tri::watertight), 0);
+}
+
+// The probes must be able to find leaks, or the tests above prove nothing
+#[test]
+fn teatime_moller_trumbore() {
+    assert!(leaks(common::load("teatime.json"), moller_trumbore) > 0);
+}
+
+#[test]
+fn acne_moller_trumbore() {
+    assert!(leaks(common::acne::acne(true), moller_trumbore) > 0);
+}
+
+// The shader is a separate copy, and may be compiled differently
+#[test]
+fn teatime_gpu() {
+    let scene = || common::load("teatime.json");
+
+    let Some(leaks) = leaks_gpu(scene(), rt::TriangleTest::Watertight) else { return; };
+    assert_eq!(leaks, 0);
+
+    assert!(leaks_gpu(scene(), rt::TriangleTest::MollerTrumbore).unwrap() > 0);
+}
+
+#[test]
+fn acne_gpu() {
+    let scene = || common::acne::acne(true);
+
+    let Some(leaks) = leaks_gpu(scene(), rt::TriangleTest::Watertight) else { return; };
+    assert_eq!(leaks, 0);
+
+    assert!(leaks_gpu(scene(), rt::TriangleTest::MollerTrumbore).unwrap() > 0);
+}